@@ -0,0 +1,392 @@
+//! Offline tool for the anchor-vault-q3 program
+//!
+//! Derives addresses, builds and signs transactions into files so they can be
+//! signed on an air-gapped machine, decodes vault accounts, and simulates
+//! transactions against the compiled program with Mollusk. Nothing here talks
+//! to a cluster: blockhashes and account snapshots are supplied by the caller.
+
+mod snapshot;
+
+use std::{ error::Error, fs, path::{ Path, PathBuf } };
+
+use anchor_vault_q3_client::{
+    close_ix,
+    decode_error_code,
+    decode_vault_state,
+    deposit_ix,
+    find_config_address,
+    find_event_authority_address,
+    find_registry_address,
+    find_treasury_address,
+    find_vault_address,
+    find_vault_state_address,
+    initialize_ix,
+    withdraw_ix,
+    VaultState,
+    PROGRAM_ID,
+};
+use clap::{ Args, Parser, Subcommand };
+use mollusk_svm::{ program::loader_keys::LOADER_V3, result::ProgramResult, Mollusk };
+use solana_log_collector::LogCollector;
+use solana_sdk::{
+    account::Account,
+    hash::Hash,
+    instruction::{ AccountMeta, Instruction },
+    message::Message,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    signature::{ read_keypair_file, Keypair },
+    transaction::Transaction,
+};
+
+#[derive(Parser)]
+#[command(name = "vault-cli", version, about = "Offline tooling for the anchor-vault-q3 program")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the addresses of a vault and of the program's global accounts
+    Address {
+        /// Key the vault was created with
+        #[arg(long)]
+        creator: Pubkey,
+        #[arg(long, default_value_t = 0)]
+        vault_id: u64,
+    },
+    /// Build a transaction creating a vault
+    Initialize {
+        /// Pays for and owns the new vault
+        #[arg(long)]
+        user: Pubkey,
+        #[arg(long, default_value_t = 0)]
+        vault_id: u64,
+        #[arg(long, default_value_t = 0)]
+        unlock_timestamp: i64,
+        #[arg(long, default_value_t = 0)]
+        unlock_slot: u64,
+        #[command(flatten)]
+        transaction: TransactionArgs,
+    },
+    /// Build a transaction depositing lamports into a vault
+    Deposit {
+        #[command(flatten)]
+        vault: VaultArgs,
+        #[arg(long)]
+        amount: u64,
+        #[command(flatten)]
+        transaction: TransactionArgs,
+    },
+    /// Build a transaction withdrawing lamports from a vault
+    Withdraw {
+        #[command(flatten)]
+        vault: VaultArgs,
+        #[arg(long)]
+        amount: u64,
+        /// Allow-listed account to receive the funds instead of the owner
+        #[arg(long)]
+        destination: Option<Pubkey>,
+        #[command(flatten)]
+        transaction: TransactionArgs,
+    },
+    /// Build a transaction closing a vault
+    Close {
+        #[command(flatten)]
+        vault: VaultArgs,
+        /// Allow-listed account to receive the balance instead of the owner
+        #[arg(long)]
+        destination: Option<Pubkey>,
+        #[command(flatten)]
+        transaction: TransactionArgs,
+    },
+    /// Add signatures to a transaction file
+    Sign {
+        #[arg(long = "in")]
+        input: PathBuf,
+        /// Keypair file to sign with; repeat for several signers
+        #[arg(long = "keypair", required = true)]
+        keypairs: Vec<PathBuf>,
+        /// Defaults to overwriting the input file
+        #[arg(long)]
+        out: Option<PathBuf>,
+    },
+    /// Decode a vault state account from a JSON account dump or base64 data
+    Decode {
+        file: PathBuf,
+    },
+    /// Run a transaction file against the compiled program and an account snapshot
+    Simulate {
+        #[arg(long)]
+        transaction: PathBuf,
+        /// Accounts in `solana account --output json` format, one object or an array.
+        /// The system program and the vault program are filled in automatically.
+        #[arg(long)]
+        accounts: PathBuf,
+        #[arg(long, default_value = "target/deploy/anchor_vault_q3.so")]
+        program: PathBuf,
+        /// Print the program logs recorded during the simulation
+        #[arg(long)]
+        logs: bool,
+    },
+}
+
+#[derive(Args)]
+struct VaultArgs {
+    /// Signer acting on the vault, its current owner
+    #[arg(long)]
+    user: Pubkey,
+    /// Key the vault was created with, if ownership has moved since
+    #[arg(long)]
+    creator: Option<Pubkey>,
+    #[arg(long, default_value_t = 0)]
+    vault_id: u64,
+}
+
+impl VaultArgs {
+    fn creator(&self) -> Pubkey {
+        self.creator.unwrap_or(self.user)
+    }
+}
+
+#[derive(Args)]
+struct TransactionArgs {
+    /// Recent blockhash or durable nonce to build the transaction with
+    #[arg(long)]
+    blockhash: Hash,
+    /// Defaults to the user
+    #[arg(long)]
+    fee_payer: Option<Pubkey>,
+    /// Keypair file to sign with right away; repeat for several signers
+    #[arg(long = "keypair")]
+    keypairs: Vec<PathBuf>,
+    #[arg(long)]
+    out: PathBuf,
+}
+
+fn main() {
+    if let Err(error) = run(Cli::parse()) {
+        eprintln!("error: {}", error);
+        std::process::exit(1);
+    }
+}
+
+fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
+    match cli.command {
+        Command::Address { creator, vault_id } => {
+            print_addresses(&creator, vault_id);
+            Ok(())
+        }
+        Command::Initialize { user, vault_id, unlock_timestamp, unlock_slot, transaction } => {
+            let instruction = initialize_ix(&user, vault_id, unlock_timestamp, unlock_slot);
+            build_transaction(instruction, &user, &transaction)
+        }
+        Command::Deposit { vault, amount, transaction } => {
+            let instruction = deposit_ix(&vault.user, &vault.creator(), vault.vault_id, amount);
+            build_transaction(instruction, &vault.user, &transaction)
+        }
+        Command::Withdraw { vault, amount, destination, transaction } => {
+            let instruction = withdraw_ix(&vault.user, &vault.creator(), vault.vault_id, amount, destination);
+            build_transaction(instruction, &vault.user, &transaction)
+        }
+        Command::Close { vault, destination, transaction } => {
+            let instruction = close_ix(&vault.user, &vault.creator(), vault.vault_id, destination);
+            build_transaction(instruction, &vault.user, &transaction)
+        }
+        Command::Sign { input, keypairs, out } => {
+            let mut transaction = snapshot::read_transaction(&input)?;
+            sign(&mut transaction, &keypairs)?;
+            snapshot::write_transaction(out.as_ref().unwrap_or(&input), &transaction)?;
+            print_signatures(&transaction);
+            Ok(())
+        }
+        Command::Decode { file } => decode(&file),
+        Command::Simulate { transaction, accounts, program, logs } => {
+            simulate(&transaction, &accounts, &program, logs)
+        }
+    }
+}
+
+fn print_addresses(creator: &Pubkey, vault_id: u64) {
+    let (vault_state, _) = find_vault_state_address(creator, vault_id);
+    println!("program:          {}", PROGRAM_ID);
+    println!("vault state:      {}", vault_state);
+    println!("vault:            {}", find_vault_address(&vault_state).0);
+    println!("registry:         {}", find_registry_address(creator).0);
+    println!("config:           {}", find_config_address().0);
+    println!("treasury:         {}", find_treasury_address().0);
+    println!("event authority:  {}", find_event_authority_address().0);
+}
+
+fn build_transaction(
+    instruction: Instruction,
+    user: &Pubkey,
+    args: &TransactionArgs
+) -> Result<(), Box<dyn Error>> {
+    let fee_payer = args.fee_payer.unwrap_or(*user);
+    let message = Message::new_with_blockhash(&[instruction], Some(&fee_payer), &args.blockhash);
+    let mut transaction = Transaction::new_unsigned(message);
+    sign(&mut transaction, &args.keypairs)?;
+    snapshot::write_transaction(&args.out, &transaction)?;
+    println!("wrote {}", args.out.display());
+    print_signatures(&transaction);
+    Ok(())
+}
+
+/// Signs with the blockhash already in the message, leaving other signers' slots alone
+fn sign(transaction: &mut Transaction, keypairs: &[PathBuf]) -> Result<(), Box<dyn Error>> {
+    if keypairs.is_empty() {
+        return Ok(());
+    }
+    let keypairs = keypairs
+        .iter()
+        .map(|path| read_keypair_file(path).map_err(|error| format!("{}: {}", path.display(), error)))
+        .collect::<Result<Vec<Keypair>, _>>()?;
+    let blockhash = transaction.message.recent_blockhash;
+    transaction.try_partial_sign(&keypairs, blockhash)?;
+    Ok(())
+}
+
+fn print_signatures(transaction: &Transaction) {
+    let signers = transaction.message.header.num_required_signatures as usize;
+    for (key, signature) in transaction.message.account_keys[..signers].iter().zip(&transaction.signatures) {
+        let status = if *signature == Default::default() { "missing".to_string() } else { signature.to_string() };
+        println!("signer {}: {}", key, status);
+    }
+}
+
+fn decode(file: &Path) -> Result<(), Box<dyn Error>> {
+    let data = snapshot::read_account_data(file)?;
+    let version = VaultState::layout_version(&data);
+    if version < VaultState::CURRENT_VERSION {
+        return Err(
+            format!(
+                "vault state is on layout version {}, run migrate_vault_state to decode it as version {}",
+                version,
+                VaultState::CURRENT_VERSION
+            ).into()
+        );
+    }
+    let state = decode_vault_state(&data)?;
+
+    println!("version:                 {}", state.version);
+    println!("vault id:                {}", state.vault_id);
+    println!("creator:                 {}", state.creator);
+    println!("owner:                   {}", state.owner);
+    println!("pending owner:           {}", state.pending_owner);
+    println!("total deposited:         {}", state.total_deposited);
+    println!("total withdrawn:         {}", state.total_withdrawn);
+    println!("deposit count:           {}", state.deposit_count);
+    println!("last activity:           slot {}, timestamp {}", state.last_activity_slot, state.last_activity_timestamp);
+    println!("unlock:                  slot {}, timestamp {}", state.unlock_slot, state.unlock_timestamp);
+    println!("min withdrawal:          {}", state.min_withdrawal);
+    println!("multisig:                {}", state.multisig);
+    println!("withdrawal limit:        {} per {}s", state.withdrawal_limit, state.withdrawal_window);
+    println!("withdrawn in window:     {} since {}", state.withdrawn_in_window, state.window_start);
+    println!(
+        "pending limit:           {} per {}s from {}",
+        state.pending_withdrawal_limit,
+        state.pending_withdrawal_window,
+        state.pending_limit_effective_at
+    );
+    println!("beneficiary:             {}", state.beneficiary);
+    println!("inactivity period:       {}s, last heartbeat {}", state.inactivity_period, state.last_heartbeat);
+    println!("reserved for streams:    {} over {} streams", state.reserved_for_streams, state.stream_count);
+    println!("staked:                  {}", state.staked_lamports);
+    println!("open delegations:        {}", state.delegation_count);
+    println!("has guardians:           {}", state.has_guardians);
+    println!(
+        "goal:                    {} by {}, holds withdrawals: {}, reached at {}",
+        state.goal_amount,
+        state.goal_deadline,
+        state.goal_locks_withdrawals,
+        state.goal_reached_at
+    );
+    Ok(())
+}
+
+/// Turns a message's compiled instructions back into instructions Mollusk can run
+fn decompile(message: &Message) -> Vec<Instruction> {
+    message.instructions
+        .iter()
+        .map(|instruction| Instruction {
+            program_id: message.account_keys[instruction.program_id_index as usize],
+            accounts: instruction.accounts
+                .iter()
+                .map(|&index| {
+                    let index = index as usize;
+                    AccountMeta {
+                        pubkey: message.account_keys[index],
+                        is_signer: message.is_signer(index),
+                        is_writable: message.is_maybe_writable(index, None),
+                    }
+                })
+                .collect(),
+            data: instruction.data.clone(),
+        })
+        .collect()
+}
+
+fn simulate(transaction: &Path, accounts: &Path, program: &Path, logs: bool) -> Result<(), Box<dyn Error>> {
+    let transaction = snapshot::read_transaction(transaction)?;
+    let snapshot = snapshot::read_accounts(accounts)?;
+
+    let mut mollusk = Mollusk::default();
+    // Transactions that never reach the vault, like funding transfers, run without its ELF
+    if transaction.message.account_keys.contains(&PROGRAM_ID) {
+        let elf = fs::read(program).map_err(|error| format!("{}: {}", program.display(), error))?;
+        mollusk.add_program_with_elf_and_loader(&PROGRAM_ID, &elf, &LOADER_V3);
+    }
+    if logs {
+        mollusk.logger = Some(LogCollector::new_ref());
+    }
+
+    let (system_program, system_account) = mollusk_svm::program::keyed_account_for_system_program();
+    let accounts: Vec<(Pubkey, Account)> = transaction.message.account_keys
+        .iter()
+        .map(|key| {
+            if *key == system_program {
+                (system_program, system_account.clone())
+            } else if *key == PROGRAM_ID {
+                (PROGRAM_ID, mollusk_svm::program::create_program_account_loader_v3(&PROGRAM_ID))
+            } else if let Some((_, account)) = snapshot.iter().find(|(k, _)| k == key) {
+                (*key, account.clone())
+            } else {
+                eprintln!("warning: {} is not in the snapshot, using an empty account", key);
+                (*key, Account::default())
+            }
+        })
+        .collect();
+
+    if !transaction.is_signed() {
+        eprintln!("warning: transaction is not fully signed; simulating anyway");
+    }
+    let result = mollusk.process_instruction_chain(&decompile(&transaction.message), &accounts);
+
+    match &result.program_result {
+        ProgramResult::Success => println!("result:         success"),
+        ProgramResult::Failure(ProgramError::Custom(code)) => {
+            match decode_error_code(*code) {
+                Some(error) => println!("result:         failed with {:?} ({}): {}", error, code, error),
+                None => println!("result:         failed with custom error {}", code),
+            }
+        }
+        ProgramResult::Failure(error) => println!("result:         failed with {}", error),
+        ProgramResult::UnknownError(error) => println!("result:         failed with {}", error),
+    }
+    println!("compute units:  {}", result.compute_units_consumed);
+    println!("balances:");
+    for (key, before) in &accounts {
+        let after = result.get_account(key).map_or(before.lamports, |account| account.lamports);
+        let change = (after as i128) - (before.lamports as i128);
+        println!("  {}  {} -> {} ({:+})", key, before.lamports, after, change);
+    }
+    if let Some(logger) = &mollusk.logger {
+        println!("logs:");
+        for message in logger.borrow().get_recorded_content() {
+            println!("  {}", message);
+        }
+    }
+    Ok(())
+}
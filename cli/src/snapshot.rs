@@ -0,0 +1,75 @@
+//! Reading account dumps and transaction files
+//!
+//! Accounts use the JSON layout of `solana account --output json`, either one
+//! object or an array of them. Transactions are bincode serialized and base64
+//! encoded, so they can be carried to and from an air-gapped machine as text.
+
+use std::{ error::Error, fs, path::Path, str::FromStr };
+
+use base64::{ engine::general_purpose::STANDARD, Engine };
+use serde::Deserialize;
+use solana_sdk::{ account::Account, pubkey::Pubkey, transaction::Transaction };
+
+#[derive(Deserialize)]
+struct AccountDump {
+    pubkey: String,
+    account: UiAccount,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UiAccount {
+    lamports: u64,
+    /// Data and its encoding, e.g. `["AQID", "base64"]`
+    data: (String, String),
+    owner: String,
+    executable: bool,
+    #[serde(default)]
+    rent_epoch: u64,
+}
+
+impl AccountDump {
+    fn into_keyed_account(self) -> Result<(Pubkey, Account), Box<dyn Error>> {
+        let (data, encoding) = self.account.data;
+        if encoding != "base64" {
+            return Err(format!("account {} is {} encoded, expected base64", self.pubkey, encoding).into());
+        }
+        let account = Account {
+            lamports: self.account.lamports,
+            data: STANDARD.decode(data.trim())?,
+            owner: Pubkey::from_str(&self.account.owner)?,
+            executable: self.account.executable,
+            rent_epoch: self.account.rent_epoch,
+        };
+        Ok((Pubkey::from_str(&self.pubkey)?, account))
+    }
+}
+
+/// Reads a snapshot of one or more accounts
+pub fn read_accounts(path: &Path) -> Result<Vec<(Pubkey, Account)>, Box<dyn Error>> {
+    let contents = fs::read_to_string(path)?;
+    let dumps: Vec<AccountDump> = match serde_json::from_str(&contents) {
+        Ok(dumps) => dumps,
+        Err(_) => vec![serde_json::from_str(&contents)?],
+    };
+    dumps.into_iter().map(AccountDump::into_keyed_account).collect()
+}
+
+/// Reads raw account data from either an account dump or a bare base64 string
+pub fn read_account_data(path: &Path) -> Result<Vec<u8>, Box<dyn Error>> {
+    let contents = fs::read_to_string(path)?;
+    if let Ok(dump) = serde_json::from_str::<AccountDump>(&contents) {
+        return Ok(dump.into_keyed_account()?.1.data);
+    }
+    Ok(STANDARD.decode(contents.trim())?)
+}
+
+pub fn read_transaction(path: &Path) -> Result<Transaction, Box<dyn Error>> {
+    let contents = fs::read_to_string(path)?;
+    Ok(bincode::deserialize(&STANDARD.decode(contents.trim())?)?)
+}
+
+pub fn write_transaction(path: &Path, transaction: &Transaction) -> Result<(), Box<dyn Error>> {
+    fs::write(path, STANDARD.encode(bincode::serialize(transaction)?) + "\n")?;
+    Ok(())
+}
@@ -0,0 +1,177 @@
+#![allow(deprecated)]
+
+use std::{ fs, path::PathBuf, process::{ Command, Output } };
+
+use anchor_lang::{
+    solana_program::system_instruction,
+    system_program,
+    AccountDeserialize,
+    AccountSerialize,
+    Discriminator,
+    Space,
+};
+use anchor_vault_q3_client::{ find_vault_address, find_vault_state_address, VaultState };
+use base64::{ engine::general_purpose::STANDARD, Engine };
+use solana_sdk::{
+    hash::Hash,
+    pubkey::Pubkey,
+    signature::{ write_keypair_file, Keypair, Signer },
+    transaction::Transaction,
+};
+
+fn vault_cli(args: &[&str]) -> Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_vault-cli")).args(args).output().unwrap();
+    assert!(
+        output.status.success(),
+        "vault-cli {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    output
+}
+
+/// Scratch path unique to one test
+fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("vault-cli-{}-{}", std::process::id(), name))
+}
+
+fn read_transaction(path: &PathBuf) -> Transaction {
+    let contents = fs::read_to_string(path).unwrap();
+    bincode::deserialize(&STANDARD.decode(contents.trim()).unwrap()).unwrap()
+}
+
+fn write_transaction(path: &PathBuf, transaction: &Transaction) {
+    fs::write(path, STANDARD.encode(bincode::serialize(transaction).unwrap())).unwrap();
+}
+
+/// Account dump in the `solana account --output json` layout
+fn account_dump(pubkey: &Pubkey, lamports: u64, data: &[u8], owner: &Pubkey) -> String {
+    format!(
+        r#"{{"pubkey":"{}","account":{{"lamports":{},"data":["{}","base64"],"owner":"{}","executable":false,"rentEpoch":0}}}}"#,
+        pubkey,
+        lamports,
+        STANDARD.encode(data),
+        owner
+    )
+}
+
+#[test]
+fn test_address_matches_client() {
+    let creator = Pubkey::new_unique();
+    let output = vault_cli(&["address", "--creator", &creator.to_string(), "--vault-id", "1"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    let (vault_state, _) = find_vault_state_address(&creator, 1);
+    assert!(stdout.contains(&vault_state.to_string()));
+    assert!(stdout.contains(&find_vault_address(&vault_state).0.to_string()));
+}
+
+#[test]
+fn test_build_then_sign_offline() {
+    let user = Keypair::new();
+    let keypair_path = temp_path("user.json");
+    write_keypair_file(&user, &keypair_path).unwrap();
+    let transaction_path = temp_path("deposit.tx");
+    let blockhash = Hash::new_unique();
+
+    // Built without the key, as on the online machine
+    vault_cli(&[
+        "deposit",
+        "--user",
+        &user.pubkey().to_string(),
+        "--amount",
+        "5000000",
+        "--blockhash",
+        &blockhash.to_string(),
+        "--out",
+        transaction_path.to_str().unwrap(),
+    ]);
+    let transaction = read_transaction(&transaction_path);
+    assert!(!transaction.is_signed());
+    assert_eq!(transaction.message.recent_blockhash, blockhash);
+    assert_eq!(transaction.message.account_keys[0], user.pubkey());
+
+    // Signed separately, as on the air-gapped machine
+    vault_cli(&["sign", "--in", transaction_path.to_str().unwrap(), "--keypair", keypair_path.to_str().unwrap()]);
+    let transaction = read_transaction(&transaction_path);
+    assert!(transaction.is_signed());
+    assert!(transaction.verify().is_ok());
+
+    fs::remove_file(keypair_path).unwrap();
+    fs::remove_file(transaction_path).unwrap();
+}
+
+#[test]
+fn test_decode_rejects_old_layout() {
+    let path = temp_path("old_state.b64");
+    fs::write(&path, STANDARD.encode([0u8; 16])).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_vault-cli"))
+        .args(["decode", path.to_str().unwrap()])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("migrate_vault_state"));
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_decode_vault_state() {
+    // A zeroed current layout, filled in through the account type itself
+    let mut data = VaultState::DISCRIMINATOR.to_vec();
+    data.resize(8 + VaultState::INIT_SPACE, 0);
+    let mut state = VaultState::try_deserialize(&mut data.as_slice()).unwrap();
+    let owner = Pubkey::new_unique();
+    state.version = VaultState::CURRENT_VERSION;
+    state.owner = owner;
+    state.total_deposited = 5_000_000;
+    let mut data = Vec::new();
+    state.try_serialize(&mut data).unwrap();
+
+    let path = temp_path("state.json");
+    let (vault_state, _) = find_vault_state_address(&owner, 0);
+    fs::write(&path, account_dump(&vault_state, 1_000_000, &data, &anchor_vault_q3_client::PROGRAM_ID)).unwrap();
+
+    let output = vault_cli(&["decode", path.to_str().unwrap()]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains(&format!("version:                 {}", VaultState::CURRENT_VERSION)));
+    assert!(stdout.contains(&format!("owner:                   {}", owner)));
+    assert!(stdout.contains("total deposited:         5000000"));
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn test_simulate_prints_balances_and_logs() {
+    let payer = Keypair::new();
+    let recipient = Pubkey::new_unique();
+    let transaction = Transaction::new_signed_with_payer(
+        &[system_instruction::transfer(&payer.pubkey(), &recipient, 1_000_000)],
+        Some(&payer.pubkey()),
+        &[&payer],
+        Hash::new_unique()
+    );
+    let transaction_path = temp_path("transfer.tx");
+    write_transaction(&transaction_path, &transaction);
+    let accounts_path = temp_path("accounts.json");
+    fs::write(&accounts_path, account_dump(&payer.pubkey(), 10_000_000, &[], &system_program::ID)).unwrap();
+
+    // The transfer never reaches the vault program, so no compiled program is needed
+    let output = vault_cli(&[
+        "simulate",
+        "--transaction",
+        transaction_path.to_str().unwrap(),
+        "--accounts",
+        accounts_path.to_str().unwrap(),
+        "--logs",
+    ]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("result:         success"), "{}", stdout);
+    assert!(stdout.contains(&format!("{}  10000000 -> 9000000 (-1000000)", payer.pubkey())), "{}", stdout);
+    assert!(stdout.contains(&format!("{}  0 -> 1000000 (+1000000)", recipient)), "{}", stdout);
+    assert!(stdout.contains(&format!("logs:\n  Program {} invoke [1]", system_program::ID)), "{}", stdout);
+
+    fs::remove_file(transaction_path).unwrap();
+    fs::remove_file(accounts_path).unwrap();
+}
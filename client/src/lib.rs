@@ -0,0 +1,163 @@
+//! Client helpers for the anchor-vault-q3 program
+//!
+//! Derives the program's addresses, builds its instructions with the account
+//! metas in the order the program expects, and decodes vault accounts and
+//! program errors.
+
+use anchor_lang::{
+    prelude::*,
+    solana_program::instruction::Instruction,
+    system_program,
+    AccountDeserialize,
+    InstructionData,
+    ToAccountMetas,
+};
+use anchor_vault_q3::{ accounts, instruction };
+
+pub use anchor_vault_q3::{ VaultErrorCode, VaultState, ID as PROGRAM_ID };
+
+/// Derives a vault's state address from the key that created it and its id
+pub fn find_vault_state_address(creator: &Pubkey, vault_id: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[b"state", creator.as_ref(), VaultState::id_seed(vault_id).as_ref()],
+        &PROGRAM_ID
+    )
+}
+
+/// Derives the system account holding a vault's lamports
+pub fn find_vault_address(vault_state: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"vault", vault_state.as_ref()], &PROGRAM_ID)
+}
+
+/// Derives the registry counting the vaults a key has created
+pub fn find_registry_address(creator: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"registry", creator.as_ref()], &PROGRAM_ID)
+}
+
+/// Derives a vault's destination allow-list
+pub fn find_allow_list_address(vault_state: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"allowlist", vault_state.as_ref()], &PROGRAM_ID)
+}
+
+pub fn find_config_address() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"config"], &PROGRAM_ID)
+}
+
+pub fn find_treasury_address() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"treasury"], &PROGRAM_ID)
+}
+
+/// Derives the event authority PDA that signs `emit_cpi!` self-invocations
+pub fn find_event_authority_address() -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"__event_authority"], &PROGRAM_ID)
+}
+
+/// Creates vault `vault_id` for `user`, who pays for and owns it
+pub fn initialize_ix(user: &Pubkey, vault_id: u64, unlock_timestamp: i64, unlock_slot: u64) -> Instruction {
+    let vault_state = find_vault_state_address(user, vault_id).0;
+    Instruction {
+        program_id: PROGRAM_ID,
+        accounts: (accounts::Initialize {
+            user: *user,
+            vault_state,
+            vault: find_vault_address(&vault_state).0,
+            registry: find_registry_address(user).0,
+            system_program: system_program::ID,
+            event_authority: find_event_authority_address().0,
+            program: PROGRAM_ID,
+        }).to_account_metas(None),
+        data: (instruction::Initialize { vault_id, unlock_timestamp, unlock_slot }).data(),
+    }
+}
+
+/// Deposits `amount` lamports into the vault `creator` created as `vault_id`.
+/// `user` has to be the vault's current owner.
+pub fn deposit_ix(user: &Pubkey, creator: &Pubkey, vault_id: u64, amount: u64) -> Instruction {
+    let vault_state = find_vault_state_address(creator, vault_id).0;
+    Instruction {
+        program_id: PROGRAM_ID,
+        accounts: (accounts::Deposit {
+            user: *user,
+            vault: find_vault_address(&vault_state).0,
+            vault_state,
+            config: find_config_address().0,
+            treasury: find_treasury_address().0,
+            system_program: system_program::ID,
+            event_authority: find_event_authority_address().0,
+            program: PROGRAM_ID,
+        }).to_account_metas(None),
+        data: (instruction::Deposit { _vault_id: vault_id, amount }).data(),
+    }
+}
+
+/// Withdraws `amount` lamports to `user`, or to `destination` if it is on the
+/// vault's allow-list
+pub fn withdraw_ix(
+    user: &Pubkey,
+    creator: &Pubkey,
+    vault_id: u64,
+    amount: u64,
+    destination: Option<Pubkey>
+) -> Instruction {
+    let vault_state = find_vault_state_address(creator, vault_id).0;
+    Instruction {
+        program_id: PROGRAM_ID,
+        accounts: (accounts::Withdraw {
+            user: *user,
+            vault: find_vault_address(&vault_state).0,
+            vault_state,
+            destination,
+            allow_list: destination.map(|_| find_allow_list_address(&vault_state).0),
+            config: find_config_address().0,
+            treasury: find_treasury_address().0,
+            system_program: system_program::ID,
+            event_authority: find_event_authority_address().0,
+            program: PROGRAM_ID,
+        }).to_account_metas(None),
+        data: (instruction::Withdraw { _vault_id: vault_id, amount }).data(),
+    }
+}
+
+/// Closes the vault, paying its balance to `user` or an allow-listed
+/// `destination` and the state and allow-list rent back to `user`
+pub fn close_ix(user: &Pubkey, creator: &Pubkey, vault_id: u64, destination: Option<Pubkey>) -> Instruction {
+    let vault_state = find_vault_state_address(creator, vault_id).0;
+    Instruction {
+        program_id: PROGRAM_ID,
+        accounts: (accounts::Close {
+            user: *user,
+            vault_state,
+            vault: find_vault_address(&vault_state).0,
+            registry: find_registry_address(creator).0,
+            destination,
+            allow_list: find_allow_list_address(&vault_state).0,
+            config: find_config_address().0,
+            treasury: find_treasury_address().0,
+            system_program: system_program::ID,
+            event_authority: find_event_authority_address().0,
+            program: PROGRAM_ID,
+        }).to_account_metas(None),
+        data: (instruction::Close { _vault_id: vault_id }).data(),
+    }
+}
+
+/// Decodes a vault state account, checking its discriminator. Accounts still
+/// on an older layout need `migrate_vault_state` first.
+pub fn decode_vault_state(mut data: &[u8]) -> Result<VaultState> {
+    VaultState::try_deserialize(&mut data)
+}
+
+/// Maps a custom program error code back to the vault error it stands for.
+/// Codes outside the program's range, such as Anchor's own errors, give `None`.
+pub fn decode_error_code(code: u32) -> Option<VaultErrorCode> {
+    let index = code.checked_sub(anchor_lang::error::ERROR_CODE_OFFSET)?;
+    VaultErrorCode::try_from(index).ok()
+}
+
+/// Decodes the vault error behind a failed instruction's `ProgramError`
+pub fn decode_program_error(error: &ProgramError) -> Option<VaultErrorCode> {
+    match error {
+        ProgramError::Custom(code) => decode_error_code(*code),
+        _ => None,
+    }
+}
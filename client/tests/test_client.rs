@@ -0,0 +1,106 @@
+use anchor_lang::{ prelude::*, system_program };
+use anchor_vault_q3_client::{
+    close_ix,
+    decode_error_code,
+    decode_program_error,
+    deposit_ix,
+    find_allow_list_address,
+    find_config_address,
+    find_event_authority_address,
+    find_registry_address,
+    find_treasury_address,
+    find_vault_address,
+    find_vault_state_address,
+    initialize_ix,
+    withdraw_ix,
+    VaultErrorCode,
+    PROGRAM_ID,
+};
+
+#[test]
+fn test_default_vault_keeps_original_address() {
+    let user = Pubkey::new_unique();
+    let (vault_state, _) = find_vault_state_address(&user, 0);
+    assert_eq!(vault_state, Pubkey::find_program_address(&[b"state", user.as_ref()], &PROGRAM_ID).0);
+
+    let (other, _) = find_vault_state_address(&user, 1);
+    assert_eq!(
+        other,
+        Pubkey::find_program_address(&[b"state", user.as_ref(), &1u64.to_le_bytes()], &PROGRAM_ID).0
+    );
+}
+
+#[test]
+fn test_deposit_ix_accounts() {
+    let user = Pubkey::new_unique();
+    let (vault_state, _) = find_vault_state_address(&user, 0);
+    let (vault, _) = find_vault_address(&vault_state);
+
+    let ix = deposit_ix(&user, &user, 0, 5_000_000);
+    assert_eq!(ix.program_id, PROGRAM_ID);
+    let keys: Vec<Pubkey> = ix.accounts.iter().map(|meta| meta.pubkey).collect();
+    assert_eq!(keys, vec![
+        user,
+        vault,
+        vault_state,
+        find_config_address().0,
+        find_treasury_address().0,
+        system_program::ID,
+        find_event_authority_address().0,
+        PROGRAM_ID
+    ]);
+    assert!(ix.accounts[0].is_signer && ix.accounts[0].is_writable);
+    assert!(ix.accounts[1].is_writable && ix.accounts[2].is_writable);
+}
+
+#[test]
+fn test_optional_destination_accounts() {
+    let user = Pubkey::new_unique();
+    let (vault_state, _) = find_vault_state_address(&user, 0);
+
+    // Left out optional accounts are passed as the program id
+    let ix = withdraw_ix(&user, &user, 0, 1_000, None);
+    assert_eq!(ix.accounts[3].pubkey, PROGRAM_ID);
+    assert_eq!(ix.accounts[4].pubkey, PROGRAM_ID);
+
+    let destination = Pubkey::new_unique();
+    let ix = withdraw_ix(&user, &user, 0, 1_000, Some(destination));
+    assert_eq!(ix.accounts[3].pubkey, destination);
+    assert!(ix.accounts[3].is_writable);
+    assert_eq!(ix.accounts[4].pubkey, find_allow_list_address(&vault_state).0);
+
+    let ix = close_ix(&user, &user, 0, Some(destination));
+    assert_eq!(ix.accounts[3].pubkey, find_registry_address(&user).0);
+    assert_eq!(ix.accounts[4].pubkey, destination);
+
+    // Close always takes the allow-list, so it can be closed with the vault
+    let ix = close_ix(&user, &user, 0, None);
+    assert_eq!(ix.accounts[4].pubkey, PROGRAM_ID);
+    assert_eq!(ix.accounts[5].pubkey, find_allow_list_address(&vault_state).0);
+    assert!(ix.accounts[5].is_writable);
+}
+
+#[test]
+fn test_initialize_ix_uses_user_as_creator() {
+    let user = Pubkey::new_unique();
+    let ix = initialize_ix(&user, 2, 0, 0);
+    assert_eq!(ix.accounts[1].pubkey, find_vault_state_address(&user, 2).0);
+    assert_eq!(ix.accounts[3].pubkey, find_registry_address(&user).0);
+}
+
+#[test]
+fn test_error_codes_round_trip() {
+    // Codes are contiguous from the offset, and each maps back to its own variant
+    let mut code = anchor_lang::error::ERROR_CODE_OFFSET;
+    while let Some(error) = decode_error_code(code) {
+        assert_eq!(u32::from(error), code);
+        code += 1;
+    }
+    assert!(code > u32::from(VaultErrorCode::InsufficientVaultBalance), "Variants should be decodable");
+    assert!(decode_error_code(code + 1).is_none());
+
+    assert!(decode_error_code(0).is_none());
+    let error = decode_program_error(&ProgramError::Custom(VaultErrorCode::VaultLocked.into()));
+    assert_eq!(error.map(u32::from), Some(VaultErrorCode::VaultLocked.into()));
+    assert!(decode_program_error(&ProgramError::InvalidArgument).is_none());
+}
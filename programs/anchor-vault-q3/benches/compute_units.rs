@@ -0,0 +1,35 @@
+//! Measures the compute units of the vault instructions and writes a markdown
+//! and a JSON report next to this file, comparing them with the committed
+//! baseline. `make bench-baseline` copies the JSON report over the baseline,
+//! which also works when there is no baseline yet.
+
+use std::{ collections::BTreeMap, fs, path::Path };
+
+#[path = "../tests/utils.rs"]
+pub mod utils;
+
+fn main() {
+    let benches = Path::new(env!("CARGO_MANIFEST_DIR")).join("benches");
+    let baseline: BTreeMap<String, u64> = fs
+        ::read_to_string(benches.join("compute_units_baseline.json"))
+        .map(|contents| serde_json::from_str(&contents).unwrap())
+        .unwrap_or_default();
+    let measured = utils::compute_unit_scenarios();
+
+    let mut markdown = String::from(
+        "| Scenario | Compute units | Baseline | Change |\n|---|---:|---:|---:|\n"
+    );
+    for (name, units) in &measured {
+        let (baseline_units, change) = match baseline.get(*name) {
+            Some(&baseline_units) =>
+                (baseline_units.to_string(), format!("{:+}", (*units as i64) - (baseline_units as i64))),
+            None => ("-".to_string(), "new".to_string()),
+        };
+        markdown.push_str(&format!("| {} | {} | {} | {} |\n", name, units, baseline_units, change));
+    }
+
+    let report: BTreeMap<&str, u64> = measured.into_iter().collect();
+    fs::write(benches.join("compute_units.md"), &markdown).unwrap();
+    fs::write(benches.join("compute_units.json"), serde_json::to_string_pretty(&report).unwrap() + "\n").unwrap();
+    print!("{}", markdown);
+}
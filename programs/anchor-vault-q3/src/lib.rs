@@ -1,7 +1,31 @@
 #![allow(deprecated)]
 #![allow(unexpected_cfgs)]
 
-use anchor_lang::{ prelude::*, system_program::{ Transfer, transfer } };
+use anchor_lang::{
+    prelude::*,
+    solana_program::{ program::{ invoke, invoke_signed }, stake, sysvar },
+    system_program::{ allocate, assign, Allocate, Assign, Transfer, transfer },
+};
+use anchor_spl::{
+    associated_token::AssociatedToken,
+    token_interface::{
+        burn,
+        close_account,
+        get_mint_extension_data,
+        harvest_withheld_tokens_to_mint,
+        mint_to,
+        spl_token_2022::{ self, extension::transfer_fee::TransferFeeConfig },
+        transfer_checked,
+        Burn,
+        CloseAccount,
+        HarvestWithheldTokensToMint,
+        Mint,
+        MintTo,
+        TokenAccount,
+        TokenInterface,
+        TransferChecked,
+    },
+};
 
 declare_id!("EQSjMmLReExSNm29r7MW1RX5UQCQbhv2bpjZYPTAAwXH");
 
@@ -9,31 +33,337 @@ declare_id!("EQSjMmLReExSNm29r7MW1RX5UQCQbhv2bpjZYPTAAwXH");
 pub mod anchor_vault_q3 {
     use super::*;
 
-    pub fn initialize(ctx: Context<Initialize>) -> Result<()> {
-        ctx.accounts.initialize(ctx.bumps)
+    pub fn initialize(
+        ctx: Context<Initialize>,
+        vault_id: u64,
+        unlock_timestamp: i64,
+        unlock_slot: u64
+    ) -> Result<()> {
+        let event = ctx.accounts.initialize(vault_id, unlock_timestamp, unlock_slot, &ctx.bumps)?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn deposit(ctx: Context<Deposit>, _vault_id: u64, amount: u64) -> Result<()> {
+        let (event, goal_event) = ctx.accounts.deposit(amount)?;
+        emit_cpi!(event);
+        if let Some(goal_event) = goal_event {
+            emit_cpi!(goal_event);
+        }
+        Ok(())
+    }
+
+    pub fn deposit_for(
+        ctx: Context<DepositFor>,
+        owner: Pubkey,
+        _vault_id: u64,
+        amount: u64
+    ) -> Result<()> {
+        let (event, goal_event) = ctx.accounts.deposit_for(owner, amount, &ctx.bumps)?;
+        emit_cpi!(event);
+        if let Some(goal_event) = goal_event {
+            emit_cpi!(goal_event);
+        }
+        Ok(())
+    }
+
+    pub fn withdraw(ctx: Context<Withdraw>, _vault_id: u64, amount: u64) -> Result<()> {
+        let event = ctx.accounts.withdraw(amount)?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn withdraw_all(ctx: Context<Withdraw>, _vault_id: u64) -> Result<()> {
+        let event = ctx.accounts.withdraw_all()?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn close(ctx: Context<Close>, _vault_id: u64) -> Result<()> {
+        let event = ctx.accounts.close()?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn set_lock(
+        ctx: Context<SetLock>,
+        _vault_id: u64,
+        unlock_timestamp: i64,
+        unlock_slot: u64
+    ) -> Result<()> {
+        ctx.accounts.set_lock(unlock_timestamp, unlock_slot)
+    }
+
+    pub fn set_min_withdrawal(
+        ctx: Context<SetMinWithdrawal>,
+        _vault_id: u64,
+        min_withdrawal: u64
+    ) -> Result<()> {
+        ctx.accounts.set_min_withdrawal(min_withdrawal)
+    }
+
+    pub fn set_withdrawal_limit(
+        ctx: Context<SetWithdrawalLimit>,
+        _vault_id: u64,
+        withdrawal_limit: u64,
+        withdrawal_window: i64
+    ) -> Result<()> {
+        ctx.accounts.set_withdrawal_limit(withdrawal_limit, withdrawal_window)
+    }
+
+    pub fn set_goal(
+        ctx: Context<SetGoal>,
+        _vault_id: u64,
+        goal_amount: u64,
+        goal_deadline: i64,
+        lock_withdrawals: bool
+    ) -> Result<()> {
+        ctx.accounts.set_goal(goal_amount, goal_deadline, lock_withdrawals)
+    }
+
+    pub fn add_destination(
+        ctx: Context<AddDestination>,
+        _vault_id: u64,
+        destination: Pubkey
+    ) -> Result<()> {
+        ctx.accounts.add_destination(destination, &ctx.bumps)
+    }
+
+    pub fn remove_destination(
+        ctx: Context<RemoveDestination>,
+        _vault_id: u64,
+        destination: Pubkey
+    ) -> Result<()> {
+        ctx.accounts.remove_destination(destination)
+    }
+
+    pub fn create_multisig(
+        ctx: Context<CreateMultisig>,
+        _vault_id: u64,
+        owners: Vec<Pubkey>,
+        threshold: u8
+    ) -> Result<()> {
+        ctx.accounts.create_multisig(owners, threshold, &ctx.bumps)
+    }
+
+    pub fn propose_withdrawal(
+        ctx: Context<ProposeWithdrawal>,
+        action: ProposalAction,
+        destination: Pubkey
+    ) -> Result<()> {
+        ctx.accounts.propose_withdrawal(action, destination, &ctx.bumps)
+    }
+
+    pub fn approve(ctx: Context<Approve>) -> Result<()> {
+        ctx.accounts.approve()
+    }
+
+    pub fn execute(ctx: Context<Execute>) -> Result<()> {
+        match ctx.accounts.execute()? {
+            ExecutedProposal::Withdrawn(event) => emit_cpi!(event),
+            ExecutedProposal::Closed(event) => emit_cpi!(event),
+        }
+        Ok(())
+    }
+
+    pub fn cancel(ctx: Context<Cancel>) -> Result<()> {
+        // Only the proposer can cancel; the account constraints close the proposal
+        ctx.accounts.cancel()
+    }
+
+    pub fn approve_delegate(
+        ctx: Context<ApproveDelegate>,
+        _vault_id: u64,
+        delegate: Pubkey,
+        allowance: u64,
+        expires_at: i64
+    ) -> Result<()> {
+        ctx.accounts.approve_delegate(delegate, allowance, expires_at, &ctx.bumps)
+    }
+
+    pub fn withdraw_as_delegate(ctx: Context<WithdrawAsDelegate>, amount: u64) -> Result<()> {
+        let event = ctx.accounts.withdraw_as_delegate(amount)?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn revoke_delegate(ctx: Context<RevokeDelegate>, _vault_id: u64, _delegate: Pubkey) -> Result<()> {
+        // The account constraints close the delegation and refund its rent
+        ctx.accounts.revoke_delegate()
+    }
+
+    pub fn propose_owner(ctx: Context<ProposeOwner>, _vault_id: u64, new_owner: Pubkey) -> Result<()> {
+        ctx.accounts.propose_owner(new_owner)
+    }
+
+    pub fn accept_owner(ctx: Context<AcceptOwner>) -> Result<()> {
+        let event = ctx.accounts.accept_owner()?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn cancel_owner_transfer(ctx: Context<ProposeOwner>, _vault_id: u64) -> Result<()> {
+        ctx.accounts.cancel_owner_transfer()
+    }
+
+    pub fn set_beneficiary(
+        ctx: Context<SetBeneficiary>,
+        _vault_id: u64,
+        beneficiary: Pubkey,
+        inactivity_period: i64
+    ) -> Result<()> {
+        ctx.accounts.set_beneficiary(beneficiary, inactivity_period)
+    }
+
+    pub fn heartbeat(ctx: Context<SetBeneficiary>, _vault_id: u64) -> Result<()> {
+        ctx.accounts.heartbeat()
+    }
+
+    pub fn claim_inheritance(ctx: Context<ClaimInheritance>) -> Result<()> {
+        let event = ctx.accounts.claim_inheritance()?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn create_stream(
+        ctx: Context<CreateStream>,
+        _vault_id: u64,
+        recipient: Pubkey,
+        total: u64,
+        start: i64,
+        cliff: i64,
+        end: i64
+    ) -> Result<()> {
+        ctx.accounts.create_stream(recipient, total, start, cliff, end, &ctx.bumps)
+    }
+
+    pub fn claim_stream(ctx: Context<ClaimStream>) -> Result<()> {
+        let event = ctx.accounts.claim_stream()?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn cancel_stream(ctx: Context<CancelStream>, _vault_id: u64) -> Result<()> {
+        if let Some(event) = ctx.accounts.cancel_stream()? {
+            emit_cpi!(event);
+        }
+        Ok(())
+    }
+
+    pub fn stake_vault(ctx: Context<StakeVault>, _vault_id: u64, amount: u64) -> Result<()> {
+        ctx.accounts.stake_vault(amount, &ctx.bumps)
+    }
+
+    pub fn deactivate_stake(ctx: Context<DeactivateStake>, _vault_id: u64) -> Result<()> {
+        ctx.accounts.deactivate_stake()
+    }
+
+    pub fn withdraw_stake(ctx: Context<WithdrawStake>, _vault_id: u64) -> Result<()> {
+        ctx.accounts.withdraw_stake()
+    }
+
+    pub fn set_guardians(
+        ctx: Context<SetGuardians>,
+        _vault_id: u64,
+        guardians: Vec<Pubkey>,
+        threshold: u8,
+        recovery_delay: i64
+    ) -> Result<()> {
+        ctx.accounts.set_guardians(guardians, threshold, recovery_delay, &ctx.bumps)
+    }
+
+    pub fn remove_guardians(ctx: Context<RemoveGuardians>, _vault_id: u64) -> Result<()> {
+        // The account constraints close the guardian set and refund its rent
+        ctx.accounts.remove_guardians()
+    }
+
+    pub fn initiate_recovery(ctx: Context<InitiateRecovery>, new_owner: Pubkey) -> Result<()> {
+        ctx.accounts.initiate_recovery(new_owner, &ctx.bumps)
+    }
+
+    pub fn approve_recovery(ctx: Context<ApproveRecovery>) -> Result<()> {
+        ctx.accounts.approve_recovery()
+    }
+
+    pub fn veto_recovery(_ctx: Context<VetoRecovery>, _vault_id: u64) -> Result<()> {
+        // The account constraints check the owner and close the request
+        Ok(())
+    }
+
+    pub fn complete_recovery(ctx: Context<CompleteRecovery>) -> Result<()> {
+        let event = ctx.accounts.complete_recovery()?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn init_config(ctx: Context<InitConfig>, admin: Pubkey) -> Result<()> {
+        ctx.accounts.init_config(admin, &ctx.bumps)
+    }
+
+    pub fn set_paused(ctx: Context<UpdateConfig>, paused: bool) -> Result<()> {
+        ctx.accounts.set_paused(paused)
     }
 
-    pub fn deposit(ctx: Context<Deposit>, amount: u64) -> Result<()> {
-        ctx.accounts.deposit(amount)
+    pub fn set_fees(
+        ctx: Context<UpdateConfig>,
+        deposit_fee_bps: u16,
+        withdraw_fee_bps: u16,
+        close_fee_lamports: u64
+    ) -> Result<()> {
+        ctx.accounts.set_fees(deposit_fee_bps, withdraw_fee_bps, close_fee_lamports)
     }
 
-    pub fn withdraw(ctx: Context<Withdraw>, amount: u64) -> Result<()> {
-        ctx.accounts.withdraw(amount)
+    pub fn collect_fees(ctx: Context<CollectFees>) -> Result<()> {
+        let event = ctx.accounts.collect_fees(&ctx.bumps)?;
+        emit_cpi!(event);
+        Ok(())
+    }
+
+    pub fn migrate_vault_state(ctx: Context<MigrateVaultState>, _vault_id: u64) -> Result<()> {
+        ctx.accounts.migrate_vault_state(ctx.bumps)
+    }
+
+    pub fn initialize_token_vault(ctx: Context<InitializeTokenVault>) -> Result<()> {
+        ctx.accounts.initialize_token_vault(ctx.bumps)
+    }
+
+    pub fn deposit_token(ctx: Context<DepositToken>, amount: u64) -> Result<()> {
+        ctx.accounts.deposit_token(amount)
+    }
+
+    pub fn withdraw_token(ctx: Context<WithdrawToken>, amount: u64) -> Result<()> {
+        ctx.accounts.withdraw_token(amount)
+    }
+
+    pub fn close_token_vault(ctx: Context<CloseTokenVault>) -> Result<()> {
+        ctx.accounts.close_token_vault()
+    }
+
+    pub fn initialize_pool(ctx: Context<InitializePool>) -> Result<()> {
+        ctx.accounts.initialize_pool(&ctx.bumps)
+    }
+
+    pub fn deposit_pool(ctx: Context<DepositPool>, amount: u64) -> Result<()> {
+        ctx.accounts.deposit_pool(amount)?;
+        Ok(())
     }
 
-    pub fn close(ctx: Context<Close>) -> Result<()> {
-        ctx.accounts.close()
+    pub fn withdraw_pool(ctx: Context<WithdrawPool>, shares: u64) -> Result<()> {
+        ctx.accounts.withdraw_pool(shares)?;
+        Ok(())
     }
 }
 
+#[event_cpi]
 #[derive(Accounts)]
+#[instruction(vault_id: u64)]
 pub struct Initialize<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
     #[account(
         init,
         payer = user,
-        seeds = [b"state", user.key().as_ref()],
+        seeds = [b"state", user.key().as_ref(), VaultState::id_seed(vault_id).as_ref()],
         bump,
         space = 8 + VaultState::INIT_SPACE
     )]
@@ -44,11 +374,40 @@ pub struct Initialize<'info> {
         bump,
     )]
     pub vault: SystemAccount<'info>,
+    #[account(
+        init_if_needed,
+        payer = user,
+        seeds = [b"registry", user.key().as_ref()],
+        bump,
+        space = 8 + VaultRegistry::INIT_SPACE
+    )]
+    pub registry: Account<'info, VaultRegistry>,
     pub system_program: Program<'info, System>,
 }
 
 impl<'info> Initialize<'info> {
-    pub fn initialize(&mut self, bumps: InitializeBumps) -> Result<()> {
+    pub fn initialize(
+        &mut self,
+        vault_id: u64,
+        unlock_timestamp: i64,
+        unlock_slot: u64,
+        bumps: &InitializeBumps
+    ) -> Result<VaultInitialized> {
+        // Ids are handed out without gaps so clients can enumerate 0..vault_count;
+        // closed ids may be reused
+        if vault_id > self.registry.vault_count {
+            return Err(VaultErrorCode::InvalidVaultId.into());
+        }
+        self.registry.bump = bumps.registry;
+        if vault_id == self.registry.vault_count {
+            self.registry.vault_count = self.registry.vault_count
+                .checked_add(1)
+                .ok_or(VaultErrorCode::ArithmeticOverflow)?;
+        }
+        self.registry.open_vaults = self.registry.open_vaults
+            .checked_add(1)
+            .ok_or(VaultErrorCode::ArithmeticOverflow)?;
+
         let rent_exempt = Rent::get()?.minimum_balance(self.vault.to_account_info().data_len());
         let cpi_program = self.system_program.to_account_info();
 
@@ -62,11 +421,30 @@ impl<'info> Initialize<'info> {
 
         self.vault_state.bump = bumps.vault_state;
         self.vault_state.vault_bump = bumps.vault;
-        Ok(())
+        self.vault_state.version = VaultState::CURRENT_VERSION;
+        self.vault_state.vault_id = vault_id;
+        self.vault_state.creator = self.user.key();
+        self.vault_state.owner = self.user.key();
+        self.vault_state.unlock_timestamp = unlock_timestamp;
+        self.vault_state.unlock_slot = unlock_slot;
+        let clock = Clock::get()?;
+        self.vault_state.record_owner_activity(&clock);
+
+        Ok(VaultInitialized {
+            user: self.user.key(),
+            vault_state: self.vault_state.key(),
+            vault: self.vault.key(),
+            vault_id,
+            amount: rent_exempt,
+            balance: self.vault.lamports(),
+            timestamp: clock.unix_timestamp,
+        })
     }
 }
 
+#[event_cpi]
 #[derive(Accounts)]
+#[instruction(vault_id: u64)]
 pub struct Deposit<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
@@ -76,13 +454,29 @@ pub struct Deposit<'info> {
         bump = vault_state.vault_bump,
     )]
     pub vault: SystemAccount<'info>,
-    #[account(seeds = [b"state", user.key().as_ref()], bump = vault_state.bump)]
+    #[account(
+        mut,
+        seeds = [b"state", vault_state.creator.as_ref(), VaultState::id_seed(vault_id).as_ref()],
+        bump = vault_state.bump,
+        constraint = vault_state.owner == user.key() @ VaultErrorCode::NotVaultOwner,
+    )]
     pub vault_state: Account<'info, VaultState>,
+    /// CHECK: read with `ProgramConfig::load`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+    /// Receives protocol fees
+    #[account(mut, seeds = [b"treasury"], bump)]
+    pub treasury: SystemAccount<'info>,
     pub system_program: Program<'info, System>,
 }
 
 impl<'info> Deposit<'info> {
-    pub fn deposit(&mut self, amount: u64) -> Result<()> {
+    /// Also returns a `GoalReached` event when this deposit completes the vault's goal
+    pub fn deposit(&mut self, amount: u64) -> Result<(Deposited, Option<GoalReached>)> {
+        let config = ProgramConfig::load(&self.config)?;
+        config.check_not_paused()?;
+        let fee = config.deposit_fee(amount)?;
+        let credited = amount.checked_sub(fee).ok_or(VaultErrorCode::ArithmeticOverflow)?;
         let cpi_program = self.system_program.to_account_info();
 
         let cpi_accounts = Transfer {
@@ -91,13 +485,119 @@ impl<'info> Deposit<'info> {
         };
 
         let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
-        transfer(cpi_ctx, amount)?;
+        transfer(cpi_ctx, credited)?;
+        pay_fee(&self.system_program, self.user.to_account_info(), &self.treasury, fee)?;
 
-        Ok(())
+        let clock = Clock::get()?;
+        self.vault_state.record_deposit(credited, &clock)?;
+        self.vault_state.record_owner_activity(&clock);
+        let goal_event = goal_reached_event(&mut self.vault_state, &clock);
+
+        let event = Deposited {
+            user: self.user.key(),
+            depositor: self.user.key(),
+            vault_state: self.vault_state.key(),
+            vault: self.vault.key(),
+            amount: credited,
+            balance: self.vault.lamports(),
+            timestamp: clock.unix_timestamp,
+        };
+        Ok((event, goal_event))
+    }
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(owner: Pubkey, vault_id: u64)]
+pub struct DepositFor<'info> {
+    #[account(mut)]
+    pub depositor: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"vault", vault_state.key().as_ref()],
+        bump = vault_state.vault_bump,
+    )]
+    pub vault: SystemAccount<'info>,
+    #[account(
+        mut,
+        seeds = [b"state", vault_state.creator.as_ref(), VaultState::id_seed(vault_id).as_ref()],
+        bump = vault_state.bump,
+        constraint = vault_state.owner == owner @ VaultErrorCode::NotVaultOwner,
+    )]
+    pub vault_state: Account<'info, VaultState>,
+    /// Pass to keep a running total of this depositor's contributions
+    #[account(
+        init_if_needed,
+        payer = depositor,
+        seeds = [b"contribution", vault_state.key().as_ref(), depositor.key().as_ref()],
+        bump,
+        space = 8 + Contribution::INIT_SPACE
+    )]
+    pub contribution: Option<Account<'info, Contribution>>,
+    /// CHECK: read with `ProgramConfig::load`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+    /// Receives protocol fees
+    #[account(mut, seeds = [b"treasury"], bump)]
+    pub treasury: SystemAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> DepositFor<'info> {
+    pub fn deposit_for(
+        &mut self,
+        owner: Pubkey,
+        amount: u64,
+        bumps: &DepositForBumps
+    ) -> Result<(Deposited, Option<GoalReached>)> {
+        let config = ProgramConfig::load(&self.config)?;
+        config.check_not_paused()?;
+        let fee = config.deposit_fee(amount)?;
+        let credited = amount.checked_sub(fee).ok_or(VaultErrorCode::ArithmeticOverflow)?;
+        let cpi_program = self.system_program.to_account_info();
+
+        let cpi_accounts = Transfer {
+            from: self.depositor.to_account_info(),
+            to: self.vault.to_account_info(),
+        };
+
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        transfer(cpi_ctx, credited)?;
+        pay_fee(&self.system_program, self.depositor.to_account_info(), &self.treasury, fee)?;
+
+        let clock = Clock::get()?;
+        self.vault_state.record_deposit(credited, &clock)?;
+
+        if let (Some(contribution), Some(bump)) = (self.contribution.as_mut(), bumps.contribution) {
+            contribution.bump = bump;
+            contribution.vault_state = self.vault_state.key();
+            contribution.depositor = self.depositor.key();
+            contribution.total_contributed = contribution.total_contributed
+                .checked_add(credited)
+                .ok_or(VaultErrorCode::ArithmeticOverflow)?;
+            contribution.contribution_count = contribution.contribution_count
+                .checked_add(1)
+                .ok_or(VaultErrorCode::ArithmeticOverflow)?;
+            contribution.last_contribution_timestamp = clock.unix_timestamp;
+        }
+
+        let goal_event = goal_reached_event(&mut self.vault_state, &clock);
+        let event = Deposited {
+            user: owner,
+            depositor: self.depositor.key(),
+            vault_state: self.vault_state.key(),
+            vault: self.vault.key(),
+            amount: credited,
+            balance: self.vault.lamports(),
+            timestamp: clock.unix_timestamp,
+        };
+        Ok((event, goal_event))
     }
 }
 
+#[event_cpi]
 #[derive(Accounts)]
+#[instruction(vault_id: u64)]
 pub struct Withdraw<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
@@ -107,46 +607,106 @@ pub struct Withdraw<'info> {
         bump = vault_state.vault_bump,
     )]
     pub vault: SystemAccount<'info>,
-    #[account(seeds = [b"state", user.key().as_ref()], bump = vault_state.bump)]
+    #[account(
+        mut,
+        seeds = [b"state", vault_state.creator.as_ref(), VaultState::id_seed(vault_id).as_ref()],
+        bump = vault_state.bump,
+        constraint = vault_state.owner == user.key() @ VaultErrorCode::NotVaultOwner,
+    )]
     pub vault_state: Account<'info, VaultState>,
+    /// Receives the funds instead of `user`; must be on the allow-list
+    #[account(mut)]
+    pub destination: Option<SystemAccount<'info>>,
+    #[account(seeds = [b"allowlist", vault_state.key().as_ref()], bump = allow_list.bump)]
+    pub allow_list: Option<Account<'info, DestinationAllowList>>,
+    /// CHECK: read with `ProgramConfig::load`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+    /// Receives protocol fees
+    #[account(mut, seeds = [b"treasury"], bump)]
+    pub treasury: SystemAccount<'info>,
     pub system_program: Program<'info, System>,
 }
 
 impl<'info> Withdraw<'info> {
-    pub fn withdraw(&mut self, amount: u64) -> Result<()> {
-        let rent_exempt = Rent::get()?.minimum_balance(self.vault.to_account_info().data_len());
-        if amount < rent_exempt {
+    pub fn withdraw(&mut self, amount: u64) -> Result<Withdrawn> {
+        let config = ProgramConfig::load(&self.config)?;
+        config.check_not_paused()?;
+        if amount == 0 || amount < self.vault_state.min_withdrawal {
             return Err(VaultErrorCode::InsufficientWithdrawalAmount.into());
         }
-        if amount > self.vault.to_account_info().lamports() {
+        if amount > self.vault_state.available_balance(&self.vault)? {
             return Err(VaultErrorCode::InsufficientVaultBalance.into());
         }
-        let cpi_program = self.system_program.to_account_info();
+        self.pay_out(amount)
+    }
 
-        let cpi_accounts = Transfer {
-            from: self.vault.to_account_info(),
-            to: self.user.to_account_info(),
-        };
+    /// Withdraws everything but the rent reserve, capped by the withdrawal limit.
+    /// The vault's minimum withdrawal doesn't apply, so a balance below it can
+    /// still be recovered. Also works while the program is paused, as the
+    /// emergency way out next to `close`.
+    pub fn withdraw_all(&mut self) -> Result<Withdrawn> {
+        let clock = Clock::get()?;
+        self.vault_state.apply_pending_limit(&clock);
+        let amount = self.vault_state
+            .available_balance(&self.vault)?
+            .min(self.vault_state.window_remaining(&clock));
+        if amount == 0 {
+            return Err(VaultErrorCode::InsufficientVaultBalance.into());
+        }
+        self.pay_out(amount)
+    }
 
-        let vault_state_key = self.vault_state.key();
-        let seeds = &[b"vault".as_ref(), vault_state_key.as_ref(), &[self.vault_state.vault_bump]];
-        let seeds_signer = &[&seeds[..]];
+    fn pay_out(&mut self, amount: u64) -> Result<Withdrawn> {
+        if self.vault_state.has_multisig() {
+            return Err(VaultErrorCode::MultisigRequired.into());
+        }
+        let clock = Clock::get()?;
+        if self.vault_state.is_locked(&clock) {
+            return Err(VaultErrorCode::VaultLocked.into());
+        }
+        if self.vault_state.is_goal_locked(&clock) {
+            return Err(VaultErrorCode::GoalNotReached.into());
+        }
+        let destination = resolve_destination(&self.user, &self.destination, self.allow_list.as_deref())?;
+        // The fee comes out of the withdrawn amount
+        let fee = ProgramConfig::load(&self.config)?.withdraw_fee(amount)?;
+        transfer_from_vault_with_fee(
+            &self.system_program,
+            &self.vault,
+            &self.vault_state,
+            destination.clone(),
+            &self.treasury,
+            amount,
+            fee
+        )?;
 
-        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, seeds_signer);
-        transfer(cpi_ctx, amount)?;
+        self.vault_state.record_withdrawal(amount, &clock)?;
+        self.vault_state.record_owner_activity(&clock);
 
-        Ok(())
+        Ok(Withdrawn {
+            user: self.user.key(),
+            vault_state: self.vault_state.key(),
+            vault: self.vault.key(),
+            destination: destination.key(),
+            amount,
+            balance: self.vault.lamports(),
+            timestamp: clock.unix_timestamp,
+        })
     }
 }
 
+#[event_cpi]
 #[derive(Accounts)]
+#[instruction(vault_id: u64)]
 pub struct Close<'info> {
     #[account(mut)]
     pub user: Signer<'info>,
     #[account(
         mut,
-        seeds = [b"state", user.key().as_ref()],
+        seeds = [b"state", vault_state.creator.as_ref(), VaultState::id_seed(vault_id).as_ref()],
         bump = vault_state.bump,
+        constraint = vault_state.owner == user.key() @ VaultErrorCode::NotVaultOwner,
         close = user,
     )]
     pub vault_state: Account<'info, VaultState>,
@@ -156,50 +716,3084 @@ pub struct Close<'info> {
         bump = vault_state.vault_bump,
     )]
     pub vault: SystemAccount<'info>,
+    #[account(mut, seeds = [b"registry", vault_state.creator.as_ref()], bump = registry.bump)]
+    pub registry: Account<'info, VaultRegistry>,
+    /// Receives the vault balance instead of `user`; must be on the allow-list.
+    /// The state account's rent still goes back to `user`.
+    #[account(mut)]
+    pub destination: Option<SystemAccount<'info>>,
+    /// CHECK: read with `DestinationAllowList::load` and closed along with the vault
+    #[account(mut, seeds = [b"allowlist", vault_state.key().as_ref()], bump)]
+    pub allow_list: UncheckedAccount<'info>,
+    /// CHECK: read with `ProgramConfig::load`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+    /// Receives protocol fees
+    #[account(mut, seeds = [b"treasury"], bump)]
+    pub treasury: SystemAccount<'info>,
     pub system_program: Program<'info, System>,
 }
 
 impl<'info> Close<'info> {
-    pub fn close(&mut self) -> Result<()> {
+    pub fn close(&mut self) -> Result<VaultClosed> {
+        if self.vault_state.has_multisig() {
+            return Err(VaultErrorCode::MultisigRequired.into());
+        }
+        let clock = Clock::get()?;
+        if self.vault_state.is_locked(&clock) {
+            return Err(VaultErrorCode::VaultLocked.into());
+        }
+        if self.vault_state.is_goal_locked(&clock) {
+            return Err(VaultErrorCode::GoalNotReached.into());
+        }
+        if self.vault_state.reserved_for_streams > 0 {
+            return Err(VaultErrorCode::StreamsOutstanding.into());
+        }
+        if self.vault_state.staked_lamports > 0 {
+            return Err(VaultErrorCode::StakeOutstanding.into());
+        }
+        if self.vault_state.delegation_count > 0 {
+            return Err(VaultErrorCode::DelegationsOutstanding.into());
+        }
+        if self.vault_state.has_guardians {
+            return Err(VaultErrorCode::GuardiansOutstanding.into());
+        }
         // Ensure the vault has lamports before closing
-        if self.vault.to_account_info().lamports() <= 0 {
+        let remaining = self.vault.to_account_info().lamports();
+        if remaining == 0 {
             return Err(VaultErrorCode::VaultAlreadyClosed.into());
         }
-        // Close the vault state account
-        let vault_state_key = self.vault_state.key();
-        let seeds = &[b"vault".as_ref(), vault_state_key.as_ref(), &[self.vault_state.vault_bump]];
-        let seeds_signer = &[&seeds[..]];   
-        let cpi_program = self.vault.to_account_info();
-        let cpi_accounts = Transfer {
-            from: self.vault.to_account_info(),
-            to: self.user.to_account_info(),
-        };
-        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, seeds_signer);
-        transfer(cpi_ctx, self.vault.to_account_info().lamports())?; // Transfer lamports to close the account
+        let allow_list = DestinationAllowList::load(&self.allow_list)?;
+        let destination = resolve_destination(&self.user, &self.destination, allow_list.as_ref())?;
+        let fee = ProgramConfig::load(&self.config)?.close_fee_lamports.min(remaining);
+        // Transfer lamports to close the vault account
+        transfer_from_vault_with_fee(
+            &self.system_program,
+            &self.vault,
+            &self.vault_state,
+            destination.clone(),
+            &self.treasury,
+            remaining,
+            fee
+        )?;
+        self.vault_state.record_withdrawal(remaining, &clock)?;
+        self.registry.open_vaults = self.registry.open_vaults.saturating_sub(1);
+        if allow_list.is_some() {
+            close_unchecked(&self.allow_list, self.user.to_account_info())?;
+        }
         // Set the vault state account to zero
         self.vault_state.bump = 0;
         self.vault_state.vault_bump = 0;
+        Ok(VaultClosed {
+            user: self.user.key(),
+            vault_state: self.vault_state.key(),
+            vault: self.vault.key(),
+            destination: destination.key(),
+            amount: remaining,
+            balance: self.vault.lamports(),
+            timestamp: clock.unix_timestamp,
+        })
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(vault_id: u64)]
+pub struct SetLock<'info> {
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"state", vault_state.creator.as_ref(), VaultState::id_seed(vault_id).as_ref()],
+        bump = vault_state.bump,
+        constraint = vault_state.owner == user.key() @ VaultErrorCode::NotVaultOwner,
+    )]
+    pub vault_state: Account<'info, VaultState>,
+}
+
+impl<'info> SetLock<'info> {
+    pub fn set_lock(&mut self, unlock_timestamp: i64, unlock_slot: u64) -> Result<()> {
+        // A lock can only ever be extended
+        if
+            unlock_timestamp < self.vault_state.unlock_timestamp ||
+            unlock_slot < self.vault_state.unlock_slot
+        {
+            return Err(VaultErrorCode::LockCannotBeShortened.into());
+        }
+        self.vault_state.unlock_timestamp = unlock_timestamp;
+        self.vault_state.unlock_slot = unlock_slot;
+        self.vault_state.record_owner_activity(&Clock::get()?);
         Ok(())
     }
 }
 
-#[account]
-#[derive(InitSpace)]
-pub struct VaultState {
-    pub bump: u8,
-    pub vault_bump: u8,
+#[derive(Accounts)]
+#[instruction(vault_id: u64)]
+pub struct SetMinWithdrawal<'info> {
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"state", vault_state.creator.as_ref(), VaultState::id_seed(vault_id).as_ref()],
+        bump = vault_state.bump,
+        constraint = vault_state.owner == user.key() @ VaultErrorCode::NotVaultOwner,
+    )]
+    pub vault_state: Account<'info, VaultState>,
 }
 
-// impl Space for VaultState {
-//     const INIT_SPACE: usize = 8 + 1 + 1; // discriminator + bump + vault_bump
-// }
+impl<'info> SetMinWithdrawal<'info> {
+    pub fn set_min_withdrawal(&mut self, min_withdrawal: u64) -> Result<()> {
+        self.vault_state.min_withdrawal = min_withdrawal;
+        self.vault_state.record_owner_activity(&Clock::get()?);
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(vault_id: u64)]
+pub struct SetWithdrawalLimit<'info> {
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"state", vault_state.creator.as_ref(), VaultState::id_seed(vault_id).as_ref()],
+        bump = vault_state.bump,
+        constraint = vault_state.owner == user.key() @ VaultErrorCode::NotVaultOwner,
+    )]
+    pub vault_state: Account<'info, VaultState>,
+}
+
+impl<'info> SetWithdrawalLimit<'info> {
+    /// Tightening the limit applies immediately and drops any pending change.
+    /// Anything else (raising or removing the limit, shortening the window) only
+    /// applies after `WITHDRAWAL_LIMIT_DELAY`, so a stolen key can't lift it on the spot.
+    pub fn set_withdrawal_limit(&mut self, withdrawal_limit: u64, withdrawal_window: i64) -> Result<()> {
+        if withdrawal_limit != 0 && withdrawal_window <= 0 {
+            return Err(VaultErrorCode::InvalidWithdrawalLimit.into());
+        }
+        let clock = Clock::get()?;
+        let state = &mut self.vault_state;
+        state.apply_pending_limit(&clock);
+
+        let tightens =
+            withdrawal_limit != 0 &&
+            (state.withdrawal_limit == 0 ||
+                (withdrawal_limit <= state.withdrawal_limit &&
+                    withdrawal_window >= state.withdrawal_window));
+        if tightens {
+            state.withdrawal_limit = withdrawal_limit;
+            state.withdrawal_window = withdrawal_window;
+            state.pending_withdrawal_limit = 0;
+            state.pending_withdrawal_window = 0;
+            state.pending_limit_effective_at = 0;
+        } else {
+            state.pending_withdrawal_limit = withdrawal_limit;
+            state.pending_withdrawal_window = if withdrawal_limit == 0 { 0 } else { withdrawal_window };
+            state.pending_limit_effective_at = clock.unix_timestamp
+                .checked_add(VaultState::WITHDRAWAL_LIMIT_DELAY)
+                .ok_or(VaultErrorCode::ArithmeticOverflow)?;
+        }
+        state.record_owner_activity(&clock);
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(vault_id: u64)]
+pub struct SetGoal<'info> {
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"state", vault_state.creator.as_ref(), VaultState::id_seed(vault_id).as_ref()],
+        bump = vault_state.bump,
+        constraint = vault_state.owner == user.key() @ VaultErrorCode::NotVaultOwner,
+    )]
+    pub vault_state: Account<'info, VaultState>,
+}
+
+impl<'info> SetGoal<'info> {
+    /// Sets a new savings goal, or removes it with a `goal_amount` of 0. While
+    /// withdrawals are held back by a goal, it can't be replaced by a looser one.
+    pub fn set_goal(&mut self, goal_amount: u64, goal_deadline: i64, lock_withdrawals: bool) -> Result<()> {
+        let clock = Clock::get()?;
+        if goal_amount == 0 && (goal_deadline != 0 || lock_withdrawals) {
+            return Err(VaultErrorCode::InvalidGoal.into());
+        }
+        if goal_deadline != 0 && goal_deadline <= clock.unix_timestamp {
+            return Err(VaultErrorCode::InvalidGoal.into());
+        }
+        let state = &mut self.vault_state;
+        if state.is_goal_locked(&clock) {
+            let loosens =
+                !lock_withdrawals ||
+                goal_amount < state.goal_amount ||
+                (state.goal_deadline != 0 && (goal_deadline == 0 || goal_deadline < state.goal_deadline));
+            if loosens {
+                return Err(VaultErrorCode::GoalCannotBeLoosened.into());
+            }
+        }
+
+        state.goal_amount = goal_amount;
+        state.goal_deadline = goal_deadline;
+        state.goal_locks_withdrawals = lock_withdrawals;
+        state.goal_reached_at = 0;
+        // A goal the vault already covers counts as reached straight away
+        state.record_goal_progress(&clock);
+        state.record_owner_activity(&clock);
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(vault_id: u64)]
+pub struct AddDestination<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        seeds = [b"state", vault_state.creator.as_ref(), VaultState::id_seed(vault_id).as_ref()],
+        bump = vault_state.bump,
+        constraint = vault_state.owner == user.key() @ VaultErrorCode::NotVaultOwner,
+    )]
+    pub vault_state: Account<'info, VaultState>,
+    #[account(
+        init_if_needed,
+        payer = user,
+        seeds = [b"allowlist", vault_state.key().as_ref()],
+        bump,
+        space = 8 + DestinationAllowList::INIT_SPACE
+    )]
+    pub allow_list: Account<'info, DestinationAllowList>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> AddDestination<'info> {
+    pub fn add_destination(&mut self, destination: Pubkey, bumps: &AddDestinationBumps) -> Result<()> {
+        if self.allow_list.destinations.contains(&destination) {
+            return Err(VaultErrorCode::DestinationAlreadyAllowed.into());
+        }
+        if self.allow_list.destinations.len() >= DestinationAllowList::MAX_DESTINATIONS {
+            return Err(VaultErrorCode::AllowListFull.into());
+        }
+        self.allow_list.bump = bumps.allow_list;
+        self.allow_list.vault_state = self.vault_state.key();
+        self.allow_list.destinations.push(destination);
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(vault_id: u64)]
+pub struct RemoveDestination<'info> {
+    pub user: Signer<'info>,
+    #[account(
+        seeds = [b"state", vault_state.creator.as_ref(), VaultState::id_seed(vault_id).as_ref()],
+        bump = vault_state.bump,
+        constraint = vault_state.owner == user.key() @ VaultErrorCode::NotVaultOwner,
+    )]
+    pub vault_state: Account<'info, VaultState>,
+    #[account(mut, seeds = [b"allowlist", vault_state.key().as_ref()], bump = allow_list.bump)]
+    pub allow_list: Account<'info, DestinationAllowList>,
+}
+
+impl<'info> RemoveDestination<'info> {
+    pub fn remove_destination(&mut self, destination: Pubkey) -> Result<()> {
+        let index = self.allow_list.destinations
+            .iter()
+            .position(|allowed| *allowed == destination)
+            .ok_or(VaultErrorCode::DestinationNotFound)?;
+        self.allow_list.destinations.swap_remove(index);
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(vault_id: u64)]
+pub struct CreateMultisig<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"state", vault_state.creator.as_ref(), VaultState::id_seed(vault_id).as_ref()],
+        bump = vault_state.bump,
+        constraint = vault_state.owner == user.key() @ VaultErrorCode::NotVaultOwner,
+    )]
+    pub vault_state: Account<'info, VaultState>,
+    #[account(
+        init,
+        payer = user,
+        seeds = [b"multisig", vault_state.key().as_ref()],
+        bump,
+        space = 8 + VaultMultisig::INIT_SPACE
+    )]
+    pub multisig: Account<'info, VaultMultisig>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> CreateMultisig<'info> {
+    pub fn create_multisig(
+        &mut self,
+        owners: Vec<Pubkey>,
+        threshold: u8,
+        bumps: &CreateMultisigBumps
+    ) -> Result<()> {
+        if owners.is_empty() || owners.len() > VaultMultisig::MAX_OWNERS {
+            return Err(VaultErrorCode::InvalidOwners.into());
+        }
+        if owners.iter().enumerate().any(|(i, owner)| owners[..i].contains(owner)) {
+            return Err(VaultErrorCode::InvalidOwners.into());
+        }
+        if threshold == 0 || (threshold as usize) > owners.len() {
+            return Err(VaultErrorCode::InvalidThreshold.into());
+        }
+        self.multisig.set_inner(VaultMultisig {
+            bump: bumps.multisig,
+            vault_state: self.vault_state.key(),
+            creator: self.vault_state.creator,
+            owners,
+            threshold,
+            proposal_count: 0,
+            open_proposals: 0,
+        });
+        // From here on only approved proposals can move funds out
+        self.vault_state.multisig = self.multisig.key();
+        self.vault_state.record_owner_activity(&Clock::get()?);
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct ProposeWithdrawal<'info> {
+    #[account(mut)]
+    pub proposer: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"multisig", multisig.vault_state.as_ref()],
+        bump = multisig.bump,
+    )]
+    pub multisig: Account<'info, VaultMultisig>,
+    #[account(
+        init,
+        payer = proposer,
+        seeds = [b"proposal", multisig.key().as_ref(), multisig.proposal_count.to_le_bytes().as_ref()],
+        bump,
+        space = 8 + Proposal::INIT_SPACE
+    )]
+    pub proposal: Account<'info, Proposal>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> ProposeWithdrawal<'info> {
+    pub fn propose_withdrawal(
+        &mut self,
+        action: ProposalAction,
+        destination: Pubkey,
+        bumps: &ProposeWithdrawalBumps
+    ) -> Result<()> {
+        if !self.multisig.owners.contains(&self.proposer.key()) {
+            return Err(VaultErrorCode::NotMultisigOwner.into());
+        }
+        if let ProposalAction::Withdraw { amount: 0 } = action {
+            return Err(VaultErrorCode::InsufficientWithdrawalAmount.into());
+        }
+        let index = self.multisig.proposal_count;
+        self.multisig.proposal_count = index
+            .checked_add(1)
+            .ok_or(VaultErrorCode::ArithmeticOverflow)?;
+        self.multisig.open_proposals = self.multisig.open_proposals
+            .checked_add(1)
+            .ok_or(VaultErrorCode::ArithmeticOverflow)?;
+        // Proposing counts as the proposer's approval
+        self.proposal.set_inner(Proposal {
+            bump: bumps.proposal,
+            multisig: self.multisig.key(),
+            index,
+            proposer: self.proposer.key(),
+            action,
+            destination,
+            approvals: vec![self.proposer.key()],
+        });
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct Approve<'info> {
+    pub owner: Signer<'info>,
+    #[account(seeds = [b"multisig", multisig.vault_state.as_ref()], bump = multisig.bump)]
+    pub multisig: Account<'info, VaultMultisig>,
+    #[account(
+        mut,
+        has_one = multisig,
+        seeds = [b"proposal", multisig.key().as_ref(), proposal.index.to_le_bytes().as_ref()],
+        bump = proposal.bump,
+    )]
+    pub proposal: Account<'info, Proposal>,
+}
+
+impl<'info> Approve<'info> {
+    pub fn approve(&mut self) -> Result<()> {
+        let owner = self.owner.key();
+        if !self.multisig.owners.contains(&owner) {
+            return Err(VaultErrorCode::NotMultisigOwner.into());
+        }
+        if self.proposal.approvals.contains(&owner) {
+            return Err(VaultErrorCode::AlreadyApproved.into());
+        }
+        self.proposal.approvals.push(owner);
+        Ok(())
+    }
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct Execute<'info> {
+    pub executor: Signer<'info>,
+    #[account(
+        mut,
+        has_one = vault_state,
+        seeds = [b"multisig", vault_state.key().as_ref()],
+        bump = multisig.bump,
+    )]
+    pub multisig: Account<'info, VaultMultisig>,
+    #[account(
+        mut,
+        has_one = multisig,
+        has_one = proposer,
+        has_one = destination,
+        seeds = [b"proposal", multisig.key().as_ref(), proposal.index.to_le_bytes().as_ref()],
+        bump = proposal.bump,
+        close = proposer,
+    )]
+    pub proposal: Account<'info, Proposal>,
+    /// Gets the proposal rent back
+    #[account(mut)]
+    pub proposer: SystemAccount<'info>,
+    #[account(mut)]
+    pub vault_state: Account<'info, VaultState>,
+    #[account(
+        mut,
+        seeds = [b"vault", vault_state.key().as_ref()],
+        bump = vault_state.vault_bump,
+    )]
+    pub vault: SystemAccount<'info>,
+    #[account(mut)]
+    pub destination: SystemAccount<'info>,
+    /// Only touched when the proposal closes the vault
+    #[account(mut, seeds = [b"registry", multisig.creator.as_ref()], bump = registry.bump)]
+    pub registry: Account<'info, VaultRegistry>,
+    /// CHECK: closed along with the vault if it exists; see `Close::allow_list`
+    #[account(mut, seeds = [b"allowlist", vault_state.key().as_ref()], bump)]
+    pub allow_list: UncheckedAccount<'info>,
+    /// CHECK: read with `ProgramConfig::load`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+    /// Receives protocol fees
+    #[account(mut, seeds = [b"treasury"], bump)]
+    pub treasury: SystemAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+/// What an executed proposal did, mapped onto the regular vault events
+pub enum ExecutedProposal {
+    Withdrawn(Withdrawn),
+    Closed(VaultClosed),
+}
+
+impl<'info> Execute<'info> {
+    pub fn execute(&mut self) -> Result<ExecutedProposal> {
+        if !self.multisig.owners.contains(&self.executor.key()) {
+            return Err(VaultErrorCode::NotMultisigOwner.into());
+        }
+        // Approvals only count while the approver is still an owner
+        let approvals = self.proposal.approvals
+            .iter()
+            .filter(|owner| self.multisig.owners.contains(owner))
+            .count();
+        if approvals < (self.multisig.threshold as usize) {
+            return Err(VaultErrorCode::NotEnoughApprovals.into());
+        }
+        let clock = Clock::get()?;
+        if self.vault_state.is_locked(&clock) {
+            return Err(VaultErrorCode::VaultLocked.into());
+        }
+        if self.vault_state.is_goal_locked(&clock) {
+            return Err(VaultErrorCode::GoalNotReached.into());
+        }
+        self.multisig.open_proposals = self.multisig.open_proposals
+            .checked_sub(1)
+            .ok_or(VaultErrorCode::ArithmeticOverflow)?;
+        let config = ProgramConfig::load(&self.config)?;
+
+        match self.proposal.action {
+            // Closing stays possible while paused, like `close`
+            ProposalAction::Withdraw { amount } => {
+                config.check_not_paused()?;
+                if amount > self.vault_state.available_balance(&self.vault)? {
+                    return Err(VaultErrorCode::InsufficientVaultBalance.into());
+                }
+                let fee = config.withdraw_fee(amount)?;
+                transfer_from_vault_with_fee(
+                    &self.system_program,
+                    &self.vault,
+                    &self.vault_state,
+                    self.destination.to_account_info(),
+                    &self.treasury,
+                    amount,
+                    fee
+                )?;
+                self.vault_state.record_withdrawal(amount, &clock)?;
+
+                Ok(
+                    ExecutedProposal::Withdrawn(Withdrawn {
+                        user: self.multisig.key(),
+                        vault_state: self.vault_state.key(),
+                        vault: self.vault.key(),
+                        destination: self.destination.key(),
+                        amount,
+                        balance: self.vault.lamports(),
+                        timestamp: clock.unix_timestamp,
+                    })
+                )
+            }
+            ProposalAction::Close => {
+                // Proposals left open would outlive the multisig and could be
+                // executed against one recreated at the same address
+                if self.multisig.open_proposals > 0 {
+                    return Err(VaultErrorCode::ProposalsOutstanding.into());
+                }
+                if self.vault_state.reserved_for_streams > 0 {
+                    return Err(VaultErrorCode::StreamsOutstanding.into());
+                }
+                if self.vault_state.staked_lamports > 0 {
+                    return Err(VaultErrorCode::StakeOutstanding.into());
+                }
+                if self.vault_state.delegation_count > 0 {
+                    return Err(VaultErrorCode::DelegationsOutstanding.into());
+                }
+                if self.vault_state.has_guardians {
+                    return Err(VaultErrorCode::GuardiansOutstanding.into());
+                }
+                let remaining = self.vault.lamports();
+                let fee = config.close_fee_lamports.min(remaining);
+                transfer_from_vault_with_fee(
+                    &self.system_program,
+                    &self.vault,
+                    &self.vault_state,
+                    self.destination.to_account_info(),
+                    &self.treasury,
+                    remaining,
+                    fee
+                )?;
+                self.vault_state.record_withdrawal(remaining, &clock)?;
+                self.registry.open_vaults = self.registry.open_vaults.saturating_sub(1);
+
+                // The state, multisig and allow-list accounts go with the vault
+                self.vault_state.close(self.destination.to_account_info())?;
+                self.multisig.close(self.destination.to_account_info())?;
+                if DestinationAllowList::load(&self.allow_list)?.is_some() {
+                    close_unchecked(&self.allow_list, self.destination.to_account_info())?;
+                }
+
+                Ok(
+                    ExecutedProposal::Closed(VaultClosed {
+                        user: self.multisig.key(),
+                        vault_state: self.vault_state.key(),
+                        vault: self.vault.key(),
+                        destination: self.destination.key(),
+                        amount: remaining,
+                        balance: self.vault.lamports(),
+                        timestamp: clock.unix_timestamp,
+                    })
+                )
+            }
+        }
+    }
+}
+
+#[derive(Accounts)]
+pub struct Cancel<'info> {
+    #[account(mut)]
+    pub proposer: Signer<'info>,
+    #[account(mut, seeds = [b"multisig", multisig.vault_state.as_ref()], bump = multisig.bump)]
+    pub multisig: Account<'info, VaultMultisig>,
+    #[account(
+        mut,
+        has_one = multisig,
+        has_one = proposer,
+        seeds = [b"proposal", multisig.key().as_ref(), proposal.index.to_le_bytes().as_ref()],
+        bump = proposal.bump,
+        close = proposer,
+    )]
+    pub proposal: Account<'info, Proposal>,
+}
+
+impl<'info> Cancel<'info> {
+    pub fn cancel(&mut self) -> Result<()> {
+        self.multisig.open_proposals = self.multisig.open_proposals
+            .checked_sub(1)
+            .ok_or(VaultErrorCode::ArithmeticOverflow)?;
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(vault_id: u64, delegate: Pubkey)]
+pub struct ApproveDelegate<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"state", vault_state.creator.as_ref(), VaultState::id_seed(vault_id).as_ref()],
+        bump = vault_state.bump,
+        constraint = vault_state.owner == user.key() @ VaultErrorCode::NotVaultOwner,
+    )]
+    pub vault_state: Account<'info, VaultState>,
+    #[account(
+        init_if_needed,
+        payer = user,
+        seeds = [b"delegation", vault_state.key().as_ref(), delegate.as_ref()],
+        bump,
+        space = 8 + Delegation::INIT_SPACE
+    )]
+    pub delegation: Account<'info, Delegation>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> ApproveDelegate<'info> {
+    /// Sets the delegate's allowance, replacing whatever was left of a previous one
+    pub fn approve_delegate(
+        &mut self,
+        delegate: Pubkey,
+        allowance: u64,
+        expires_at: i64,
+        bumps: &ApproveDelegateBumps
+    ) -> Result<()> {
+        if expires_at != 0 && expires_at <= Clock::get()?.unix_timestamp {
+            return Err(VaultErrorCode::AllowanceExpired.into());
+        }
+        // Count new delegations so the vault can't be closed and reopened under them
+        if self.delegation.vault_state == Pubkey::default() {
+            self.vault_state.delegation_count = self.vault_state.delegation_count
+                .checked_add(1)
+                .ok_or(VaultErrorCode::ArithmeticOverflow)?;
+        }
+        self.delegation.set_inner(Delegation {
+            bump: bumps.delegation,
+            vault_state: self.vault_state.key(),
+            owner: self.user.key(),
+            delegate,
+            allowance,
+            expires_at,
+        });
+        Ok(())
+    }
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct WithdrawAsDelegate<'info> {
+    pub delegate: Signer<'info>,
+    #[account(
+        mut,
+        has_one = vault_state,
+        has_one = delegate,
+        constraint = delegation.owner == vault_state.owner @ VaultErrorCode::DelegationLapsed,
+        seeds = [b"delegation", vault_state.key().as_ref(), delegate.key().as_ref()],
+        bump = delegation.bump,
+    )]
+    pub delegation: Account<'info, Delegation>,
+    #[account(mut)]
+    pub vault_state: Account<'info, VaultState>,
+    #[account(
+        mut,
+        seeds = [b"vault", vault_state.key().as_ref()],
+        bump = vault_state.vault_bump,
+    )]
+    pub vault: SystemAccount<'info>,
+    /// Paid by the delegate, e.g. the invoice recipient
+    #[account(mut)]
+    pub destination: SystemAccount<'info>,
+    /// CHECK: read with `ProgramConfig::load`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+    /// Receives protocol fees
+    #[account(mut, seeds = [b"treasury"], bump)]
+    pub treasury: SystemAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> WithdrawAsDelegate<'info> {
+    pub fn withdraw_as_delegate(&mut self, amount: u64) -> Result<Withdrawn> {
+        let config = ProgramConfig::load(&self.config)?;
+        config.check_not_paused()?;
+        if self.vault_state.has_multisig() {
+            return Err(VaultErrorCode::MultisigRequired.into());
+        }
+        let clock = Clock::get()?;
+        if self.delegation.is_expired(&clock) {
+            return Err(VaultErrorCode::AllowanceExpired.into());
+        }
+        if amount > self.delegation.allowance {
+            return Err(VaultErrorCode::AllowanceExceeded.into());
+        }
+        if amount == 0 || amount < self.vault_state.min_withdrawal {
+            return Err(VaultErrorCode::InsufficientWithdrawalAmount.into());
+        }
+        if amount > self.vault_state.available_balance(&self.vault)? {
+            return Err(VaultErrorCode::InsufficientVaultBalance.into());
+        }
+        if self.vault_state.is_locked(&clock) {
+            return Err(VaultErrorCode::VaultLocked.into());
+        }
+        if self.vault_state.is_goal_locked(&clock) {
+            return Err(VaultErrorCode::GoalNotReached.into());
+        }
+
+        let fee = config.withdraw_fee(amount)?;
+        transfer_from_vault_with_fee(
+            &self.system_program,
+            &self.vault,
+            &self.vault_state,
+            self.destination.to_account_info(),
+            &self.treasury,
+            amount,
+            fee
+        )?;
+        self.delegation.allowance = self.delegation.allowance
+            .checked_sub(amount)
+            .ok_or(VaultErrorCode::AllowanceExceeded)?;
+        self.vault_state.record_withdrawal(amount, &clock)?;
+
+        Ok(Withdrawn {
+            user: self.delegate.key(),
+            vault_state: self.vault_state.key(),
+            vault: self.vault.key(),
+            destination: self.destination.key(),
+            amount,
+            balance: self.vault.lamports(),
+            timestamp: clock.unix_timestamp,
+        })
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(vault_id: u64, delegate: Pubkey)]
+pub struct RevokeDelegate<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"state", vault_state.creator.as_ref(), VaultState::id_seed(vault_id).as_ref()],
+        bump = vault_state.bump,
+        constraint = vault_state.owner == user.key() @ VaultErrorCode::NotVaultOwner,
+    )]
+    pub vault_state: Account<'info, VaultState>,
+    #[account(
+        mut,
+        seeds = [b"delegation", vault_state.key().as_ref(), delegate.as_ref()],
+        bump = delegation.bump,
+        close = user,
+    )]
+    pub delegation: Account<'info, Delegation>,
+}
+
+impl<'info> RevokeDelegate<'info> {
+    pub fn revoke_delegate(&mut self) -> Result<()> {
+        self.vault_state.delegation_count = self.vault_state.delegation_count
+            .checked_sub(1)
+            .ok_or(VaultErrorCode::ArithmeticOverflow)?;
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(vault_id: u64)]
+pub struct ProposeOwner<'info> {
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"state", vault_state.creator.as_ref(), VaultState::id_seed(vault_id).as_ref()],
+        bump = vault_state.bump,
+        constraint = vault_state.owner == user.key() @ VaultErrorCode::NotVaultOwner,
+    )]
+    pub vault_state: Account<'info, VaultState>,
+}
+
+impl<'info> ProposeOwner<'info> {
+    pub fn propose_owner(&mut self, new_owner: Pubkey) -> Result<()> {
+        if new_owner == Pubkey::default() || new_owner == self.user.key() {
+            return Err(VaultErrorCode::InvalidNewOwner.into());
+        }
+        // Replaces any earlier proposal
+        self.vault_state.pending_owner = new_owner;
+        self.vault_state.record_owner_activity(&Clock::get()?);
+        Ok(())
+    }
+
+    pub fn cancel_owner_transfer(&mut self) -> Result<()> {
+        if self.vault_state.pending_owner == Pubkey::default() {
+            return Err(VaultErrorCode::NoPendingOwner.into());
+        }
+        self.vault_state.pending_owner = Pubkey::default();
+        self.vault_state.record_owner_activity(&Clock::get()?);
+        Ok(())
+    }
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct AcceptOwner<'info> {
+    pub new_owner: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"state", vault_state.creator.as_ref(), VaultState::id_seed(vault_state.vault_id).as_ref()],
+        bump = vault_state.bump,
+        constraint = vault_state.pending_owner == new_owner.key() @ VaultErrorCode::NoPendingOwner,
+    )]
+    pub vault_state: Account<'info, VaultState>,
+}
+
+impl<'info> AcceptOwner<'info> {
+    pub fn accept_owner(&mut self) -> Result<OwnershipTransferred> {
+        let clock = Clock::get()?;
+        let previous_owner = self.vault_state.owner;
+        self.vault_state.owner = self.new_owner.key();
+        self.vault_state.pending_owner = Pubkey::default();
+        self.vault_state.record_owner_activity(&clock);
+
+        Ok(OwnershipTransferred {
+            vault_state: self.vault_state.key(),
+            previous_owner,
+            new_owner: self.new_owner.key(),
+            timestamp: clock.unix_timestamp,
+        })
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(vault_id: u64)]
+pub struct SetBeneficiary<'info> {
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"state", vault_state.creator.as_ref(), VaultState::id_seed(vault_id).as_ref()],
+        bump = vault_state.bump,
+        constraint = vault_state.owner == user.key() @ VaultErrorCode::NotVaultOwner,
+    )]
+    pub vault_state: Account<'info, VaultState>,
+}
+
+impl<'info> SetBeneficiary<'info> {
+    /// Passing the default key removes the beneficiary
+    pub fn set_beneficiary(&mut self, beneficiary: Pubkey, inactivity_period: i64) -> Result<()> {
+        if beneficiary == self.user.key() {
+            return Err(VaultErrorCode::InvalidBeneficiary.into());
+        }
+        if beneficiary != Pubkey::default() && inactivity_period < VaultState::MIN_INACTIVITY_PERIOD {
+            return Err(VaultErrorCode::InactivityPeriodTooShort.into());
+        }
+        self.vault_state.beneficiary = beneficiary;
+        self.vault_state.inactivity_period = if beneficiary == Pubkey::default() { 0 } else { inactivity_period };
+        self.vault_state.record_owner_activity(&Clock::get()?);
+        Ok(())
+    }
+
+    pub fn heartbeat(&mut self) -> Result<()> {
+        self.vault_state.record_owner_activity(&Clock::get()?);
+        Ok(())
+    }
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ClaimInheritance<'info> {
+    pub beneficiary: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"state", vault_state.creator.as_ref(), VaultState::id_seed(vault_state.vault_id).as_ref()],
+        bump = vault_state.bump,
+        constraint = vault_state.beneficiary == beneficiary.key() @ VaultErrorCode::NotBeneficiary,
+    )]
+    pub vault_state: Account<'info, VaultState>,
+}
+
+impl<'info> ClaimInheritance<'info> {
+    /// Hands the vault to the beneficiary, who can then withdraw or close it
+    /// like any owner
+    pub fn claim_inheritance(&mut self) -> Result<OwnershipTransferred> {
+        let clock = Clock::get()?;
+        if !self.vault_state.is_inactive(&clock) {
+            return Err(VaultErrorCode::OwnerStillActive.into());
+        }
+
+        let previous_owner = self.vault_state.owner;
+        self.vault_state.owner = self.beneficiary.key();
+        self.vault_state.pending_owner = Pubkey::default();
+        self.vault_state.beneficiary = Pubkey::default();
+        self.vault_state.inactivity_period = 0;
+        self.vault_state.record_owner_activity(&clock);
+
+        Ok(OwnershipTransferred {
+            vault_state: self.vault_state.key(),
+            previous_owner,
+            new_owner: self.beneficiary.key(),
+            timestamp: clock.unix_timestamp,
+        })
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(vault_id: u64)]
+pub struct SetGuardians<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"state", vault_state.creator.as_ref(), VaultState::id_seed(vault_id).as_ref()],
+        bump = vault_state.bump,
+        constraint = vault_state.owner == user.key() @ VaultErrorCode::NotVaultOwner,
+    )]
+    pub vault_state: Account<'info, VaultState>,
+    #[account(
+        init_if_needed,
+        payer = user,
+        seeds = [b"guardians", vault_state.key().as_ref()],
+        bump,
+        space = 8 + GuardianSet::INIT_SPACE
+    )]
+    pub guardian_set: Account<'info, GuardianSet>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> SetGuardians<'info> {
+    /// Replaces the guardian set. An open recovery request keeps its approvals,
+    /// but only current guardians count towards the threshold when completing it.
+    pub fn set_guardians(
+        &mut self,
+        guardians: Vec<Pubkey>,
+        threshold: u8,
+        recovery_delay: i64,
+        bumps: &SetGuardiansBumps
+    ) -> Result<()> {
+        if guardians.is_empty() || guardians.len() > GuardianSet::MAX_GUARDIANS {
+            return Err(VaultErrorCode::InvalidGuardians.into());
+        }
+        if guardians.iter().enumerate().any(|(i, guardian)| guardians[..i].contains(guardian)) {
+            return Err(VaultErrorCode::InvalidGuardians.into());
+        }
+        // The owner can't be one of their own guardians
+        if guardians.contains(&self.user.key()) {
+            return Err(VaultErrorCode::InvalidGuardians.into());
+        }
+        if threshold == 0 || (threshold as usize) > guardians.len() {
+            return Err(VaultErrorCode::InvalidThreshold.into());
+        }
+        if recovery_delay < GuardianSet::MIN_RECOVERY_DELAY {
+            return Err(VaultErrorCode::RecoveryDelayTooShort.into());
+        }
+        self.guardian_set.set_inner(GuardianSet {
+            bump: bumps.guardian_set,
+            vault_state: self.vault_state.key(),
+            guardians,
+            threshold,
+            recovery_delay,
+        });
+        self.vault_state.has_guardians = true;
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(vault_id: u64)]
+pub struct RemoveGuardians<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"state", vault_state.creator.as_ref(), VaultState::id_seed(vault_id).as_ref()],
+        bump = vault_state.bump,
+        constraint = vault_state.owner == user.key() @ VaultErrorCode::NotVaultOwner,
+    )]
+    pub vault_state: Account<'info, VaultState>,
+    #[account(
+        mut,
+        has_one = vault_state,
+        seeds = [b"guardians", vault_state.key().as_ref()],
+        bump = guardian_set.bump,
+        close = user,
+    )]
+    pub guardian_set: Account<'info, GuardianSet>,
+    /// CHECK: Only checked to be empty; an open request has to be vetoed first
+    #[account(seeds = [b"recovery", vault_state.key().as_ref()], bump)]
+    pub recovery_request: UncheckedAccount<'info>,
+}
+
+impl<'info> RemoveGuardians<'info> {
+    pub fn remove_guardians(&mut self) -> Result<()> {
+        if !self.recovery_request.data_is_empty() {
+            return Err(VaultErrorCode::RecoveryPending.into());
+        }
+        self.vault_state.has_guardians = false;
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct InitiateRecovery<'info> {
+    #[account(mut)]
+    pub guardian: Signer<'info>,
+    #[account(has_one = vault_state, seeds = [b"guardians", vault_state.key().as_ref()], bump = guardian_set.bump)]
+    pub guardian_set: Account<'info, GuardianSet>,
+    pub vault_state: Account<'info, VaultState>,
+    #[account(
+        init,
+        payer = guardian,
+        seeds = [b"recovery", vault_state.key().as_ref()],
+        bump,
+        space = 8 + RecoveryRequest::INIT_SPACE
+    )]
+    pub recovery_request: Account<'info, RecoveryRequest>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> InitiateRecovery<'info> {
+    pub fn initiate_recovery(&mut self, new_owner: Pubkey, bumps: &InitiateRecoveryBumps) -> Result<()> {
+        if !self.guardian_set.guardians.contains(&self.guardian.key()) {
+            return Err(VaultErrorCode::NotGuardian.into());
+        }
+        // Opening the request counts as the guardian's approval
+        self.recovery_request.set_inner(RecoveryRequest {
+            bump: bumps.recovery_request,
+            vault_state: self.vault_state.key(),
+            new_owner,
+            proposer: self.guardian.key(),
+            approvals: vec![self.guardian.key()],
+            created_at: Clock::get()?.unix_timestamp,
+        });
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct ApproveRecovery<'info> {
+    pub guardian: Signer<'info>,
+    #[account(
+        seeds = [b"guardians", recovery_request.vault_state.as_ref()],
+        bump = guardian_set.bump,
+    )]
+    pub guardian_set: Account<'info, GuardianSet>,
+    #[account(
+        mut,
+        seeds = [b"recovery", recovery_request.vault_state.as_ref()],
+        bump = recovery_request.bump,
+    )]
+    pub recovery_request: Account<'info, RecoveryRequest>,
+}
+
+impl<'info> ApproveRecovery<'info> {
+    pub fn approve_recovery(&mut self) -> Result<()> {
+        let guardian = self.guardian.key();
+        if !self.guardian_set.guardians.contains(&guardian) {
+            return Err(VaultErrorCode::NotGuardian.into());
+        }
+        if self.recovery_request.approvals.contains(&guardian) {
+            return Err(VaultErrorCode::AlreadyApproved.into());
+        }
+        self.recovery_request.approvals.push(guardian);
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(vault_id: u64)]
+pub struct VetoRecovery<'info> {
+    pub user: Signer<'info>,
+    #[account(
+        seeds = [b"state", vault_state.creator.as_ref(), VaultState::id_seed(vault_id).as_ref()],
+        bump = vault_state.bump,
+        constraint = vault_state.owner == user.key() @ VaultErrorCode::NotVaultOwner,
+    )]
+    pub vault_state: Account<'info, VaultState>,
+    #[account(
+        mut,
+        has_one = proposer,
+        seeds = [b"recovery", vault_state.key().as_ref()],
+        bump = recovery_request.bump,
+        close = proposer,
+    )]
+    pub recovery_request: Account<'info, RecoveryRequest>,
+    /// Guardian that opened the request, gets its rent back
+    #[account(mut)]
+    pub proposer: SystemAccount<'info>,
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct CompleteRecovery<'info> {
+    /// The recovered key has to sign to show it is usable
+    pub new_owner: Signer<'info>,
+    #[account(has_one = vault_state, seeds = [b"guardians", vault_state.key().as_ref()], bump = guardian_set.bump)]
+    pub guardian_set: Account<'info, GuardianSet>,
+    #[account(mut)]
+    pub vault_state: Account<'info, VaultState>,
+    #[account(
+        mut,
+        has_one = new_owner,
+        has_one = proposer,
+        seeds = [b"recovery", vault_state.key().as_ref()],
+        bump = recovery_request.bump,
+        close = proposer,
+    )]
+    pub recovery_request: Account<'info, RecoveryRequest>,
+    #[account(mut)]
+    pub proposer: SystemAccount<'info>,
+}
+
+impl<'info> CompleteRecovery<'info> {
+    pub fn complete_recovery(&mut self) -> Result<OwnershipTransferred> {
+        let approvals = self.recovery_request.approvals
+            .iter()
+            .filter(|guardian| self.guardian_set.guardians.contains(guardian))
+            .count();
+        if approvals < (self.guardian_set.threshold as usize) {
+            return Err(VaultErrorCode::NotEnoughApprovals.into());
+        }
+        let clock = Clock::get()?;
+        let ready_at = self.recovery_request.created_at.saturating_add(self.guardian_set.recovery_delay);
+        if clock.unix_timestamp < ready_at {
+            return Err(VaultErrorCode::RecoveryDelayNotElapsed.into());
+        }
+
+        let previous_owner = self.vault_state.owner;
+        self.vault_state.owner = self.new_owner.key();
+        // A transfer proposed with the lost key must not survive the recovery
+        self.vault_state.pending_owner = Pubkey::default();
+        self.vault_state.record_owner_activity(&clock);
+
+        Ok(OwnershipTransferred {
+            vault_state: self.vault_state.key(),
+            previous_owner,
+            new_owner: self.new_owner.key(),
+            timestamp: clock.unix_timestamp,
+        })
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(vault_id: u64)]
+pub struct CreateStream<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"state", vault_state.creator.as_ref(), VaultState::id_seed(vault_id).as_ref()],
+        bump = vault_state.bump,
+        constraint = vault_state.owner == user.key() @ VaultErrorCode::NotVaultOwner,
+    )]
+    pub vault_state: Account<'info, VaultState>,
+    #[account(seeds = [b"vault", vault_state.key().as_ref()], bump = vault_state.vault_bump)]
+    pub vault: SystemAccount<'info>,
+    #[account(
+        init,
+        payer = user,
+        seeds = [b"stream", vault_state.key().as_ref(), vault_state.stream_count.to_le_bytes().as_ref()],
+        bump,
+        space = 8 + Stream::INIT_SPACE
+    )]
+    pub stream: Account<'info, Stream>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> CreateStream<'info> {
+    /// Reserves `total` lamports of the vault for `recipient`, vesting linearly
+    /// from `start` to `end` with nothing claimable before `cliff`
+    pub fn create_stream(
+        &mut self,
+        recipient: Pubkey,
+        total: u64,
+        start: i64,
+        cliff: i64,
+        end: i64,
+        bumps: &CreateStreamBumps
+    ) -> Result<()> {
+        if self.vault_state.has_multisig() {
+            return Err(VaultErrorCode::MultisigRequired.into());
+        }
+        let clock = Clock::get()?;
+        if self.vault_state.is_locked(&clock) {
+            return Err(VaultErrorCode::VaultLocked.into());
+        }
+        if self.vault_state.is_goal_locked(&clock) {
+            return Err(VaultErrorCode::GoalNotReached.into());
+        }
+        if total == 0 || start >= end || cliff < start || cliff > end {
+            return Err(VaultErrorCode::InvalidStreamSchedule.into());
+        }
+        if total > self.vault_state.available_balance(&self.vault)? {
+            return Err(VaultErrorCode::InsufficientVaultBalance.into());
+        }
+
+        let stream_id = self.vault_state.stream_count;
+        self.stream.set_inner(Stream {
+            bump: bumps.stream,
+            vault_state: self.vault_state.key(),
+            recipient,
+            stream_id,
+            total,
+            start,
+            cliff,
+            end,
+            withdrawn: 0,
+            payer: self.user.key(),
+        });
+        self.vault_state.stream_count = stream_id
+            .checked_add(1)
+            .ok_or(VaultErrorCode::ArithmeticOverflow)?;
+        self.vault_state.reserved_for_streams = self.vault_state.reserved_for_streams
+            .checked_add(total)
+            .ok_or(VaultErrorCode::ArithmeticOverflow)?;
+        self.vault_state.record_owner_activity(&clock);
+        Ok(())
+    }
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct ClaimStream<'info> {
+    #[account(mut)]
+    pub recipient: Signer<'info>,
+    #[account(
+        mut,
+        has_one = vault_state,
+        has_one = recipient,
+        has_one = payer,
+        seeds = [b"stream", vault_state.key().as_ref(), stream.stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump,
+    )]
+    pub stream: Account<'info, Stream>,
+    #[account(mut)]
+    pub vault_state: Account<'info, VaultState>,
+    #[account(
+        mut,
+        seeds = [b"vault", vault_state.key().as_ref()],
+        bump = vault_state.vault_bump,
+    )]
+    pub vault: SystemAccount<'info>,
+    /// Paid for the stream account, gets its rent back after the final claim
+    #[account(mut)]
+    pub payer: SystemAccount<'info>,
+    /// CHECK: read with `ProgramConfig::load`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+    /// Receives protocol fees
+    #[account(mut, seeds = [b"treasury"], bump)]
+    pub treasury: SystemAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> ClaimStream<'info> {
+    pub fn claim_stream(&mut self) -> Result<Withdrawn> {
+        let config = ProgramConfig::load(&self.config)?;
+        config.check_not_paused()?;
+        let clock = Clock::get()?;
+        let amount = self.stream.claimable(&clock);
+        if amount == 0 {
+            return Err(VaultErrorCode::NothingToClaim.into());
+        }
+        let event = pay_stream(
+            &self.system_program,
+            &self.vault,
+            &mut self.vault_state,
+            &mut self.stream,
+            self.recipient.to_account_info(),
+            &self.treasury,
+            amount,
+            config.withdraw_fee(amount)?,
+            &clock
+        )?;
+        // A fully claimed stream is done; closing it keeps its address free for
+        // a vault reopened at the same address
+        if self.stream.withdrawn == self.stream.total {
+            self.stream.close(self.payer.to_account_info())?;
+        }
+        Ok(event)
+    }
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+#[instruction(vault_id: u64)]
+pub struct CancelStream<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"state", vault_state.creator.as_ref(), VaultState::id_seed(vault_id).as_ref()],
+        bump = vault_state.bump,
+        constraint = vault_state.owner == user.key() @ VaultErrorCode::NotVaultOwner,
+    )]
+    pub vault_state: Account<'info, VaultState>,
+    #[account(
+        mut,
+        seeds = [b"vault", vault_state.key().as_ref()],
+        bump = vault_state.vault_bump,
+    )]
+    pub vault: SystemAccount<'info>,
+    #[account(
+        mut,
+        has_one = vault_state,
+        has_one = recipient,
+        has_one = payer,
+        seeds = [b"stream", vault_state.key().as_ref(), stream.stream_id.to_le_bytes().as_ref()],
+        bump = stream.bump,
+        close = payer,
+    )]
+    pub stream: Account<'info, Stream>,
+    /// Still gets whatever had vested before the cancel
+    #[account(mut)]
+    pub recipient: SystemAccount<'info>,
+    /// Paid for the stream account, gets its rent back
+    #[account(mut)]
+    pub payer: SystemAccount<'info>,
+    /// CHECK: read with `ProgramConfig::load`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+    /// Receives protocol fees
+    #[account(mut, seeds = [b"treasury"], bump)]
+    pub treasury: SystemAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> CancelStream<'info> {
+    /// Pays out what has vested so far and hands the unvested rest back to the vault
+    pub fn cancel_stream(&mut self) -> Result<Option<Withdrawn>> {
+        let clock = Clock::get()?;
+        let amount = self.stream.claimable(&clock);
+        let event = if amount > 0 {
+            Some(
+                pay_stream(
+                    &self.system_program,
+                    &self.vault,
+                    &mut self.vault_state,
+                    &mut self.stream,
+                    self.recipient.to_account_info(),
+                    &self.treasury,
+                    amount,
+                    ProgramConfig::load(&self.config)?.withdraw_fee(amount)?,
+                    &clock
+                )?
+            )
+        } else {
+            None
+        };
+
+        let unvested = self.stream.total
+            .checked_sub(self.stream.withdrawn)
+            .ok_or(VaultErrorCode::ArithmeticOverflow)?;
+        self.vault_state.reserved_for_streams = self.vault_state.reserved_for_streams.saturating_sub(unvested);
+        self.vault_state.record_owner_activity(&clock);
+        Ok(event)
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(vault_id: u64)]
+pub struct StakeVault<'info> {
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"state", vault_state.creator.as_ref(), VaultState::id_seed(vault_id).as_ref()],
+        bump = vault_state.bump,
+        constraint = vault_state.owner == user.key() @ VaultErrorCode::NotVaultOwner,
+    )]
+    pub vault_state: Account<'info, VaultState>,
+    #[account(mut, seeds = [b"vault", vault_state.key().as_ref()], bump = vault_state.vault_bump)]
+    pub vault: SystemAccount<'info>,
+    /// Stake account created and funded by the vault, with the vault as staker and withdrawer
+    #[account(mut, seeds = [b"stake", vault_state.key().as_ref()], bump)]
+    pub stake_account: SystemAccount<'info>,
+    /// CHECK: validated by the stake program when delegating
+    pub vote_account: UncheckedAccount<'info>,
+    pub rent: Sysvar<'info, Rent>,
+    pub clock: Sysvar<'info, Clock>,
+    /// CHECK: stake history sysvar
+    #[account(address = sysvar::stake_history::ID)]
+    pub stake_history: UncheckedAccount<'info>,
+    /// CHECK: stake config account, unused but still expected by `DelegateStake`
+    #[account(address = stake::config::ID)]
+    pub stake_config: UncheckedAccount<'info>,
+    /// CHECK: stake program
+    #[account(address = stake::program::ID)]
+    pub stake_program: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> StakeVault<'info> {
+    /// Moves `amount` liquid lamports into a stake account delegated to `vote_account`.
+    /// The amount has to cover the stake account's rent and the minimum delegation.
+    pub fn stake_vault(&mut self, amount: u64, bumps: &StakeVaultBumps) -> Result<()> {
+        if self.vault_state.staked_lamports > 0 {
+            return Err(VaultErrorCode::StakeOutstanding.into());
+        }
+        if amount > self.vault_state.available_balance(&self.vault)? {
+            return Err(VaultErrorCode::InsufficientVaultBalance.into());
+        }
+
+        let vault_state_key = self.vault_state.key();
+        let vault_seeds = &[b"vault".as_ref(), vault_state_key.as_ref(), &[self.vault_state.vault_bump]];
+        let stake_seeds = &[b"stake".as_ref(), vault_state_key.as_ref(), &[bumps.stake_account]];
+
+        // Funding, allocating and assigning separately instead of `create_account`,
+        // which fails once anyone has sent lamports to the stake address. Lamports
+        // already there are staked along with `amount` and come back on withdrawal.
+        transfer_from_vault(
+            &self.system_program,
+            &self.vault,
+            &self.vault_state,
+            self.stake_account.to_account_info(),
+            amount
+        )?;
+        allocate(
+            CpiContext::new_with_signer(
+                self.system_program.to_account_info(),
+                Allocate { account_to_allocate: self.stake_account.to_account_info() },
+                &[&stake_seeds[..]]
+            ),
+            stake::state::StakeStateV2::size_of() as u64
+        )?;
+        assign(
+            CpiContext::new_with_signer(
+                self.system_program.to_account_info(),
+                Assign { account_to_assign: self.stake_account.to_account_info() },
+                &[&stake_seeds[..]]
+            ),
+            &stake::program::ID
+        )?;
+
+        let authorized = stake::state::Authorized::auto(&self.vault.key());
+        invoke(
+            &stake::instruction::initialize(
+                &self.stake_account.key(),
+                &authorized,
+                &stake::state::Lockup::default()
+            ),
+            &[self.stake_account.to_account_info(), self.rent.to_account_info()]
+        )?;
+        invoke_signed(
+            &stake::instruction::delegate_stake(
+                &self.stake_account.key(),
+                &self.vault.key(),
+                &self.vote_account.key()
+            ),
+            &[
+                self.stake_account.to_account_info(),
+                self.vote_account.to_account_info(),
+                self.clock.to_account_info(),
+                self.stake_history.to_account_info(),
+                self.stake_config.to_account_info(),
+                self.vault.to_account_info(),
+            ],
+            &[&vault_seeds[..]]
+        )?;
+
+        self.vault_state.staked_lamports = amount;
+        self.vault_state.record_owner_activity(&self.clock);
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(vault_id: u64)]
+pub struct DeactivateStake<'info> {
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"state", vault_state.creator.as_ref(), VaultState::id_seed(vault_id).as_ref()],
+        bump = vault_state.bump,
+        constraint = vault_state.owner == user.key() @ VaultErrorCode::NotVaultOwner,
+    )]
+    pub vault_state: Account<'info, VaultState>,
+    #[account(seeds = [b"vault", vault_state.key().as_ref()], bump = vault_state.vault_bump)]
+    pub vault: SystemAccount<'info>,
+    /// CHECK: the vault's stake account, checked by its seeds and by the stake program
+    #[account(mut, seeds = [b"stake", vault_state.key().as_ref()], bump)]
+    pub stake_account: UncheckedAccount<'info>,
+    pub clock: Sysvar<'info, Clock>,
+    /// CHECK: stake program
+    #[account(address = stake::program::ID)]
+    pub stake_program: UncheckedAccount<'info>,
+}
+
+impl<'info> DeactivateStake<'info> {
+    /// Starts cooling down the vault's stake; it can be withdrawn once fully inactive
+    pub fn deactivate_stake(&mut self) -> Result<()> {
+        if self.vault_state.staked_lamports == 0 {
+            return Err(VaultErrorCode::NoStake.into());
+        }
+
+        let vault_state_key = self.vault_state.key();
+        let vault_seeds = &[b"vault".as_ref(), vault_state_key.as_ref(), &[self.vault_state.vault_bump]];
+        invoke_signed(
+            &stake::instruction::deactivate_stake(&self.stake_account.key(), &self.vault.key()),
+            &[
+                self.stake_account.to_account_info(),
+                self.clock.to_account_info(),
+                self.vault.to_account_info(),
+            ],
+            &[&vault_seeds[..]]
+        )?;
+
+        self.vault_state.record_owner_activity(&self.clock);
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(vault_id: u64)]
+pub struct WithdrawStake<'info> {
+    pub user: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"state", vault_state.creator.as_ref(), VaultState::id_seed(vault_id).as_ref()],
+        bump = vault_state.bump,
+        constraint = vault_state.owner == user.key() @ VaultErrorCode::NotVaultOwner,
+    )]
+    pub vault_state: Account<'info, VaultState>,
+    #[account(mut, seeds = [b"vault", vault_state.key().as_ref()], bump = vault_state.vault_bump)]
+    pub vault: SystemAccount<'info>,
+    /// CHECK: the vault's stake account, checked by its seeds and by the stake program
+    #[account(mut, seeds = [b"stake", vault_state.key().as_ref()], bump)]
+    pub stake_account: UncheckedAccount<'info>,
+    pub clock: Sysvar<'info, Clock>,
+    /// CHECK: stake history sysvar
+    #[account(address = sysvar::stake_history::ID)]
+    pub stake_history: UncheckedAccount<'info>,
+    /// CHECK: stake program
+    #[account(address = stake::program::ID)]
+    pub stake_program: UncheckedAccount<'info>,
+}
+
+impl<'info> WithdrawStake<'info> {
+    /// Returns the whole stake account, rewards included, to the vault's liquid balance.
+    /// The stake program rejects this until the stake has fully deactivated.
+    pub fn withdraw_stake(&mut self) -> Result<()> {
+        if self.vault_state.staked_lamports == 0 {
+            return Err(VaultErrorCode::NoStake.into());
+        }
+
+        let vault_state_key = self.vault_state.key();
+        let vault_seeds = &[b"vault".as_ref(), vault_state_key.as_ref(), &[self.vault_state.vault_bump]];
+        invoke_signed(
+            &stake::instruction::withdraw(
+                &self.stake_account.key(),
+                &self.vault.key(),
+                &self.vault.key(),
+                self.stake_account.lamports(),
+                None
+            ),
+            &[
+                self.stake_account.to_account_info(),
+                self.vault.to_account_info(),
+                self.clock.to_account_info(),
+                self.stake_history.to_account_info(),
+                self.vault.to_account_info(),
+            ],
+            &[&vault_seeds[..]]
+        )?;
+
+        self.vault_state.staked_lamports = 0;
+        self.vault_state.record_owner_activity(&self.clock);
+        Ok(())
+    }
+}
+
+/// Pays a stream's recipient out of the lamports reserved for it, less the withdrawal fee
+#[allow(clippy::too_many_arguments)]
+pub fn pay_stream<'info>(
+    system_program: &Program<'info, System>,
+    vault: &SystemAccount<'info>,
+    vault_state: &mut Account<'info, VaultState>,
+    stream: &mut Account<'info, Stream>,
+    recipient: AccountInfo<'info>,
+    treasury: &SystemAccount<'info>,
+    amount: u64,
+    fee: u64,
+    clock: &Clock
+) -> Result<Withdrawn> {
+    transfer_from_vault_with_fee(system_program, vault, vault_state, recipient.clone(), treasury, amount, fee)?;
+    stream.withdrawn = stream.withdrawn
+        .checked_add(amount)
+        .ok_or(VaultErrorCode::ArithmeticOverflow)?;
+    vault_state.reserved_for_streams = vault_state.reserved_for_streams.saturating_sub(amount);
+    vault_state.record_withdrawal(amount, clock)?;
+
+    Ok(Withdrawn {
+        user: recipient.key(),
+        vault_state: vault_state.key(),
+        vault: vault.key(),
+        destination: recipient.key(),
+        amount,
+        balance: vault.lamports(),
+        timestamp: clock.unix_timestamp,
+    })
+}
+
+/// Records a deposit's progress toward the vault's goal, returning the event
+/// for the deposit that reaches it
+pub fn goal_reached_event(vault_state: &mut Account<VaultState>, clock: &Clock) -> Option<GoalReached> {
+    if !vault_state.record_goal_progress(clock) {
+        return None;
+    }
+    Some(GoalReached {
+        vault_state: vault_state.key(),
+        owner: vault_state.owner,
+        goal_amount: vault_state.goal_amount,
+        saved: vault_state.saved(),
+        timestamp: clock.unix_timestamp,
+    })
+}
+
+/// Pays a protocol fee from a signer to the treasury
+pub fn pay_fee<'info>(
+    system_program: &Program<'info, System>,
+    from: AccountInfo<'info>,
+    treasury: &SystemAccount<'info>,
+    fee: u64
+) -> Result<()> {
+    if fee == 0 {
+        return Ok(());
+    }
+    let cpi_accounts = Transfer {
+        from,
+        to: treasury.to_account_info(),
+    };
+    let cpi_ctx = CpiContext::new(system_program.to_account_info(), cpi_accounts);
+    transfer(cpi_ctx, fee)
+}
+
+/// Moves lamports out of a vault, signing with its `[b"vault", vault_state]` seeds
+pub fn transfer_from_vault<'info>(
+    system_program: &Program<'info, System>,
+    vault: &SystemAccount<'info>,
+    vault_state: &Account<'info, VaultState>,
+    to: AccountInfo<'info>,
+    amount: u64
+) -> Result<()> {
+    let cpi_program = system_program.to_account_info();
+
+    let cpi_accounts = Transfer {
+        from: vault.to_account_info(),
+        to,
+    };
+
+    let vault_state_key = vault_state.key();
+    let seeds = &[b"vault".as_ref(), vault_state_key.as_ref(), &[vault_state.vault_bump]];
+    let seeds_signer = &[&seeds[..]];
+
+    let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, seeds_signer);
+    transfer(cpi_ctx, amount)
+}
+
+/// Pays `amount` out of a vault, of which `fee` goes to the treasury and the rest to `to`
+pub fn transfer_from_vault_with_fee<'info>(
+    system_program: &Program<'info, System>,
+    vault: &SystemAccount<'info>,
+    vault_state: &Account<'info, VaultState>,
+    to: AccountInfo<'info>,
+    treasury: &SystemAccount<'info>,
+    amount: u64,
+    fee: u64
+) -> Result<()> {
+    let net = amount.checked_sub(fee).ok_or(VaultErrorCode::ArithmeticOverflow)?;
+    transfer_from_vault(system_program, vault, vault_state, to, net)?;
+    if fee > 0 {
+        transfer_from_vault(system_program, vault, vault_state, treasury.to_account_info(), fee)?;
+    }
+    Ok(())
+}
+
+/// Closes a program account held as an `UncheckedAccount`, refunding its rent to `to`
+pub fn close_unchecked<'info>(account: &UncheckedAccount<'info>, to: AccountInfo<'info>) -> Result<()> {
+    let lamports = to.lamports()
+        .checked_add(account.lamports())
+        .ok_or(VaultErrorCode::ArithmeticOverflow)?;
+    **to.try_borrow_mut_lamports()? = lamports;
+    **account.try_borrow_mut_lamports()? = 0;
+    account.assign(&System::id());
+    account.resize(0)?;
+    Ok(())
+}
+
+/// Picks the account withdrawn funds are paid to: `user` unless another
+/// destination is given, in which case it must be on the vault's allow-list.
+pub fn resolve_destination<'info>(
+    user: &Signer<'info>,
+    destination: &Option<SystemAccount<'info>>,
+    allow_list: Option<&DestinationAllowList>
+) -> Result<AccountInfo<'info>> {
+    match destination {
+        Some(destination) if destination.key() != user.key() => {
+            let allowed = allow_list.is_some_and(|allow_list| allow_list.destinations.contains(&destination.key()));
+            if !allowed {
+                return Err(VaultErrorCode::DestinationNotAllowed.into());
+            }
+            Ok(destination.to_account_info())
+        }
+        _ => Ok(user.to_account_info()),
+    }
+}
+
+#[derive(Accounts)]
+pub struct InitConfig<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(
+        init,
+        payer = authority,
+        seeds = [b"config"],
+        bump,
+        space = 8 + ProgramConfig::INIT_SPACE
+    )]
+    pub config: Account<'info, ProgramConfig>,
+    #[account(constraint = program.programdata_address()? == Some(program_data.key()))]
+    pub program: Program<'info, crate::program::AnchorVaultQ3>,
+    /// Only the upgrade authority can create the config
+    #[account(
+        constraint = program_data.upgrade_authority_address == Some(authority.key()) @ VaultErrorCode::NotUpgradeAuthority,
+    )]
+    pub program_data: Account<'info, ProgramData>,
+    #[account(mut, seeds = [b"treasury"], bump)]
+    pub treasury: SystemAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> InitConfig<'info> {
+    pub fn init_config(&mut self, admin: Pubkey, bumps: &InitConfigBumps) -> Result<()> {
+        // Fund the treasury up front so fees smaller than its rent reserve can be paid in
+        let rent_due = Rent::get()?
+            .minimum_balance(0)
+            .saturating_sub(self.treasury.lamports());
+        pay_fee(&self.system_program, self.authority.to_account_info(), &self.treasury, rent_due)?;
+
+        self.config.set_inner(ProgramConfig {
+            bump: bumps.config,
+            version: ProgramConfig::CURRENT_VERSION,
+            admin,
+            paused: false,
+            deposit_fee_bps: 0,
+            withdraw_fee_bps: 0,
+            close_fee_lamports: 0,
+        });
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct UpdateConfig<'info> {
+    pub admin: Signer<'info>,
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ VaultErrorCode::NotConfigAdmin,
+    )]
+    pub config: Account<'info, ProgramConfig>,
+}
+
+impl<'info> UpdateConfig<'info> {
+    pub fn set_paused(&mut self, paused: bool) -> Result<()> {
+        self.config.paused = paused;
+        Ok(())
+    }
+
+    pub fn set_fees(
+        &mut self,
+        deposit_fee_bps: u16,
+        withdraw_fee_bps: u16,
+        close_fee_lamports: u64
+    ) -> Result<()> {
+        if deposit_fee_bps > ProgramConfig::MAX_FEE_BPS || withdraw_fee_bps > ProgramConfig::MAX_FEE_BPS {
+            return Err(VaultErrorCode::FeeTooHigh.into());
+        }
+        self.config.deposit_fee_bps = deposit_fee_bps;
+        self.config.withdraw_fee_bps = withdraw_fee_bps;
+        self.config.close_fee_lamports = close_fee_lamports;
+        Ok(())
+    }
+}
+
+#[event_cpi]
+#[derive(Accounts)]
+pub struct CollectFees<'info> {
+    pub admin: Signer<'info>,
+    #[account(seeds = [b"config"], bump = config.bump, has_one = admin @ VaultErrorCode::NotConfigAdmin)]
+    pub config: Account<'info, ProgramConfig>,
+    #[account(mut, seeds = [b"treasury"], bump)]
+    pub treasury: SystemAccount<'info>,
+    #[account(mut)]
+    pub destination: SystemAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> CollectFees<'info> {
+    /// Sends everything above the treasury's rent reserve to `destination`
+    pub fn collect_fees(&mut self, bumps: &CollectFeesBumps) -> Result<FeesCollected> {
+        let rent_exempt = Rent::get()?.minimum_balance(0);
+        let amount = self.treasury.lamports().saturating_sub(rent_exempt);
+        if amount == 0 {
+            return Err(VaultErrorCode::NoFeesToCollect.into());
+        }
+
+        let cpi_accounts = Transfer {
+            from: self.treasury.to_account_info(),
+            to: self.destination.to_account_info(),
+        };
+        let seeds = &[b"treasury".as_ref(), &[bumps.treasury]];
+        let seeds_signer = &[&seeds[..]];
+        let cpi_ctx = CpiContext::new_with_signer(
+            self.system_program.to_account_info(),
+            cpi_accounts,
+            seeds_signer
+        );
+        transfer(cpi_ctx, amount)?;
+
+        Ok(FeesCollected {
+            admin: self.admin.key(),
+            destination: self.destination.key(),
+            amount,
+            timestamp: Clock::get()?.unix_timestamp,
+        })
+    }
+}
+
+#[derive(Accounts)]
+#[instruction(vault_id: u64)]
+pub struct MigrateVaultState<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    /// CHECK: older layouts are shorter than `VaultState` and can't be deserialized yet;
+    /// ownership and seeds are checked here, the discriminator in the handler.
+    #[account(
+        mut,
+        owner = crate::ID,
+        seeds = [b"state", user.key().as_ref(), VaultState::id_seed(vault_id).as_ref()],
+        bump,
+    )]
+    pub vault_state: UncheckedAccount<'info>,
+    #[account(
+        init_if_needed,
+        payer = user,
+        seeds = [b"registry", user.key().as_ref()],
+        bump,
+        space = 8 + VaultRegistry::INIT_SPACE
+    )]
+    pub registry: Account<'info, VaultRegistry>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> MigrateVaultState<'info> {
+    pub fn migrate_vault_state(&mut self, bumps: MigrateVaultStateBumps) -> Result<()> {
+        let vault_state = self.vault_state.to_account_info();
+        {
+            let data = vault_state.try_borrow_data()?;
+            if data.len() < VaultState::LEGACY_SPACE || !data.starts_with(VaultState::DISCRIMINATOR) {
+                return Err(ErrorCode::AccountDiscriminatorMismatch.into());
+            }
+            if VaultState::layout_version(&data) >= VaultState::CURRENT_VERSION {
+                return Err(VaultErrorCode::VaultAlreadyMigrated.into());
+            }
+        }
+
+        // Top up rent for the larger layout before growing the account
+        let new_space = 8 + VaultState::INIT_SPACE;
+        let rent_due = Rent::get()?
+            .minimum_balance(new_space)
+            .saturating_sub(vault_state.lamports());
+        if rent_due > 0 {
+            let cpi_accounts = Transfer {
+                from: self.user.to_account_info(),
+                to: vault_state.clone(),
+            };
+            let cpi_ctx = CpiContext::new(self.system_program.to_account_info(), cpi_accounts);
+            transfer(cpi_ctx, rent_due)?;
+        }
+
+        // New fields are appended and zero-initialized, so history before the
+        // migration is simply not counted
+        vault_state.resize(new_space)?;
+        vault_state.try_borrow_mut_data()?[VaultState::VERSION_OFFSET] = VaultState::CURRENT_VERSION;
+
+        // Vaults from before ownership could move are owned by the key they were created with
+        {
+            let mut data = vault_state.try_borrow_mut_data()?;
+            let mut state = VaultState::try_deserialize(&mut &data[..])?;
+            if state.creator == Pubkey::default() {
+                state.creator = self.user.key();
+                state.owner = self.user.key();
+                state.try_serialize(&mut &mut data[..])?;
+            }
+        }
+
+        // Vaults created before registries existed all use the default vault id;
+        // register it
+        if self.registry.vault_count == 0 {
+            self.registry.bump = bumps.registry;
+            self.registry.vault_count = 1;
+            self.registry.open_vaults = 1;
+        }
+        Ok(())
+    }
+}
+
+/// Program-wide settings, a single `[b"config"]` account
+#[account]
+#[derive(InitSpace, Default)]
+pub struct ProgramConfig {
+    pub bump: u8,
+    pub version: u8,
+    /// Key allowed to change the config
+    pub admin: Pubkey,
+    /// Blocks deposits and withdrawals; `close` and `withdraw_all` keep working
+    pub paused: bool,
+    pub deposit_fee_bps: u16,
+    pub withdraw_fee_bps: u16,
+    pub close_fee_lamports: u64,
+}
+
+impl ProgramConfig {
+    pub const CURRENT_VERSION: u8 = 1;
+    /// Highest deposit or withdrawal fee the admin can set, 10%
+    pub const MAX_FEE_BPS: u16 = 1_000;
+    pub const BPS_DENOMINATOR: u128 = 10_000;
+
+    /// Fee on a deposit of `amount`, rounded down
+    pub fn deposit_fee(&self, amount: u64) -> Result<u64> {
+        Self::fee(amount, self.deposit_fee_bps)
+    }
+
+    /// Fee on a withdrawal of `amount`, rounded down
+    pub fn withdraw_fee(&self, amount: u64) -> Result<u64> {
+        Self::fee(amount, self.withdraw_fee_bps)
+    }
+
+    fn fee(amount: u64, fee_bps: u16) -> Result<u64> {
+        let fee = (amount as u128)
+            .checked_mul(fee_bps as u128)
+            .ok_or(VaultErrorCode::ArithmeticOverflow)? / Self::BPS_DENOMINATOR;
+        u64::try_from(fee).map_err(|_| VaultErrorCode::ArithmeticOverflow.into())
+    }
+
+    /// Reads the config from its `[b"config"]` account. Until `init_config` has
+    /// run that account is empty, and the program is unpaused and fee-free.
+    pub fn load(account: &AccountInfo) -> Result<Self> {
+        if account.owner != &crate::ID || account.data_is_empty() {
+            return Ok(Self::default());
+        }
+        Self::try_deserialize(&mut &account.data.borrow()[..])
+    }
+
+    pub fn check_not_paused(&self) -> Result<()> {
+        if self.paused {
+            return Err(VaultErrorCode::ProgramPaused.into());
+        }
+        Ok(())
+    }
+}
+
+/// Fields are only ever appended so that older accounts can be grown in place
+/// by `migrate_vault_state`.
+#[account]
+#[derive(InitSpace)]
+pub struct VaultState {
+    pub bump: u8,
+    pub vault_bump: u8,
+    pub version: u8,
+    pub total_deposited: u64,
+    pub total_withdrawn: u64,
+    pub deposit_count: u64,
+    pub last_activity_slot: u64,
+    pub last_activity_timestamp: i64,
+    /// Unix timestamp before which withdrawals are blocked, 0 if unused
+    pub unlock_timestamp: i64,
+    /// Slot before which withdrawals are blocked, 0 if unused
+    pub unlock_slot: u64,
+    pub vault_id: u64,
+    /// Smallest amount `withdraw` accepts, 0 if unused
+    pub min_withdrawal: u64,
+    /// Multisig that must approve withdrawals and closes, default if unused
+    pub multisig: Pubkey,
+    /// Most lamports that can leave the vault per window, 0 if unused
+    pub withdrawal_limit: u64,
+    /// Length of the withdrawal limit window in seconds
+    pub withdrawal_window: i64,
+    /// Unix timestamp the current window started at
+    pub window_start: i64,
+    /// Lamports withdrawn since `window_start`
+    pub withdrawn_in_window: u64,
+    /// Looser limit waiting out `WITHDRAWAL_LIMIT_DELAY`
+    pub pending_withdrawal_limit: u64,
+    pub pending_withdrawal_window: i64,
+    /// Unix timestamp the pending limit takes effect at, 0 if none is pending
+    pub pending_limit_effective_at: i64,
+    /// Key the vault was created with, which its address is derived from
+    pub creator: Pubkey,
+    /// Key allowed to manage the vault; starts out as `creator` but can be
+    /// transferred or recovered
+    pub owner: Pubkey,
+    /// Key proposed as the next owner, default if no transfer is pending
+    pub pending_owner: Pubkey,
+    /// Key that can claim the vault once the owner goes inactive, default if unused
+    pub beneficiary: Pubkey,
+    /// Seconds without an owner action after which the beneficiary can claim
+    pub inactivity_period: i64,
+    /// Unix timestamp of the last owner action
+    pub last_heartbeat: i64,
+    /// Lamports promised to open streams that haven't been claimed yet
+    pub reserved_for_streams: u64,
+    /// Number of streams created so far, used to derive stream addresses
+    pub stream_count: u64,
+    /// Lamports moved from the vault into its stake account; only the rest is liquid
+    pub staked_lamports: u64,
+    /// Savings goal in lamports of net deposits, 0 if unused
+    pub goal_amount: u64,
+    /// Unix timestamp after which the goal no longer holds withdrawals back, 0 if none
+    pub goal_deadline: i64,
+    /// Whether withdrawals wait for the goal to be reached or its deadline to pass
+    pub goal_locks_withdrawals: bool,
+    /// Unix timestamp of the deposit that reached the goal, 0 until then
+    pub goal_reached_at: i64,
+    /// Open delegation accounts; the vault can't be closed while any are left
+    pub delegation_count: u64,
+    /// Whether a guardian set exists; it has to be removed before closing
+    pub has_guardians: bool,
+}
+
+impl VaultState {
+    pub const CURRENT_VERSION: u8 = 14;
+    /// Shortest inactivity period a beneficiary can be set up with
+    pub const MIN_INACTIVITY_PERIOD: i64 = 30 * 24 * 60 * 60;
+    /// How long a looser withdrawal limit waits before it applies
+    pub const WITHDRAWAL_LIMIT_DELAY: i64 = 24 * 60 * 60;
+    /// Size of the unversioned layout: discriminator + bump + vault_bump
+    pub const LEGACY_SPACE: usize = 8 + 1 + 1;
+    pub const VERSION_OFFSET: usize = 8 + 1 + 1;
+
+    /// Reads the layout version from raw account data, treating the
+    /// unversioned layout as version 0.
+    pub fn layout_version(data: &[u8]) -> u8 {
+        data.get(Self::VERSION_OFFSET).copied().unwrap_or(0)
+    }
+
+    pub fn record_activity(&mut self, clock: &Clock) {
+        self.last_activity_slot = clock.slot;
+        self.last_activity_timestamp = clock.unix_timestamp;
+    }
+
+    /// Records an action taken by the owner, which also proves they are still around
+    pub fn record_owner_activity(&mut self, clock: &Clock) {
+        self.record_activity(clock);
+        self.last_heartbeat = clock.unix_timestamp;
+    }
+
+    pub fn record_deposit(&mut self, amount: u64, clock: &Clock) -> Result<()> {
+        self.total_deposited = self.total_deposited
+            .checked_add(amount)
+            .ok_or(VaultErrorCode::ArithmeticOverflow)?;
+        self.deposit_count = self.deposit_count
+            .checked_add(1)
+            .ok_or(VaultErrorCode::ArithmeticOverflow)?;
+        self.record_activity(clock);
+        Ok(())
+    }
+
+    /// Records lamports leaving the vault, counting them against the withdrawal limit.
+    /// Every path that pays out of the vault goes through here.
+    pub fn record_withdrawal(&mut self, amount: u64, clock: &Clock) -> Result<()> {
+        self.apply_pending_limit(clock);
+        if self.withdrawal_limit != 0 {
+            if amount > self.window_remaining(clock) {
+                return Err(VaultErrorCode::WithdrawalLimitExceeded.into());
+            }
+            if self.window_elapsed(clock) {
+                self.window_start = clock.unix_timestamp;
+                self.withdrawn_in_window = 0;
+            }
+            self.withdrawn_in_window = self.withdrawn_in_window
+                .checked_add(amount)
+                .ok_or(VaultErrorCode::ArithmeticOverflow)?;
+        }
+        self.total_withdrawn = self.total_withdrawn
+            .checked_add(amount)
+            .ok_or(VaultErrorCode::ArithmeticOverflow)?;
+        self.record_activity(clock);
+        Ok(())
+    }
+
+    /// Lamports above the vault's rent-exempt reserve, which only closing the vault can
+    /// reclaim, and above what open streams still owe their recipients
+    pub fn available_balance(&self, vault: &SystemAccount) -> Result<u64> {
+        let vault = vault.to_account_info();
+        let rent_exempt = Rent::get()?.minimum_balance(vault.data_len());
+        Ok(vault.lamports().saturating_sub(rent_exempt).saturating_sub(self.reserved_for_streams))
+    }
+
+    /// Moves a pending limit into place once its delay has passed
+    pub fn apply_pending_limit(&mut self, clock: &Clock) {
+        if self.pending_limit_effective_at != 0 && clock.unix_timestamp >= self.pending_limit_effective_at {
+            self.withdrawal_limit = self.pending_withdrawal_limit;
+            self.withdrawal_window = self.pending_withdrawal_window;
+            self.pending_withdrawal_limit = 0;
+            self.pending_withdrawal_window = 0;
+            self.pending_limit_effective_at = 0;
+        }
+    }
+
+    fn window_elapsed(&self, clock: &Clock) -> bool {
+        clock.unix_timestamp >= self.window_start.saturating_add(self.withdrawal_window)
+    }
+
+    /// Lamports that can still be withdrawn in the current window
+    pub fn window_remaining(&self, clock: &Clock) -> u64 {
+        if self.withdrawal_limit == 0 {
+            u64::MAX
+        } else if self.window_elapsed(clock) {
+            self.withdrawal_limit
+        } else {
+            self.withdrawal_limit.saturating_sub(self.withdrawn_in_window)
+        }
+    }
+
+    pub fn is_inactive(&self, clock: &Clock) -> bool {
+        clock.unix_timestamp >= self.last_heartbeat.saturating_add(self.inactivity_period)
+    }
+
+    pub fn has_multisig(&self) -> bool {
+        self.multisig != Pubkey::default()
+    }
+
+    pub fn is_locked(&self, clock: &Clock) -> bool {
+        clock.unix_timestamp < self.unlock_timestamp || clock.slot < self.unlock_slot
+    }
+
+    /// Lamports deposited and not withdrawn since, which is what counts toward the goal
+    pub fn saved(&self) -> u64 {
+        self.total_deposited.saturating_sub(self.total_withdrawn)
+    }
+
+    /// Once reached, a goal stays reached even if withdrawals take the vault back below it
+    pub fn goal_reached(&self) -> bool {
+        self.goal_amount != 0 && self.goal_reached_at != 0
+    }
+
+    pub fn is_goal_locked(&self, clock: &Clock) -> bool {
+        self.goal_locks_withdrawals &&
+            !self.goal_reached() &&
+            (self.goal_deadline == 0 || clock.unix_timestamp < self.goal_deadline)
+    }
+
+    /// Marks the goal reached if the savings now cover it. Returns true only for
+    /// the call that reaches it.
+    pub fn record_goal_progress(&mut self, clock: &Clock) -> bool {
+        if self.goal_amount == 0 || self.goal_reached() || self.saved() < self.goal_amount {
+            return false;
+        }
+        self.goal_reached_at = clock.unix_timestamp;
+        true
+    }
+
+    /// Seed for the vault id. The default vault (id 0) keeps the original
+    /// `[b"state", user]` address so vaults created before ids existed stay valid.
+    pub fn id_seed(vault_id: u64) -> Vec<u8> {
+        if vault_id == 0 { Vec::new() } else { vault_id.to_le_bytes().to_vec() }
+    }
+}
+
+/// Per-user counter of vaults, so a user's vaults can be enumerated by
+/// deriving the state PDA for every id below `vault_count`.
+#[account]
+#[derive(InitSpace)]
+pub struct VaultRegistry {
+    pub bump: u8,
+    /// Number of vault ids handed out so far
+    pub vault_count: u64,
+    /// Number of vaults currently open
+    pub open_vaults: u64,
+}
+
+/// Running total of what one depositor has paid into someone else's vault
+#[account]
+#[derive(InitSpace)]
+pub struct Contribution {
+    pub bump: u8,
+    pub vault_state: Pubkey,
+    pub depositor: Pubkey,
+    pub total_contributed: u64,
+    pub contribution_count: u64,
+    pub last_contribution_timestamp: i64,
+}
+
+/// Destinations other than the owner that withdrawals and closes may pay out to
+#[account]
+#[derive(InitSpace)]
+pub struct DestinationAllowList {
+    pub bump: u8,
+    pub vault_state: Pubkey,
+    #[max_len(8)]
+    pub destinations: Vec<Pubkey>,
+}
+
+impl DestinationAllowList {
+    pub const MAX_DESTINATIONS: usize = 8;
+
+    /// Reads the allow-list from its `[b"allowlist", vault_state]` account,
+    /// or `None` if the vault never had a destination added
+    pub fn load(account: &AccountInfo) -> Result<Option<Self>> {
+        if account.owner != &crate::ID || account.data_is_empty() {
+            return Ok(None);
+        }
+        Self::try_deserialize(&mut &account.data.borrow()[..]).map(Some)
+    }
+}
+
+/// Owner set that has to approve withdrawals and closes of a vault
+#[account]
+#[derive(InitSpace)]
+pub struct VaultMultisig {
+    pub bump: u8,
+    pub vault_state: Pubkey,
+    /// Key the vault was created with, which its registry is derived from
+    pub creator: Pubkey,
+    #[max_len(10)]
+    pub owners: Vec<Pubkey>,
+    pub threshold: u8,
+    pub proposal_count: u64,
+    /// Proposals neither executed nor cancelled yet
+    pub open_proposals: u64,
+}
+
+impl VaultMultisig {
+    pub const MAX_OWNERS: usize = 10;
+}
+
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug, PartialEq, Eq, InitSpace)]
+pub enum ProposalAction {
+    Withdraw {
+        amount: u64,
+    },
+    Close,
+}
+
+/// Pending multisig action, closed once executed or cancelled
+#[account]
+#[derive(InitSpace)]
+pub struct Proposal {
+    pub bump: u8,
+    pub multisig: Pubkey,
+    pub index: u64,
+    pub proposer: Pubkey,
+    pub action: ProposalAction,
+    pub destination: Pubkey,
+    #[max_len(10)]
+    pub approvals: Vec<Pubkey>,
+}
+
+/// Lamports a delegate may still withdraw from a vault
+#[account]
+#[derive(InitSpace)]
+pub struct Delegation {
+    pub bump: u8,
+    pub vault_state: Pubkey,
+    /// Owner that granted the allowance; it lapses once the vault changes hands
+    pub owner: Pubkey,
+    pub delegate: Pubkey,
+    /// Remaining allowance, reduced by every delegate withdrawal
+    pub allowance: u64,
+    /// Unix timestamp after which the allowance can't be used, 0 if it never expires
+    pub expires_at: i64,
+}
+
+impl Delegation {
+    pub fn is_expired(&self, clock: &Clock) -> bool {
+        self.expires_at != 0 && clock.unix_timestamp >= self.expires_at
+    }
+}
+
+/// Guardians that can together move a vault to a new owner
+#[account]
+#[derive(InitSpace)]
+pub struct GuardianSet {
+    pub bump: u8,
+    pub vault_state: Pubkey,
+    #[max_len(10)]
+    pub guardians: Vec<Pubkey>,
+    pub threshold: u8,
+    /// Seconds the owner has to veto a recovery request
+    pub recovery_delay: i64,
+}
+
+impl GuardianSet {
+    pub const MAX_GUARDIANS: usize = 10;
+    pub const MIN_RECOVERY_DELAY: i64 = 24 * 60 * 60;
+}
+
+/// Open request to move a vault to `new_owner`, at most one per vault
+#[account]
+#[derive(InitSpace)]
+pub struct RecoveryRequest {
+    pub bump: u8,
+    pub vault_state: Pubkey,
+    pub new_owner: Pubkey,
+    pub proposer: Pubkey,
+    #[max_len(10)]
+    pub approvals: Vec<Pubkey>,
+    pub created_at: i64,
+}
+
+/// Lamports of a vault vesting linearly to a recipient
+#[account]
+#[derive(InitSpace)]
+pub struct Stream {
+    pub bump: u8,
+    pub vault_state: Pubkey,
+    pub recipient: Pubkey,
+    pub stream_id: u64,
+    pub total: u64,
+    pub start: i64,
+    /// Nothing can be claimed before this timestamp
+    pub cliff: i64,
+    pub end: i64,
+    pub withdrawn: u64,
+    /// Paid the account's rent, which goes back to it once the stream is done
+    pub payer: Pubkey,
+}
+
+impl Stream {
+    pub fn vested(&self, clock: &Clock) -> u64 {
+        let now = clock.unix_timestamp;
+        if now < self.cliff {
+            0
+        } else if now >= self.end {
+            self.total
+        } else {
+            // Widened so schedules spanning most of the i64 range can't overflow
+            let elapsed = ((now as i128) - (self.start as i128)) as u128;
+            let duration = ((self.end as i128) - (self.start as i128)) as u128;
+            ((self.total as u128) * elapsed / duration) as u64
+        }
+    }
+
+    /// Vested lamports the recipient hasn't claimed yet
+    pub fn claimable(&self, clock: &Clock) -> u64 {
+        self.vested(clock).saturating_sub(self.withdrawn)
+    }
+}
+
+// impl Space for VaultState {
+//     const INIT_SPACE: usize = 8 + 1 + 1; // discriminator + bump + vault_bump
+// }
+
+#[derive(Accounts)]
+pub struct InitializeTokenVault<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(mint::token_program = token_program)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init,
+        payer = user,
+        seeds = [b"token_state", user.key().as_ref(), mint.key().as_ref()],
+        bump,
+        space = 8 + TokenVaultState::INIT_SPACE
+    )]
+    pub token_vault_state: Account<'info, TokenVaultState>,
+    #[account(
+        init,
+        payer = user,
+        associated_token::mint = mint,
+        associated_token::authority = token_vault_state,
+        associated_token::token_program = token_program,
+    )]
+    pub token_vault: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> InitializeTokenVault<'info> {
+    pub fn initialize_token_vault(&mut self, bumps: InitializeTokenVaultBumps) -> Result<()> {
+        self.token_vault_state.bump = bumps.token_vault_state;
+        self.token_vault_state.mint = self.mint.key();
+        self.token_vault_state.record_activity(&Clock::get()?);
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct DepositToken<'info> {
+    pub user: Signer<'info>,
+    #[account(mint::token_program = token_program)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        token::mint = mint,
+        token::authority = user,
+        token::token_program = token_program,
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [b"token_state", user.key().as_ref(), mint.key().as_ref()],
+        bump = token_vault_state.bump,
+    )]
+    pub token_vault_state: Account<'info, TokenVaultState>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = token_vault_state,
+        associated_token::token_program = token_program,
+    )]
+    pub token_vault: InterfaceAccount<'info, TokenAccount>,
+    /// CHECK: read with `ProgramConfig::load`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+impl<'info> DepositToken<'info> {
+    pub fn deposit_token(&mut self, amount: u64) -> Result<()> {
+        ProgramConfig::load(&self.config)?.check_not_paused()?;
+        let balance_before = self.token_vault.amount;
+
+        let cpi_program = self.token_program.to_account_info();
+
+        let cpi_accounts = TransferChecked {
+            from: self.user_token_account.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.token_vault.to_account_info(),
+            authority: self.user.to_account_info(),
+        };
+
+        let cpi_ctx = CpiContext::new(cpi_program, cpi_accounts);
+        transfer_checked(cpi_ctx, amount, self.mint.decimals)?;
+
+        // Token-2022 transfer fees are withheld from the received amount, so
+        // only count what actually landed in the vault
+        self.token_vault.reload()?;
+        let received = self.token_vault.amount
+            .checked_sub(balance_before)
+            .ok_or(VaultErrorCode::ArithmeticOverflow)?;
+
+        self.token_vault_state.total_deposited = self.token_vault_state.total_deposited
+            .checked_add(received)
+            .ok_or(VaultErrorCode::ArithmeticOverflow)?;
+        self.token_vault_state.deposit_count = self.token_vault_state.deposit_count
+            .checked_add(1)
+            .ok_or(VaultErrorCode::ArithmeticOverflow)?;
+        self.token_vault_state.record_activity(&Clock::get()?);
+
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct WithdrawToken<'info> {
+    pub user: Signer<'info>,
+    #[account(mint::token_program = token_program)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        token::mint = mint,
+        token::token_program = token_program,
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [b"token_state", user.key().as_ref(), mint.key().as_ref()],
+        bump = token_vault_state.bump,
+    )]
+    pub token_vault_state: Account<'info, TokenVaultState>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = token_vault_state,
+        associated_token::token_program = token_program,
+    )]
+    pub token_vault: InterfaceAccount<'info, TokenAccount>,
+    /// CHECK: read with `ProgramConfig::load`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+impl<'info> WithdrawToken<'info> {
+    pub fn withdraw_token(&mut self, amount: u64) -> Result<()> {
+        ProgramConfig::load(&self.config)?.check_not_paused()?;
+        if amount > self.token_vault.amount {
+            return Err(VaultErrorCode::InsufficientVaultBalance.into());
+        }
+        let cpi_program = self.token_program.to_account_info();
+
+        let cpi_accounts = TransferChecked {
+            from: self.token_vault.to_account_info(),
+            mint: self.mint.to_account_info(),
+            to: self.user_token_account.to_account_info(),
+            authority: self.token_vault_state.to_account_info(),
+        };
+
+        let user_key = self.user.key();
+        let mint_key = self.mint.key();
+        let seeds = &[
+            b"token_state".as_ref(),
+            user_key.as_ref(),
+            mint_key.as_ref(),
+            &[self.token_vault_state.bump],
+        ];
+        let seeds_signer = &[&seeds[..]];
+
+        let cpi_ctx = CpiContext::new_with_signer(cpi_program, cpi_accounts, seeds_signer);
+        transfer_checked(cpi_ctx, amount, self.mint.decimals)?;
+
+        self.token_vault_state.total_withdrawn = self.token_vault_state.total_withdrawn
+            .checked_add(amount)
+            .ok_or(VaultErrorCode::ArithmeticOverflow)?;
+        self.token_vault_state.record_activity(&Clock::get()?);
+
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct CloseTokenVault<'info> {
+    #[account(mut)]
+    pub user: Signer<'info>,
+    #[account(mut, mint::token_program = token_program)]
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        token::mint = mint,
+        token::token_program = token_program,
+    )]
+    pub user_token_account: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        seeds = [b"token_state", user.key().as_ref(), mint.key().as_ref()],
+        bump = token_vault_state.bump,
+        close = user,
+    )]
+    pub token_vault_state: Account<'info, TokenVaultState>,
+    #[account(
+        mut,
+        associated_token::mint = mint,
+        associated_token::authority = token_vault_state,
+        associated_token::token_program = token_program,
+    )]
+    pub token_vault: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+}
+
+impl<'info> CloseTokenVault<'info> {
+    pub fn close_token_vault(&mut self) -> Result<()> {
+        let user_key = self.user.key();
+        let mint_key = self.mint.key();
+        let seeds = &[
+            b"token_state".as_ref(),
+            user_key.as_ref(),
+            mint_key.as_ref(),
+            &[self.token_vault_state.bump],
+        ];
+        let seeds_signer = &[&seeds[..]];
+
+        // Return any remaining tokens to the user
+        let remaining = self.token_vault.amount;
+        if remaining > 0 {
+            let cpi_accounts = TransferChecked {
+                from: self.token_vault.to_account_info(),
+                mint: self.mint.to_account_info(),
+                to: self.user_token_account.to_account_info(),
+                authority: self.token_vault_state.to_account_info(),
+            };
+            let cpi_ctx = CpiContext::new_with_signer(
+                self.token_program.to_account_info(),
+                cpi_accounts,
+                seeds_signer
+            );
+            transfer_checked(cpi_ctx, remaining, self.mint.decimals)?;
+        }
+
+        // Token-2022 refuses to close an account holding withheld transfer
+        // fees, so move them to the mint first
+        let mint_info = self.mint.to_account_info();
+        if
+            self.token_program.key() == spl_token_2022::ID &&
+            get_mint_extension_data::<TransferFeeConfig>(&mint_info).is_ok()
+        {
+            let cpi_accounts = HarvestWithheldTokensToMint {
+                token_program_id: self.token_program.to_account_info(),
+                mint: mint_info,
+            };
+            let cpi_ctx = CpiContext::new(self.token_program.to_account_info(), cpi_accounts);
+            harvest_withheld_tokens_to_mint(cpi_ctx, vec![self.token_vault.to_account_info()])?;
+        }
+
+        // Close the token account, returning its rent to the user
+        let cpi_accounts = CloseAccount {
+            account: self.token_vault.to_account_info(),
+            destination: self.user.to_account_info(),
+            authority: self.token_vault_state.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            cpi_accounts,
+            seeds_signer
+        );
+        close_account(cpi_ctx)?;
+
+        self.token_vault_state.total_withdrawn = self.token_vault_state.total_withdrawn
+            .checked_add(remaining)
+            .ok_or(VaultErrorCode::ArithmeticOverflow)?;
+        Ok(())
+    }
+}
+
+/// Per-user, per-mint token vault. The vault's token account is the
+/// associated token account of this PDA.
+#[account]
+#[derive(InitSpace)]
+pub struct TokenVaultState {
+    pub bump: u8,
+    pub mint: Pubkey,
+    pub total_deposited: u64,
+    pub total_withdrawn: u64,
+    pub deposit_count: u64,
+    pub last_activity_slot: u64,
+    pub last_activity_timestamp: i64,
+}
+
+impl TokenVaultState {
+    pub fn record_activity(&mut self, clock: &Clock) {
+        self.last_activity_slot = clock.slot;
+        self.last_activity_timestamp = clock.unix_timestamp;
+    }
+}
+
+#[derive(Accounts)]
+pub struct InitializePool<'info> {
+    #[account(mut)]
+    pub creator: Signer<'info>,
+    #[account(
+        init,
+        payer = creator,
+        seeds = [b"pool", creator.key().as_ref()],
+        bump,
+        space = 8 + PoolState::INIT_SPACE
+    )]
+    pub pool: Account<'info, PoolState>,
+    #[account(
+        mut,
+        seeds = [b"pool_vault", pool.key().as_ref()],
+        bump,
+    )]
+    pub pool_vault: SystemAccount<'info>,
+    #[account(
+        init,
+        payer = creator,
+        seeds = [b"share_mint", pool.key().as_ref()],
+        bump,
+        mint::decimals = PoolState::SHARE_DECIMALS,
+        mint::authority = pool,
+        mint::token_program = token_program,
+    )]
+    pub share_mint: InterfaceAccount<'info, Mint>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> InitializePool<'info> {
+    pub fn initialize_pool(&mut self, bumps: &InitializePoolBumps) -> Result<()> {
+        // The pool vault holds its rent reserve from the start, so the first
+        // deposit can be of any size
+        let rent_exempt = Rent::get()?.minimum_balance(0);
+        let cpi_accounts = Transfer {
+            from: self.creator.to_account_info(),
+            to: self.pool_vault.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(self.system_program.to_account_info(), cpi_accounts);
+        transfer(cpi_ctx, rent_exempt.saturating_sub(self.pool_vault.lamports()))?;
+
+        self.pool.set_inner(PoolState {
+            bump: bumps.pool,
+            vault_bump: bumps.pool_vault,
+            creator: self.creator.key(),
+            share_mint: self.share_mint.key(),
+            total_deposited: 0,
+            total_withdrawn: 0,
+        });
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct DepositPool<'info> {
+    #[account(mut)]
+    pub depositor: Signer<'info>,
+    #[account(
+        mut,
+        has_one = share_mint,
+        seeds = [b"pool", pool.creator.as_ref()],
+        bump = pool.bump,
+    )]
+    pub pool: Account<'info, PoolState>,
+    #[account(
+        mut,
+        seeds = [b"pool_vault", pool.key().as_ref()],
+        bump = pool.vault_bump,
+    )]
+    pub pool_vault: SystemAccount<'info>,
+    #[account(mut, mint::token_program = token_program)]
+    pub share_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init_if_needed,
+        payer = depositor,
+        associated_token::mint = share_mint,
+        associated_token::authority = depositor,
+        associated_token::token_program = token_program,
+    )]
+    pub depositor_shares: InterfaceAccount<'info, TokenAccount>,
+    /// CHECK: read with `ProgramConfig::load`
+    #[account(seeds = [b"config"], bump)]
+    pub config: UncheckedAccount<'info>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub associated_token_program: Program<'info, AssociatedToken>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> DepositPool<'info> {
+    /// Mints shares for `amount` lamports at the pool's current share price
+    pub fn deposit_pool(&mut self, amount: u64) -> Result<u64> {
+        let config = ProgramConfig::load(&self.config)?;
+        config.check_not_paused()?;
+        let assets = PoolState::assets(&self.pool_vault)?;
+        let shares = PoolState::shares_for(amount, assets, self.share_mint.supply)?;
+        if shares == 0 {
+            return Err(VaultErrorCode::ZeroShares.into());
+        }
+
+        let cpi_accounts = Transfer {
+            from: self.depositor.to_account_info(),
+            to: self.pool_vault.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(self.system_program.to_account_info(), cpi_accounts);
+        transfer(cpi_ctx, amount)?;
+
+        let creator = self.pool.creator;
+        let seeds = &[b"pool".as_ref(), creator.as_ref(), &[self.pool.bump]];
+        let seeds_signer = &[&seeds[..]];
+        let cpi_accounts = MintTo {
+            mint: self.share_mint.to_account_info(),
+            to: self.depositor_shares.to_account_info(),
+            authority: self.pool.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(
+            self.token_program.to_account_info(),
+            cpi_accounts,
+            seeds_signer
+        );
+        mint_to(cpi_ctx, shares)?;
+
+        self.pool.total_deposited = self.pool.total_deposited
+            .checked_add(amount)
+            .ok_or(VaultErrorCode::ArithmeticOverflow)?;
+        Ok(shares)
+    }
+}
+
+#[derive(Accounts)]
+pub struct WithdrawPool<'info> {
+    #[account(mut)]
+    pub depositor: Signer<'info>,
+    #[account(
+        mut,
+        has_one = share_mint,
+        seeds = [b"pool", pool.creator.as_ref()],
+        bump = pool.bump,
+    )]
+    pub pool: Account<'info, PoolState>,
+    #[account(
+        mut,
+        seeds = [b"pool_vault", pool.key().as_ref()],
+        bump = pool.vault_bump,
+    )]
+    pub pool_vault: SystemAccount<'info>,
+    #[account(mut, mint::token_program = token_program)]
+    pub share_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        mut,
+        token::mint = share_mint,
+        token::authority = depositor,
+        token::token_program = token_program,
+    )]
+    pub depositor_shares: InterfaceAccount<'info, TokenAccount>,
+    pub token_program: Interface<'info, TokenInterface>,
+    pub system_program: Program<'info, System>,
+}
+
+impl<'info> WithdrawPool<'info> {
+    /// Burns `shares` for their pro-rata part of the pool, rounded down.
+    /// Not blocked by the pause, so pooled funds can always be taken out.
+    pub fn withdraw_pool(&mut self, shares: u64) -> Result<u64> {
+        let assets = PoolState::assets(&self.pool_vault)?;
+        let amount = PoolState::assets_for(shares, assets, self.share_mint.supply)?;
+        if amount == 0 {
+            return Err(VaultErrorCode::InsufficientWithdrawalAmount.into());
+        }
+
+        let cpi_accounts = Burn {
+            mint: self.share_mint.to_account_info(),
+            from: self.depositor_shares.to_account_info(),
+            authority: self.depositor.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new(self.token_program.to_account_info(), cpi_accounts);
+        burn(cpi_ctx, shares)?;
+
+        let pool_key = self.pool.key();
+        let seeds = &[b"pool_vault".as_ref(), pool_key.as_ref(), &[self.pool.vault_bump]];
+        let seeds_signer = &[&seeds[..]];
+        let cpi_accounts = Transfer {
+            from: self.pool_vault.to_account_info(),
+            to: self.depositor.to_account_info(),
+        };
+        let cpi_ctx = CpiContext::new_with_signer(
+            self.system_program.to_account_info(),
+            cpi_accounts,
+            seeds_signer
+        );
+        transfer(cpi_ctx, amount)?;
+
+        self.pool.total_withdrawn = self.pool.total_withdrawn
+            .checked_add(amount)
+            .ok_or(VaultErrorCode::ArithmeticOverflow)?;
+        Ok(amount)
+    }
+}
+
+/// Pooled SOL vault. Depositors hold shares of the pool as tokens of
+/// `share_mint`, which can be transferred like any other token.
+#[account]
+#[derive(InitSpace)]
+pub struct PoolState {
+    pub bump: u8,
+    pub vault_bump: u8,
+    pub creator: Pubkey,
+    pub share_mint: Pubkey,
+    pub total_deposited: u64,
+    pub total_withdrawn: u64,
+}
+
+impl PoolState {
+    pub const SHARE_DECIMALS: u8 = 9;
+    /// Virtual shares and lamports added to both sides of the share price.
+    /// Donating lamports to an empty pool to round the next depositor down to
+    /// nothing then mostly benefits the virtual shares, making the attack a loss.
+    pub const VIRTUAL_SHARES: u128 = 1_000_000;
+    pub const VIRTUAL_ASSETS: u128 = 1_000_000;
+
+    /// Lamports the pool holds for its shareholders, including donations
+    pub fn assets(pool_vault: &SystemAccount) -> Result<u64> {
+        let rent_exempt = Rent::get()?.minimum_balance(0);
+        Ok(pool_vault.lamports().saturating_sub(rent_exempt))
+    }
+
+    /// Shares minted for depositing `amount`, rounded down in the pool's favour
+    pub fn shares_for(amount: u64, assets: u64, supply: u64) -> Result<u64> {
+        let shares = (amount as u128)
+            .checked_mul((supply as u128) + Self::VIRTUAL_SHARES)
+            .ok_or(VaultErrorCode::ArithmeticOverflow)? / ((assets as u128) + Self::VIRTUAL_ASSETS);
+        u64::try_from(shares).map_err(|_| VaultErrorCode::ArithmeticOverflow.into())
+    }
+
+    /// Lamports paid out for burning `shares`, rounded down in the pool's favour
+    pub fn assets_for(shares: u64, assets: u64, supply: u64) -> Result<u64> {
+        let amount = (shares as u128)
+            .checked_mul((assets as u128) + Self::VIRTUAL_ASSETS)
+            .ok_or(VaultErrorCode::ArithmeticOverflow)? / ((supply as u128) + Self::VIRTUAL_SHARES);
+        u64::try_from(amount).map_err(|_| VaultErrorCode::ArithmeticOverflow.into())
+    }
+}
+
+#[event]
+pub struct VaultInitialized {
+    pub user: Pubkey,
+    pub vault_state: Pubkey,
+    pub vault: Pubkey,
+    pub vault_id: u64,
+    /// Rent-exempt reserve moved into the vault
+    pub amount: u64,
+    pub balance: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct Deposited {
+    pub user: Pubkey,
+    /// Differs from `user` for deposits made with `deposit_for`
+    pub depositor: Pubkey,
+    pub vault_state: Pubkey,
+    pub vault: Pubkey,
+    pub amount: u64,
+    pub balance: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct Withdrawn {
+    pub user: Pubkey,
+    pub vault_state: Pubkey,
+    pub vault: Pubkey,
+    /// Account that received the funds
+    pub destination: Pubkey,
+    pub amount: u64,
+    pub balance: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct VaultClosed {
+    pub user: Pubkey,
+    pub vault_state: Pubkey,
+    pub vault: Pubkey,
+    /// Account that received the funds
+    pub destination: Pubkey,
+    pub amount: u64,
+    pub balance: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct FeesCollected {
+    pub admin: Pubkey,
+    pub destination: Pubkey,
+    pub amount: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct GoalReached {
+    pub vault_state: Pubkey,
+    pub owner: Pubkey,
+    pub goal_amount: u64,
+    pub saved: u64,
+    pub timestamp: i64,
+}
+
+#[event]
+pub struct OwnershipTransferred {
+    pub vault_state: Pubkey,
+    pub previous_owner: Pubkey,
+    pub new_owner: Pubkey,
+    pub timestamp: i64,
+}
 
 #[error_code]
+#[derive(num_enum::TryFromPrimitive)]
 pub enum VaultErrorCode {
-    #[msg("Insufficient withdrawal amount")]
+    #[msg("Withdrawal amount is below the vault minimum")]
     InsufficientWithdrawalAmount,
     #[msg("Insufficient balance in vault")]
     InsufficientVaultBalance,
     #[msg("Vault already closed")]
     VaultAlreadyClosed,
+    #[msg("Arithmetic overflow")]
+    ArithmeticOverflow,
+    #[msg("Vault state already uses the current layout")]
+    VaultAlreadyMigrated,
+    #[msg("Vault is locked")]
+    VaultLocked,
+    #[msg("Vault lock can only be extended")]
+    LockCannotBeShortened,
+    #[msg("Vault id must not skip unused ids")]
+    InvalidVaultId,
+    #[msg("Destination is not on the vault allow-list")]
+    DestinationNotAllowed,
+    #[msg("Destination is already on the vault allow-list")]
+    DestinationAlreadyAllowed,
+    #[msg("Destination was not found on the vault allow-list")]
+    DestinationNotFound,
+    #[msg("Vault allow-list is full")]
+    AllowListFull,
+    #[msg("Vault is controlled by a multisig")]
+    MultisigRequired,
+    #[msg("Multisig owners must be unique and at most 10")]
+    InvalidOwners,
+    #[msg("Multisig threshold must be between 1 and the number of owners")]
+    InvalidThreshold,
+    #[msg("Signer is not a multisig owner")]
+    NotMultisigOwner,
+    #[msg("Owner already approved this proposal")]
+    AlreadyApproved,
+    #[msg("Proposal does not have enough approvals")]
+    NotEnoughApprovals,
+    #[msg("Withdrawal exceeds the delegate's allowance")]
+    AllowanceExceeded,
+    #[msg("Delegate allowance has expired")]
+    AllowanceExpired,
+    #[msg("Withdrawal exceeds the vault's withdrawal limit for this window")]
+    WithdrawalLimitExceeded,
+    #[msg("Withdrawal limit needs a positive window")]
+    InvalidWithdrawalLimit,
+    #[msg("Signer is not the vault owner")]
+    NotVaultOwner,
+    #[msg("Guardians must be unique, at most 10 and not include the owner")]
+    InvalidGuardians,
+    #[msg("Recovery delay is shorter than the minimum")]
+    RecoveryDelayTooShort,
+    #[msg("Signer is not a guardian of this vault")]
+    NotGuardian,
+    #[msg("Recovery delay has not elapsed yet")]
+    RecoveryDelayNotElapsed,
+    #[msg("New owner must differ from the current owner and the default key")]
+    InvalidNewOwner,
+    #[msg("No ownership transfer is pending for this key")]
+    NoPendingOwner,
+    #[msg("Beneficiary must differ from the owner")]
+    InvalidBeneficiary,
+    #[msg("Inactivity period is shorter than the minimum")]
+    InactivityPeriodTooShort,
+    #[msg("Signer is not the vault beneficiary")]
+    NotBeneficiary,
+    #[msg("Vault owner has not been inactive for long enough")]
+    OwnerStillActive,
+    #[msg("Stream needs a positive total and start <= cliff <= end with start < end")]
+    InvalidStreamSchedule,
+    #[msg("Nothing has vested that hasn't been claimed")]
+    NothingToClaim,
+    #[msg("Vault still has open streams")]
+    StreamsOutstanding,
+    #[msg("Signer is not the program upgrade authority")]
+    NotUpgradeAuthority,
+    #[msg("Signer is not the config admin")]
+    NotConfigAdmin,
+    #[msg("Program is paused")]
+    ProgramPaused,
+    #[msg("Fee exceeds the maximum")]
+    FeeTooHigh,
+    #[msg("Treasury holds no fees to collect")]
+    NoFeesToCollect,
+    #[msg("Deposit is too small to mint any shares")]
+    ZeroShares,
+    #[msg("Vault has lamports staked")]
+    StakeOutstanding,
+    #[msg("Vault has no stake")]
+    NoStake,
+    #[msg("Invalid savings goal")]
+    InvalidGoal,
+    #[msg("Withdrawals are held until the savings goal is reached")]
+    GoalNotReached,
+    #[msg("Goal holding withdrawals back can only be tightened")]
+    GoalCannotBeLoosened,
+    #[msg("Vault still has open delegations")]
+    DelegationsOutstanding,
+    #[msg("Allowance was granted by a previous owner")]
+    DelegationLapsed,
+    #[msg("Vault still has guardians")]
+    GuardiansOutstanding,
+    #[msg("A recovery request is still open")]
+    RecoveryPending,
+    #[msg("Multisig still has other open proposals")]
+    ProposalsOutstanding,
 }
@@ -1,86 +1,48 @@
 #![cfg(feature = "test-sbf")]
 
-use anchor_lang::{ InstructionData };
-use solana_sdk::{ instruction::{ AccountMeta, Instruction } };
+use anchor_lang::{ solana_program::rent::Rent, AccountDeserialize, Space };
 
-mod utils;
-use utils::{ setup_initialized_and_deposited_vault, USER_INITIAL_LAMPORTS };
+pub mod utils;
+use utils::{ find_registry, VaultTestEnv, DEPOSIT_AMOUNT, USER_INITIAL_LAMPORTS };
 
 #[test]
 fn test_close_success() {
-    let (mollusk, user, vault_state, vault, _, _, deposit_result) =
-        setup_initialized_and_deposited_vault();
-    let (system_program, system_account) = mollusk_svm::program::keyed_account_for_system_program();
-    let program_id = anchor_vault_q3::id();
-    let withdraw_amount = 5_000_000; // 0.005 SOL
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+    let vault_state = env.vault_state_address(&user);
+    let vault = env.vault_address(&user);
 
-    // Create withdraw instruction
-    let withdraw_instruction = Instruction::new_with_bytes(
-        program_id,
-        &(anchor_vault_q3::instruction::Withdraw { amount: withdraw_amount }).data(),
-        vec![
-            AccountMeta::new(user, true),
-            AccountMeta::new(vault, false),
-            AccountMeta::new_readonly(vault_state, false),
-            AccountMeta::new_readonly(system_program, false)
-        ]
-    );
+    let result = env.withdraw(&user, DEPOSIT_AMOUNT);
+    assert!(!result.program_result.is_err(), "Withdraw should succeed");
 
-    let user_account = deposit_result.get_account(&user).unwrap();
-    let vault_account = deposit_result.get_account(&vault).unwrap();
-    let withdraw_accounts = &vec![
-        (user, user_account.clone()),
-        (vault_state, deposit_result.get_account(&vault_state).unwrap().clone()),
-        (vault, vault_account.clone()),
-        (system_program, system_account.clone())
-    ];
-    let withdraw_result = mollusk.process_instruction(&withdraw_instruction, &withdraw_accounts);
+    let result = env.close(&user);
+    assert!(!result.program_result.is_err(), "Close should succeed");
 
-    // Verify success
-    assert!(!withdraw_result.program_result.is_err(), "Withdraw should succeed");
+    // Verify user received all lamports from vault and vault_state; only the
+    // registry, which outlives its vaults, keeps its rent
+    let registry_rent = Rent::default().minimum_balance(8 + anchor_vault_q3::VaultRegistry::INIT_SPACE);
+    env.assert_lamports(&user, USER_INITIAL_LAMPORTS - registry_rent);
 
-    // Create close instruction
-    let close_instruction = Instruction::new_with_bytes(
-        program_id,
-        &(anchor_vault_q3::instruction::Close {}).data(),
-        vec![
-            AccountMeta::new(user, true),
-            AccountMeta::new(vault_state, false),
-            AccountMeta::new(vault, false),
-            AccountMeta::new_readonly(system_program, false)
-        ]
-    );
-
-    let user_account = withdraw_result.get_account(&user).unwrap();
-    let vault_state_account = withdraw_result.get_account(&vault_state).unwrap();
-    let vault_account = withdraw_result.get_account(&vault).unwrap();
-
-    let close_accounts = &vec![
-        (user, user_account.clone()),
-        (vault_state, vault_state_account.clone()),
-        (vault, vault_account.clone()),
-        (system_program, system_account.clone())
-    ];
-    let close_result = mollusk.process_instruction(&close_instruction, &close_accounts);
-
-    assert!(!close_result.program_result.is_err(), "Close should succeed");
+    // Verify vault account is empty
+    env.assert_lamports(&vault, 0);
 
-    // Verify user received all lamports from vault and vault_state
-    let user_account = &close_result.get_account(&user).unwrap();
-    assert_eq!(
-        user_account.lamports,
-        USER_INITIAL_LAMPORTS,
-        "User should receive vault lamports and vault_state rent"
-    );
+    // Verify vault_state account is closed
+    env.assert_lamports(&vault_state, 0);
 
-    // Verify vault account is empty
-    let vault_account = &close_result.get_account(&vault).unwrap();
-    assert_eq!(vault_account.lamports, 0, "Vault should be empty after close");
+    // Verify the registry no longer counts the vault as open
+    let registry_account = env.account(&find_registry(&user)).unwrap();
+    let registry = anchor_vault_q3::VaultRegistry
+        ::try_deserialize(&mut registry_account.data.as_slice())
+        .unwrap();
+    assert_eq!(registry.open_vaults, 0);
+}
 
-    // Verify vault_state account is closed (lamports = 0)
-    let vault_state_account = &close_result.get_account(&vault_state);
-    assert!(
-        vault_state_account.is_none() || vault_state_account.unwrap().lamports == 0,
-        "Vault state should be closed"
-    );
+#[test]
+fn test_close_with_balance_returns_it() {
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+    let registry_rent = Rent::default().minimum_balance(8 + anchor_vault_q3::VaultRegistry::INIT_SPACE);
+
+    let result = env.close(&user);
+    assert!(!result.program_result.is_err(), "Close should succeed");
+    env.assert_lamports(&user, USER_INITIAL_LAMPORTS - registry_rent);
+    env.assert_lamports(&env.vault_address(&user), 0);
 }
@@ -0,0 +1,38 @@
+#![cfg(feature = "test-sbf")]
+
+use std::{ collections::BTreeMap, fs, path::Path };
+
+pub mod utils;
+use utils::compute_unit_scenarios;
+
+/// Written by `make bench-baseline`
+const BASELINE: &str = "benches/compute_units_baseline.json";
+
+/// How far an instruction may grow over its baseline before the test fails
+const TOLERANCE_PERCENT: u64 = 2;
+
+#[test]
+fn test_compute_units_within_baseline() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(BASELINE);
+    let contents = fs::read_to_string(&path).unwrap_or_else(|error| {
+        panic!("No compute unit baseline at {} ({}); run `make bench-baseline`", path.display(), error)
+    });
+    let baseline: BTreeMap<String, u64> = serde_json::from_str(&contents).unwrap();
+    assert!(!baseline.is_empty(), "Compute unit baseline at {} is empty; run `make bench-baseline`", path.display());
+
+    let mut regressions = Vec::new();
+    for (name, units) in compute_unit_scenarios() {
+        match baseline.get(name) {
+            Some(&baseline_units) if units > baseline_units + (baseline_units * TOLERANCE_PERCENT) / 100 => {
+                regressions.push(format!("{}: {} compute units, baseline {}", name, units, baseline_units));
+            }
+            Some(_) => {}
+            None => regressions.push(format!("{}: {} compute units, no baseline", name, units)),
+        }
+    }
+    assert!(
+        regressions.is_empty(),
+        "Compute units above the baseline; run `make bench-baseline` if this is expected:\n{}",
+        regressions.join("\n")
+    );
+}
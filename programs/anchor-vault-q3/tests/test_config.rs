@@ -0,0 +1,285 @@
+#![cfg(feature = "test-sbf")]
+#![allow(deprecated)]
+
+use anchor_lang::{ prelude::*, solana_program::bpf_loader_upgradeable, system_program, InstructionData };
+use anchor_vault_q3::{ accounts, instruction, ProgramConfig, ProposalAction, VaultErrorCode };
+use anchor_vault_q3_client::{ close_ix, find_allow_list_address };
+use solana_sdk::{ account::Account, instruction::Instruction };
+
+pub mod utils;
+use utils::{
+    assert_vault_error,
+    config_account,
+    find_config,
+    find_event_authority,
+    find_registry,
+    find_treasury,
+    vault_instruction,
+    VaultTestEnv,
+};
+
+/// Program data account of the upgradeable loader, as `init_config` reads it
+fn program_data_account(upgrade_authority: Pubkey) -> (Pubkey, Account) {
+    let program_data = Pubkey::find_program_address(
+        &[anchor_vault_q3::id().as_ref()],
+        &bpf_loader_upgradeable::id()
+    ).0;
+    // UpgradeableLoaderState::ProgramData { slot, upgrade_authority_address }
+    let mut data = Vec::new();
+    data.extend_from_slice(&(3u32).to_le_bytes());
+    data.extend_from_slice(&(0u64).to_le_bytes());
+    data.push(1);
+    data.extend_from_slice(upgrade_authority.as_ref());
+    let mut account = Account::new(
+        Rent::default().minimum_balance(data.len()),
+        data.len(),
+        &bpf_loader_upgradeable::id()
+    );
+    account.data = data;
+    (program_data, account)
+}
+
+fn init_config_instruction(authority: Pubkey, program_data: Pubkey, admin: Pubkey) -> Instruction {
+    vault_instruction(
+        accounts::InitConfig {
+            authority,
+            config: find_config(),
+            program: anchor_vault_q3::id(),
+            program_data,
+            treasury: find_treasury(),
+            system_program: system_program::ID,
+        },
+        instruction::InitConfig { admin }
+    )
+}
+
+fn set_paused_instruction(admin: Pubkey, paused: bool) -> Instruction {
+    vault_instruction(accounts::UpdateConfig { admin, config: find_config() }, instruction::SetPaused { paused })
+}
+
+/// Deposited vault with a paused config
+fn setup_paused() -> (VaultTestEnv, Pubkey) {
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+    env.set_account(find_config(), config_account(Pubkey::default(), true));
+    (env, user)
+}
+
+/// Deposited vault whose config gets paused by `pause` once the test has set it up
+fn setup_unpaused() -> (VaultTestEnv, Pubkey) {
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+    env.set_account(find_config(), config_account(Pubkey::default(), false));
+    (env, user)
+}
+
+fn pause(env: &mut VaultTestEnv) {
+    env.set_account(find_config(), config_account(Pubkey::default(), true));
+}
+
+fn program_address(seeds: &[&[u8]]) -> Pubkey {
+    Pubkey::find_program_address(seeds, &anchor_vault_q3::id()).0
+}
+
+#[test]
+fn test_init_config_requires_upgrade_authority() {
+    let mut env = VaultTestEnv::new();
+    let authority = env.add_user();
+    let impostor = env.add_user();
+    let admin = Pubkey::new_unique();
+    let (program_data, program_data_account) = program_data_account(authority);
+    env.set_account(program_data, program_data_account);
+    env.set_account(find_config(), Account::default());
+
+    let failed = env.process(&init_config_instruction(impostor, program_data, admin));
+    assert_vault_error(&failed, VaultErrorCode::NotUpgradeAuthority);
+
+    let result = env.process(&init_config_instruction(authority, program_data, admin));
+    assert!(!result.program_result.is_err(), "Init config should succeed");
+    let config: ProgramConfig = env.decode(&find_config());
+    assert_eq!(config.admin, admin);
+    assert!(!config.paused);
+}
+
+#[test]
+fn test_pause_blocks_deposit_and_withdraw() {
+    let (mut env, user) = setup_paused();
+
+    let failed = env.deposit(&user, 1_000_000);
+    assert_vault_error(&failed, VaultErrorCode::ProgramPaused);
+
+    let failed = env.withdraw(&user, 1_000_000);
+    assert_vault_error(&failed, VaultErrorCode::ProgramPaused);
+
+    // Users can still get their funds out
+    let withdraw_all = Instruction {
+        data: (instruction::WithdrawAll { _vault_id: 0 }).data(),
+        ..anchor_vault_q3_client::withdraw_ix(&user, &user, 0, 0, None)
+    };
+    let result = env.process(&withdraw_all);
+    assert!(!result.program_result.is_err(), "Withdraw all should work while paused");
+
+    let result = env.process(&close_ix(&user, &user, 0, None));
+    assert!(!result.program_result.is_err(), "Close should work while paused");
+}
+
+#[test]
+fn test_set_paused_requires_admin() {
+    let (mut env, _) = VaultTestEnv::with_deposited_vault();
+    let admin = Pubkey::new_unique();
+    env.set_account(find_config(), config_account(admin, false));
+
+    let failed = env.process(&set_paused_instruction(Pubkey::new_unique(), true));
+    assert_vault_error(&failed, VaultErrorCode::NotConfigAdmin);
+
+    let result = env.process(&set_paused_instruction(admin, true));
+    assert!(!result.program_result.is_err(), "Set paused should succeed");
+    let config: ProgramConfig = env.decode(&find_config());
+    assert!(config.paused);
+}
+
+#[test]
+fn test_pause_blocks_withdraw_as_delegate() {
+    let (mut env, user) = setup_unpaused();
+    let vault_state = env.vault_state_address(&user);
+    let delegate = env.add_user();
+    let delegation = program_address(&[b"delegation", vault_state.as_ref(), delegate.as_ref()]);
+
+    let approve = vault_instruction(
+        accounts::ApproveDelegate { user, vault_state, delegation, system_program: system_program::ID },
+        instruction::ApproveDelegate { _vault_id: 0, delegate, allowance: 1_000_000, expires_at: 0 }
+    );
+    let result = env.process(&approve);
+    assert!(!result.program_result.is_err(), "Approve delegate should succeed");
+    pause(&mut env);
+
+    let withdraw = vault_instruction(
+        accounts::WithdrawAsDelegate {
+            delegate,
+            delegation,
+            vault_state,
+            vault: env.vault_address(&user),
+            destination: delegate,
+            config: find_config(),
+            treasury: find_treasury(),
+            system_program: system_program::ID,
+            event_authority: find_event_authority(),
+            program: anchor_vault_q3::id(),
+        },
+        instruction::WithdrawAsDelegate { amount: 1_000_000 }
+    );
+    let failed = env.process(&withdraw);
+    assert_vault_error(&failed, VaultErrorCode::ProgramPaused);
+}
+
+#[test]
+fn test_pause_blocks_multisig_withdrawal() {
+    let (mut env, user) = setup_unpaused();
+    let vault_state = env.vault_state_address(&user);
+    let owner = env.add_user();
+    let multisig = program_address(&[b"multisig", vault_state.as_ref()]);
+    let proposal = program_address(&[b"proposal", multisig.as_ref(), (0u64).to_le_bytes().as_ref()]);
+
+    let create = vault_instruction(
+        accounts::CreateMultisig { user, vault_state, multisig, system_program: system_program::ID },
+        instruction::CreateMultisig { _vault_id: 0, owners: vec![owner], threshold: 1 }
+    );
+    let result = env.process(&create);
+    assert!(!result.program_result.is_err(), "Create multisig should succeed");
+
+    let propose = vault_instruction(
+        accounts::ProposeWithdrawal { proposer: owner, multisig, proposal, system_program: system_program::ID },
+        instruction::ProposeWithdrawal { action: ProposalAction::Withdraw { amount: 1_000_000 }, destination: owner }
+    );
+    let result = env.process(&propose);
+    assert!(!result.program_result.is_err(), "Propose should succeed");
+    pause(&mut env);
+
+    let execute = vault_instruction(
+        accounts::Execute {
+            executor: owner,
+            multisig,
+            proposal,
+            proposer: owner,
+            vault_state,
+            vault: env.vault_address(&user),
+            destination: owner,
+            registry: find_registry(&user),
+            allow_list: find_allow_list_address(&vault_state).0,
+            config: find_config(),
+            treasury: find_treasury(),
+            system_program: system_program::ID,
+            event_authority: find_event_authority(),
+            program: anchor_vault_q3::id(),
+        },
+        instruction::Execute {}
+    );
+    let failed = env.process(&execute);
+    assert_vault_error(&failed, VaultErrorCode::ProgramPaused);
+}
+
+#[test]
+fn test_pause_blocks_claim_stream() {
+    let (mut env, user) = setup_unpaused();
+    let vault_state = env.vault_state_address(&user);
+    let recipient = env.add_user();
+    let stream = program_address(&[b"stream", vault_state.as_ref(), (0u64).to_le_bytes().as_ref()]);
+    let start = env.now();
+
+    let create = vault_instruction(
+        accounts::CreateStream {
+            user,
+            vault_state,
+            vault: env.vault_address(&user),
+            stream,
+            system_program: system_program::ID,
+        },
+        instruction::CreateStream {
+            _vault_id: 0,
+            recipient,
+            total: 1_000_000,
+            start,
+            cliff: start,
+            end: start + 100,
+        }
+    );
+    let result = env.process(&create);
+    assert!(!result.program_result.is_err(), "Create stream should succeed");
+    env.warp_to_timestamp(start + 100);
+    pause(&mut env);
+
+    let claim = vault_instruction(
+        accounts::ClaimStream {
+            recipient,
+            stream,
+            vault_state,
+            vault: env.vault_address(&user),
+            payer: user,
+            config: find_config(),
+            treasury: find_treasury(),
+            system_program: system_program::ID,
+            event_authority: find_event_authority(),
+            program: anchor_vault_q3::id(),
+        },
+        instruction::ClaimStream {}
+    );
+    let failed = env.process(&claim);
+    assert_vault_error(&failed, VaultErrorCode::ProgramPaused);
+}
+
+#[test]
+fn test_vaults_work_before_init_config() {
+    // An upgraded deployment whose admin hasn't run `init_config` yet
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+    env.set_account(find_config(), Account::default());
+    let treasury_lamports = env.lamports(&find_treasury());
+
+    let result = env.deposit(&user, 1_000_000);
+    assert!(!result.program_result.is_err(), "Deposit should succeed without a config");
+    let user_lamports = env.lamports(&user);
+    let result = env.withdraw(&user, 1_000_000);
+    assert!(!result.program_result.is_err(), "Withdraw should succeed without a config");
+    env.assert_lamports(&user, user_lamports + 1_000_000);
+
+    let result = env.close(&user);
+    assert!(!result.program_result.is_err(), "Close should succeed without a config");
+    env.assert_lamports(&find_treasury(), treasury_lamports);
+}
@@ -0,0 +1,220 @@
+#![cfg(feature = "test-sbf")]
+
+use anchor_lang::{ prelude::*, system_program };
+use anchor_vault_q3::{ accounts, instruction, Delegation, VaultErrorCode };
+use solana_sdk::instruction::Instruction;
+
+pub mod utils;
+use utils::{ assert_vault_error, find_config, find_event_authority, find_treasury, vault_instruction, VaultTestEnv };
+
+fn find_delegation(vault_state: &Pubkey, delegate: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"delegation", vault_state.as_ref(), delegate.as_ref()],
+        &anchor_vault_q3::id()
+    ).0
+}
+
+fn approve_delegate_instruction(
+    user: Pubkey,
+    vault_state: Pubkey,
+    delegate: Pubkey,
+    allowance: u64,
+    expires_at: i64
+) -> Instruction {
+    vault_instruction(
+        accounts::ApproveDelegate {
+            user,
+            vault_state,
+            delegation: find_delegation(&vault_state, &delegate),
+            system_program: system_program::ID,
+        },
+        instruction::ApproveDelegate { _vault_id: 0, delegate, allowance, expires_at }
+    )
+}
+
+fn withdraw_as_delegate_instruction(
+    env: &VaultTestEnv,
+    owner: Pubkey,
+    delegate: Pubkey,
+    destination: Pubkey,
+    amount: u64
+) -> Instruction {
+    let vault_state = env.vault_state_address(&owner);
+    vault_instruction(
+        accounts::WithdrawAsDelegate {
+            delegate,
+            delegation: find_delegation(&vault_state, &delegate),
+            vault_state,
+            vault: env.vault_address(&owner),
+            destination,
+            config: find_config(),
+            treasury: find_treasury(),
+            system_program: system_program::ID,
+            event_authority: find_event_authority(),
+            program: anchor_vault_q3::id(),
+        },
+        instruction::WithdrawAsDelegate { amount }
+    )
+}
+
+#[test]
+fn test_withdraw_as_delegate() {
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+    let vault_state = env.vault_state_address(&user);
+    let delegate = Pubkey::new_unique();
+    let destination = Pubkey::new_unique();
+
+    let result = env.process(&approve_delegate_instruction(user, vault_state, delegate, 2_000_000, 0));
+    assert!(!result.program_result.is_err(), "Approve delegate should succeed");
+    let delegation: Delegation = env.decode(&find_delegation(&vault_state, &delegate));
+    assert_eq!(delegation.allowance, 2_000_000);
+
+    let result = env.process(&withdraw_as_delegate_instruction(&env, user, delegate, destination, 1_500_000));
+    assert!(!result.program_result.is_err(), "Withdraw as delegate should succeed");
+    env.assert_lamports(&destination, 1_500_000);
+    let delegation: Delegation = env.decode(&find_delegation(&vault_state, &delegate));
+    assert_eq!(delegation.allowance, 500_000);
+
+    // Only what is left of the allowance can be spent
+    let result = env.process(&withdraw_as_delegate_instruction(&env, user, delegate, destination, 1_000_000));
+    assert_vault_error(&result, VaultErrorCode::AllowanceExceeded);
+}
+
+#[test]
+fn test_withdraw_as_delegate_pays_fee() {
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+    env.set_withdraw_fee(50);
+    let vault_state = env.vault_state_address(&user);
+    let delegate = Pubkey::new_unique();
+    let destination = Pubkey::new_unique();
+    let amount = 1_000_000;
+
+    let result = env.process(&approve_delegate_instruction(user, vault_state, delegate, amount, 0));
+    assert!(!result.program_result.is_err(), "Approve delegate should succeed");
+
+    let vault_lamports_before = env.lamports(&env.vault_address(&user));
+    let treasury_lamports_before = env.lamports(&find_treasury());
+    let result = env.process(&withdraw_as_delegate_instruction(&env, user, delegate, destination, amount));
+    assert!(!result.program_result.is_err(), "Withdraw as delegate should succeed");
+
+    // The fee comes out of the amount, which is spent from the allowance in full
+    let fee = 5_000;
+    env.assert_lamports(&destination, amount - fee);
+    env.assert_lamports(&env.vault_address(&user), vault_lamports_before - amount);
+    env.assert_lamports(&find_treasury(), treasury_lamports_before + fee);
+    let delegation: Delegation = env.decode(&find_delegation(&vault_state, &delegate));
+    assert_eq!(delegation.allowance, 0);
+}
+
+#[test]
+fn test_withdraw_as_delegate_expired() {
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+    let vault_state = env.vault_state_address(&user);
+    let delegate = Pubkey::new_unique();
+    let expires_at = env.now() + 100;
+
+    let result = env.process(&approve_delegate_instruction(user, vault_state, delegate, 2_000_000, expires_at));
+    assert!(!result.program_result.is_err(), "Approve delegate should succeed");
+
+    env.warp_to_timestamp(expires_at);
+    let result = env.process(
+        &withdraw_as_delegate_instruction(&env, user, delegate, Pubkey::new_unique(), 1_000_000)
+    );
+    assert_vault_error(&result, VaultErrorCode::AllowanceExpired);
+}
+
+#[test]
+fn test_revoke_delegate() {
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+    let vault_state = env.vault_state_address(&user);
+    let delegate = Pubkey::new_unique();
+    let delegation = find_delegation(&vault_state, &delegate);
+
+    let result = env.process(&approve_delegate_instruction(user, vault_state, delegate, 2_000_000, 0));
+    assert!(!result.program_result.is_err(), "Approve delegate should succeed");
+    let user_lamports_before = env.lamports(&user);
+    let delegation_lamports = env.lamports(&delegation);
+
+    let revoke_instruction = vault_instruction(
+        accounts::RevokeDelegate { user, vault_state, delegation },
+        instruction::RevokeDelegate { _vault_id: 0, _delegate: delegate }
+    );
+    let result = env.process(&revoke_instruction);
+    assert!(!result.program_result.is_err(), "Revoke delegate should succeed");
+    env.assert_lamports(&user, user_lamports_before + delegation_lamports);
+
+    let result = env.process(
+        &withdraw_as_delegate_instruction(&env, user, delegate, Pubkey::new_unique(), 1_000_000)
+    );
+    assert!(result.program_result.is_err(), "Withdraw after revoke should fail");
+}
+
+#[test]
+fn test_delegation_lapses_with_ownership_change() {
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+    let vault_state = env.vault_state_address(&user);
+    let delegate = Pubkey::new_unique();
+    let new_owner = Pubkey::new_unique();
+
+    let result = env.process(&approve_delegate_instruction(user, vault_state, delegate, 2_000_000, 0));
+    assert!(!result.program_result.is_err(), "Approve delegate should succeed");
+    assert_eq!(env.vault_state(&user).delegation_count, 1);
+
+    let propose_instruction = vault_instruction(
+        accounts::ProposeOwner { user, vault_state },
+        instruction::ProposeOwner { _vault_id: 0, new_owner }
+    );
+    let accept_instruction = vault_instruction(
+        accounts::AcceptOwner {
+            new_owner,
+            vault_state,
+            event_authority: find_event_authority(),
+            program: anchor_vault_q3::id(),
+        },
+        instruction::AcceptOwner {}
+    );
+    for instruction in [propose_instruction, accept_instruction] {
+        let result = env.process(&instruction);
+        assert!(!result.program_result.is_err(), "Ownership transfer should succeed");
+    }
+
+    // The allowance was granted by the previous owner
+    let result = env.process(
+        &withdraw_as_delegate_instruction(&env, user, delegate, Pubkey::new_unique(), 1_000_000)
+    );
+    assert_vault_error(&result, VaultErrorCode::DelegationLapsed);
+
+    // The new owner can grant it again
+    let result = env.process(&approve_delegate_instruction(new_owner, vault_state, delegate, 2_000_000, 0));
+    assert!(!result.program_result.is_err(), "Approve by the new owner should succeed");
+    assert_eq!(env.vault_state(&user).delegation_count, 1, "Re-approving doesn't add a delegation");
+    let result = env.process(
+        &withdraw_as_delegate_instruction(&env, user, delegate, Pubkey::new_unique(), 1_000_000)
+    );
+    assert!(!result.program_result.is_err(), "Withdraw as delegate should succeed");
+}
+
+#[test]
+fn test_close_blocked_by_delegation() {
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+    let vault_state = env.vault_state_address(&user);
+    let delegate = Pubkey::new_unique();
+
+    let result = env.process(&approve_delegate_instruction(user, vault_state, delegate, 2_000_000, 0));
+    assert!(!result.program_result.is_err(), "Approve delegate should succeed");
+
+    // Closing would leave the delegation to a vault reopened at the same address
+    let result = env.close(&user);
+    assert_vault_error(&result, VaultErrorCode::DelegationsOutstanding);
+
+    let revoke_instruction = vault_instruction(
+        accounts::RevokeDelegate { user, vault_state, delegation: find_delegation(&vault_state, &delegate) },
+        instruction::RevokeDelegate { _vault_id: 0, _delegate: delegate }
+    );
+    let result = env.process(&revoke_instruction);
+    assert!(!result.program_result.is_err(), "Revoke delegate should succeed");
+    assert_eq!(env.vault_state(&user).delegation_count, 0);
+
+    let result = env.close(&user);
+    assert!(!result.program_result.is_err(), "Close should succeed once delegations are revoked");
+}
@@ -0,0 +1,92 @@
+#![cfg(feature = "test-sbf")]
+
+use anchor_lang::{ prelude::*, system_program };
+use anchor_vault_q3::{ accounts, instruction, Contribution };
+use solana_sdk::instruction::Instruction;
+
+pub mod utils;
+use utils::{ find_config, find_event_authority, find_treasury, vault_instruction, VaultTestEnv };
+
+const DEPOSITOR_INITIAL_LAMPORTS: u64 = 100_000_000;
+
+fn find_contribution(vault_state: &Pubkey, depositor: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"contribution", vault_state.as_ref(), depositor.as_ref()],
+        &anchor_vault_q3::id()
+    ).0
+}
+
+fn deposit_for_instruction(
+    env: &VaultTestEnv,
+    depositor: Pubkey,
+    owner: Pubkey,
+    vault_creator: Pubkey,
+    contribution: Option<Pubkey>,
+    amount: u64
+) -> Instruction {
+    vault_instruction(
+        accounts::DepositFor {
+            depositor,
+            vault: env.vault_address(&vault_creator),
+            vault_state: env.vault_state_address(&vault_creator),
+            contribution,
+            config: find_config(),
+            treasury: find_treasury(),
+            system_program: system_program::ID,
+            event_authority: find_event_authority(),
+            program: anchor_vault_q3::id(),
+        },
+        instruction::DepositFor { owner, _vault_id: 0, amount }
+    )
+}
+
+#[test]
+fn test_deposit_for_with_contribution() {
+    let (mut env, owner) = VaultTestEnv::with_initialized_vault();
+    let depositor = env.add_user_with_lamports(DEPOSITOR_INITIAL_LAMPORTS);
+    let vault_state = env.vault_state_address(&owner);
+    let vault = env.vault_address(&owner);
+    let contribution = find_contribution(&vault_state, &depositor);
+    let amount = 5_000_000; // 0.005 SOL
+    let vault_lamports_before = env.lamports(&vault);
+
+    let instruction = deposit_for_instruction(&env, depositor, owner, owner, Some(contribution), amount);
+    let result = env.process(&instruction);
+    assert!(!result.program_result.is_err(), "Deposit for another user should succeed");
+    let result = env.process(&instruction);
+    assert!(!result.program_result.is_err(), "Repeated deposit for another user should succeed");
+
+    env.assert_lamports(&vault, vault_lamports_before + 2 * amount);
+
+    let state = env.vault_state(&owner);
+    assert_eq!(state.total_deposited, 2 * amount);
+    assert_eq!(state.deposit_count, 2);
+
+    let contribution_state: Contribution = env.decode(&contribution);
+    assert_eq!(contribution_state.depositor, depositor);
+    assert_eq!(contribution_state.vault_state, vault_state);
+    assert_eq!(contribution_state.total_contributed, 2 * amount);
+    assert_eq!(contribution_state.contribution_count, 2);
+}
+
+#[test]
+fn test_deposit_for_without_contribution() {
+    let (mut env, owner) = VaultTestEnv::with_initialized_vault();
+    let depositor = env.add_user_with_lamports(DEPOSITOR_INITIAL_LAMPORTS);
+    let amount = 5_000_000; // 0.005 SOL
+
+    let result = env.process(&deposit_for_instruction(&env, depositor, owner, owner, None, amount));
+    assert!(!result.program_result.is_err(), "Deposit without a contribution account should succeed");
+    env.assert_lamports(&depositor, DEPOSITOR_INITIAL_LAMPORTS - amount);
+}
+
+#[test]
+fn test_deposit_for_wrong_owner() {
+    let (mut env, owner) = VaultTestEnv::with_initialized_vault();
+    let depositor = env.add_user_with_lamports(DEPOSITOR_INITIAL_LAMPORTS);
+
+    // The vault isn't derived from the given owner
+    let instruction = deposit_for_instruction(&env, depositor, Pubkey::new_unique(), owner, None, 5_000_000);
+    let result = env.process(&instruction);
+    assert!(result.program_result.is_err(), "Deposit for a mismatched owner should fail");
+}
@@ -0,0 +1,112 @@
+#![cfg(feature = "test-sbf")]
+
+use anchor_lang::{ prelude::*, system_program };
+use anchor_vault_q3::{ accounts, instruction, DestinationAllowList, VaultErrorCode };
+use anchor_vault_q3_client::{ close_ix, find_allow_list_address, withdraw_ix };
+use solana_sdk::instruction::Instruction;
+
+pub mod utils;
+use utils::{ assert_vault_error, vault_instruction, VaultTestEnv };
+
+fn add_destination_instruction(env: &VaultTestEnv, user: Pubkey, destination: Pubkey) -> Instruction {
+    let vault_state = env.vault_state_address(&user);
+    vault_instruction(
+        accounts::AddDestination {
+            user,
+            vault_state,
+            allow_list: find_allow_list_address(&vault_state).0,
+            system_program: system_program::ID,
+        },
+        instruction::AddDestination { _vault_id: 0, destination }
+    )
+}
+
+#[test]
+fn test_withdraw_to_allowed_destination() {
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+    let destination = Pubkey::new_unique();
+    let amount = 1_000_000;
+
+    let result = env.process(&add_destination_instruction(&env, user, destination));
+    assert!(!result.program_result.is_err(), "Adding a destination should succeed");
+
+    let allow_list: DestinationAllowList = env.decode(&find_allow_list_address(&env.vault_state_address(&user)).0);
+    assert_eq!(allow_list.destinations, vec![destination]);
+
+    let user_lamports_before = env.lamports(&user);
+    let result = env.process(&withdraw_ix(&user, &user, 0, amount, Some(destination)));
+    assert!(!result.program_result.is_err(), "Withdraw to an allowed destination should succeed");
+
+    env.assert_lamports(&destination, amount);
+    env.assert_lamports(&user, user_lamports_before);
+}
+
+#[test]
+fn test_withdraw_to_unlisted_destination() {
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+    let allowed = Pubkey::new_unique();
+
+    let result = env.process(&add_destination_instruction(&env, user, allowed));
+    assert!(!result.program_result.is_err(), "Adding a destination should succeed");
+
+    let result = env.process(&withdraw_ix(&user, &user, 0, 1_000_000, Some(Pubkey::new_unique())));
+    assert_vault_error(&result, VaultErrorCode::DestinationNotAllowed);
+
+    // Removing a destination revokes it
+    let vault_state = env.vault_state_address(&user);
+    let remove_instruction = vault_instruction(
+        accounts::RemoveDestination {
+            user,
+            vault_state,
+            allow_list: find_allow_list_address(&vault_state).0,
+        },
+        instruction::RemoveDestination { _vault_id: 0, destination: allowed }
+    );
+    let result = env.process(&remove_instruction);
+    assert!(!result.program_result.is_err(), "Removing a destination should succeed");
+
+    let result = env.process(&withdraw_ix(&user, &user, 0, 1_000_000, Some(allowed)));
+    assert_vault_error(&result, VaultErrorCode::DestinationNotAllowed);
+}
+
+#[test]
+fn test_close_to_allowed_destination() {
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+    let destination = Pubkey::new_unique();
+
+    let result = env.process(&add_destination_instruction(&env, user, destination));
+    assert!(!result.program_result.is_err(), "Adding a destination should succeed");
+    let allow_list = find_allow_list_address(&env.vault_state_address(&user)).0;
+    let vault_lamports = env.lamports(&env.vault_address(&user));
+    let vault_state_lamports = env.lamports(&env.vault_state_address(&user));
+    let allow_list_lamports = env.lamports(&allow_list);
+    let user_lamports_before = env.lamports(&user);
+
+    let result = env.process(&close_ix(&user, &user, 0, Some(destination)));
+    assert!(!result.program_result.is_err(), "Close to an allowed destination should succeed");
+
+    // The vault balance goes to the destination, the state and allow-list rent back to the user
+    env.assert_lamports(&destination, vault_lamports);
+    env.assert_lamports(&user, user_lamports_before + vault_state_lamports + allow_list_lamports);
+    env.assert_lamports(&allow_list, 0);
+}
+
+#[test]
+fn test_close_to_owner_closes_allow_list() {
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+
+    let result = env.process(&add_destination_instruction(&env, user, Pubkey::new_unique()));
+    assert!(!result.program_result.is_err(), "Adding a destination should succeed");
+    let allow_list = find_allow_list_address(&env.vault_state_address(&user)).0;
+    let allow_list_lamports = env.lamports(&allow_list);
+    let expected = env.lamports(&user) +
+        env.lamports(&env.vault_address(&user)) +
+        env.lamports(&env.vault_state_address(&user)) +
+        allow_list_lamports;
+
+    // A list left behind would still apply to a vault recreated at the same address
+    let result = env.close(&user);
+    assert!(!result.program_result.is_err(), "Close should succeed");
+    env.assert_lamports(&allow_list, 0);
+    env.assert_lamports(&user, expected);
+}
@@ -0,0 +1,121 @@
+#![cfg(feature = "test-sbf")]
+
+use anchor_lang::{ prelude::*, system_program };
+use anchor_vault_q3::{ accounts, instruction, VaultErrorCode };
+use solana_sdk::instruction::Instruction;
+
+pub mod utils;
+use utils::{
+    assert_vault_error,
+    config_account,
+    find_config,
+    find_event_authority,
+    find_treasury,
+    vault_instruction,
+    VaultTestEnv,
+};
+
+const DEPOSIT_FEE_BPS: u16 = 30;
+const WITHDRAW_FEE_BPS: u16 = 50;
+const CLOSE_FEE_LAMPORTS: u64 = 100_000;
+
+fn set_fees_instruction(admin: Pubkey, deposit_fee_bps: u16, withdraw_fee_bps: u16, close_fee_lamports: u64) -> Instruction {
+    vault_instruction(
+        accounts::UpdateConfig { admin, config: find_config() },
+        instruction::SetFees { deposit_fee_bps, withdraw_fee_bps, close_fee_lamports }
+    )
+}
+
+/// Sets up a deposited vault with fees configured by the returned admin
+fn setup_fees() -> (VaultTestEnv, Pubkey, Pubkey) {
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+    let admin = Pubkey::new_unique();
+    env.set_account(find_config(), config_account(admin, false));
+
+    let result = env.process(&set_fees_instruction(admin, DEPOSIT_FEE_BPS, WITHDRAW_FEE_BPS, CLOSE_FEE_LAMPORTS));
+    assert!(!result.program_result.is_err(), "Set fees should succeed");
+    (env, admin, user)
+}
+
+#[test]
+fn test_deposit_fee() {
+    let (mut env, _, user) = setup_fees();
+    let vault = env.vault_address(&user);
+    let amount = 1_000_001;
+    let user_lamports = env.lamports(&user);
+    let vault_lamports = env.lamports(&vault);
+
+    let result = env.deposit(&user, amount);
+    assert!(!result.program_result.is_err(), "Deposit should succeed");
+
+    // 30 bps of 1_000_001 is 3_000.003, rounded down
+    let fee = 3_000;
+    env.assert_lamports(&user, user_lamports - amount);
+    env.assert_lamports(&vault, vault_lamports + amount - fee);
+    env.assert_lamports(&find_treasury(), Rent::default().minimum_balance(0) + fee);
+}
+
+#[test]
+fn test_withdraw_fee() {
+    let (mut env, _, user) = setup_fees();
+    let vault = env.vault_address(&user);
+    let amount = 1_000_001;
+    let user_lamports = env.lamports(&user);
+    let vault_lamports = env.lamports(&vault);
+
+    let result = env.withdraw(&user, amount);
+    assert!(!result.program_result.is_err(), "Withdraw should succeed");
+
+    // 50 bps of 1_000_001 is 5_000.005, rounded down and taken out of the amount
+    let fee = 5_000;
+    env.assert_lamports(&user, user_lamports + amount - fee);
+    env.assert_lamports(&vault, vault_lamports - amount);
+    env.assert_lamports(&find_treasury(), Rent::default().minimum_balance(0) + fee);
+}
+
+#[test]
+fn test_close_fee_and_collect() {
+    let (mut env, admin, user) = setup_fees();
+    let expected_user_lamports = env.lamports(&user) +
+        env.lamports(&env.vault_address(&user)) +
+        env.lamports(&env.vault_state_address(&user)) -
+        CLOSE_FEE_LAMPORTS;
+
+    let result = env.close(&user);
+    assert!(!result.program_result.is_err(), "Close should succeed");
+    env.assert_lamports(&user, expected_user_lamports);
+    env.assert_lamports(&find_treasury(), Rent::default().minimum_balance(0) + CLOSE_FEE_LAMPORTS);
+
+    let destination = Pubkey::new_unique();
+    let collect_instruction = vault_instruction(
+        accounts::CollectFees {
+            admin,
+            config: find_config(),
+            treasury: find_treasury(),
+            destination,
+            system_program: system_program::ID,
+            event_authority: find_event_authority(),
+            program: anchor_vault_q3::id(),
+        },
+        instruction::CollectFees {}
+    );
+    let result = env.process(&collect_instruction);
+    assert!(!result.program_result.is_err(), "Collect fees should succeed");
+    env.assert_lamports(&destination, CLOSE_FEE_LAMPORTS);
+    env.assert_lamports(&find_treasury(), Rent::default().minimum_balance(0));
+
+    // The rent reserve stays behind
+    let failed = env.process(&collect_instruction);
+    assert_vault_error(&failed, VaultErrorCode::NoFeesToCollect);
+}
+
+#[test]
+fn test_set_fees_validation() {
+    let (mut env, admin, _) = setup_fees();
+
+    let failed = env.process(&set_fees_instruction(admin, 1_001, 0, 0));
+    assert_vault_error(&failed, VaultErrorCode::FeeTooHigh);
+
+    let failed = env.process(&set_fees_instruction(Pubkey::new_unique(), 0, 0, 0));
+    assert_vault_error(&failed, VaultErrorCode::NotConfigAdmin);
+}
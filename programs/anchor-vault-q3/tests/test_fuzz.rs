@@ -0,0 +1,259 @@
+#![cfg(feature = "test-sbf")]
+
+//! Runs random sequences of vault instructions across several users and checks
+//! invariants after every step. Instructions are sent by the vault's owner, by
+//! other users, without a signature or against another user's vault, with
+//! amounts ranging from zero to `u64::MAX`.
+
+use anchor_lang::solana_program::rent::Rent;
+use anchor_vault_q3_client::{ close_ix, deposit_ix, initialize_ix, withdraw_ix };
+use proptest::prelude::*;
+use solana_sdk::{ instruction::Instruction, pubkey::Pubkey };
+
+pub mod utils;
+use utils::VaultTestEnv;
+
+const USERS: usize = 3;
+const USER_LAMPORTS: u64 = 1_000_000_000;
+/// Comfortably covers the rent of a new vault, its state and the registry
+const INITIALIZE_COST: u64 = 10_000_000;
+
+#[derive(Clone, Copy, Debug)]
+enum Tamper {
+    None,
+    /// The sender's signature is missing
+    Unsigned,
+    /// The vault account belongs to the next user's vault
+    WrongVault,
+}
+
+#[derive(Clone, Debug)]
+enum Action {
+    Initialize {
+        owner: usize,
+        tamper: Tamper,
+    },
+    Deposit {
+        sender: usize,
+        owner: usize,
+        amount: u64,
+        tamper: Tamper,
+    },
+    Withdraw {
+        sender: usize,
+        owner: usize,
+        amount: u64,
+        tamper: Tamper,
+    },
+    Close {
+        sender: usize,
+        owner: usize,
+        tamper: Tamper,
+    },
+}
+
+impl Action {
+    fn owner(&self) -> usize {
+        match *self {
+            Action::Initialize { owner, .. } |
+            Action::Deposit { owner, .. } |
+            Action::Withdraw { owner, .. } |
+            Action::Close { owner, .. } => owner,
+        }
+    }
+
+    fn sender(&self) -> usize {
+        match *self {
+            Action::Initialize { owner, .. } => owner,
+            Action::Deposit { sender, .. } | Action::Withdraw { sender, .. } | Action::Close { sender, .. } =>
+                sender,
+        }
+    }
+
+    fn tamper(&self) -> Tamper {
+        match *self {
+            Action::Initialize { tamper, .. } |
+            Action::Deposit { tamper, .. } |
+            Action::Withdraw { tamper, .. } |
+            Action::Close { tamper, .. } => tamper,
+        }
+    }
+}
+
+fn tamper() -> impl Strategy<Value = Tamper> {
+    prop_oneof![8 => Just(Tamper::None), 1 => Just(Tamper::Unsigned), 1 => Just(Tamper::WrongVault)]
+}
+
+fn amount() -> impl Strategy<Value = u64> {
+    prop_oneof![
+        1 => Just(0u64),
+        1 => Just(1u64),
+        6 => 1..USER_LAMPORTS / 10,
+        1 => USER_LAMPORTS / 10..=USER_LAMPORTS * 2,
+        1 => Just(u64::MAX)
+    ]
+}
+
+/// Owner of the vault and the user sending the instruction, who is usually the owner
+fn sender_and_owner() -> impl Strategy<Value = (usize, usize)> {
+    (0..USERS, prop::option::weighted(0.2, 0..USERS)).prop_map(|(owner, sender)| (
+        sender.unwrap_or(owner),
+        owner,
+    ))
+}
+
+fn action() -> impl Strategy<Value = Action> {
+    prop_oneof![
+        2 => (0..USERS, tamper()).prop_map(|(owner, tamper)| Action::Initialize { owner, tamper }),
+        4 => (sender_and_owner(), amount(), tamper()).prop_map(|((sender, owner), amount, tamper)| {
+            Action::Deposit { sender, owner, amount, tamper }
+        }),
+        3 => (sender_and_owner(), amount(), tamper()).prop_map(|((sender, owner), amount, tamper)| {
+            Action::Withdraw { sender, owner, amount, tamper }
+        }),
+        1 => (sender_and_owner(), tamper()).prop_map(|((sender, owner), tamper)| Action::Close {
+            sender,
+            owner,
+            tamper,
+        })
+    ]
+}
+
+struct Fuzz {
+    env: VaultTestEnv,
+    users: Vec<Pubkey>,
+    total_lamports: u64,
+}
+
+impl Fuzz {
+    fn new() -> Self {
+        let mut env = VaultTestEnv::new();
+        let users = (0..USERS).map(|_| env.add_user_with_lamports(USER_LAMPORTS)).collect();
+        let total_lamports = Self::total_lamports(&env);
+        Self { env, users, total_lamports }
+    }
+
+    fn total_lamports(env: &VaultTestEnv) -> u64 {
+        env.accounts.values().map(|account| account.lamports as u128).sum::<u128>() as u64
+    }
+
+    fn vault(&self, user: usize) -> Pubkey {
+        self.env.vault_address(&self.users[user])
+    }
+
+    fn is_open(&self, user: usize) -> bool {
+        self.env
+            .account(&self.env.vault_state_address(&self.users[user]))
+            .is_some_and(|account| account.owner == anchor_vault_q3::id() && account.lamports > 0)
+    }
+
+    fn instruction(&self, action: &Action) -> Instruction {
+        let owner = &self.users[action.owner()];
+        let sender = &self.users[action.sender()];
+        let mut instruction = match *action {
+            Action::Initialize { .. } => initialize_ix(owner, 0, 0, 0),
+            Action::Deposit { amount, .. } => deposit_ix(sender, owner, 0, amount),
+            Action::Withdraw { amount, .. } => withdraw_ix(sender, owner, 0, amount, None),
+            Action::Close { .. } => close_ix(sender, owner, 0, None),
+        };
+
+        let vault = self.vault(action.owner());
+        let other_vault = self.vault((action.owner() + 1) % USERS);
+        for meta in &mut instruction.accounts {
+            match action.tamper() {
+                Tamper::None => {}
+                Tamper::Unsigned if meta.pubkey == *sender => {
+                    meta.is_signer = false;
+                }
+                Tamper::WrongVault if meta.pubkey == vault => {
+                    meta.pubkey = other_vault;
+                }
+                _ => {}
+            }
+        }
+        instruction
+    }
+
+    fn step(&mut self, action: &Action) {
+        let sender = action.sender();
+        let owner = action.owner();
+        let was_open = self.is_open(owner);
+        let sender_lamports = self.env.lamports(&self.users[sender]);
+        let before: Vec<(u64, u64)> = (0..USERS)
+            .map(|user| (self.env.lamports(&self.users[user]), self.env.lamports(&self.vault(user))))
+            .collect();
+
+        let result = self.env.process(&self.instruction(action));
+        let succeeded = !result.program_result.is_err();
+
+        assert_eq!(Self::total_lamports(&self.env), self.total_lamports, "Lamports not conserved by {:?}", action);
+
+        if succeeded {
+            assert!(
+                matches!(action.tamper(), Tamper::None),
+                "Tampered instruction succeeded: {:?}",
+                action
+            );
+            assert_eq!(sender, owner, "Instruction from a non-owner succeeded: {:?}", action);
+        }
+
+        // Only the sender's own vault and balance can change
+        for (user, (user_lamports, vault_lamports)) in before.into_iter().enumerate() {
+            if user != sender {
+                assert_eq!(self.env.lamports(&self.users[user]), user_lamports, "{:?}", action);
+                assert_eq!(self.env.lamports(&self.vault(user)), vault_lamports, "{:?}", action);
+            }
+        }
+
+        // Open vaults keep their rent reserve and closed ones are emptied
+        let vault_rent = Rent::default().minimum_balance(0);
+        for user in 0..USERS {
+            let vault_lamports = self.env.lamports(&self.vault(user));
+            if self.is_open(user) {
+                assert!(vault_lamports >= vault_rent, "Vault {} below rent after {:?}", user, action);
+            } else {
+                assert_eq!(vault_lamports, 0, "Closed vault {} holds lamports after {:?}", user, action);
+            }
+        }
+
+        // A vault that is closed, or was never created, can always be initialized
+        if let Action::Initialize { tamper: Tamper::None, .. } = action {
+            if !was_open && sender_lamports >= INITIALIZE_COST {
+                assert!(succeeded, "Initialize failed: {:?}", result.program_result);
+                let state = self.env.vault_state(&self.users[owner]);
+                assert_eq!(state.total_deposited, 0);
+                assert_eq!(state.total_withdrawn, 0);
+                assert_eq!(state.owner, self.users[owner]);
+            }
+        }
+    }
+}
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(64))]
+
+    #[test]
+    fn test_fuzz_instruction_sequences(actions in prop::collection::vec(action(), 1..40)) {
+        let mut fuzz = Fuzz::new();
+        for action in &actions {
+            fuzz.step(action);
+        }
+    }
+}
+
+#[test]
+fn test_fuzz_close_and_reinitialize() {
+    let mut fuzz = Fuzz::new();
+    for action in [
+        Action::Initialize { owner: 0, tamper: Tamper::None },
+        Action::Deposit { sender: 0, owner: 0, amount: 5_000_000, tamper: Tamper::None },
+        Action::Withdraw { sender: 1, owner: 0, amount: 1_000_000, tamper: Tamper::None },
+        Action::Close { sender: 0, owner: 0, tamper: Tamper::WrongVault },
+        Action::Close { sender: 0, owner: 0, tamper: Tamper::None },
+        Action::Initialize { owner: 0, tamper: Tamper::None },
+        Action::Deposit { sender: 0, owner: 0, amount: u64::MAX, tamper: Tamper::None },
+    ] {
+        fuzz.step(&action);
+    }
+    assert!(fuzz.is_open(0));
+}
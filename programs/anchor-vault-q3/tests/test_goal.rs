@@ -0,0 +1,136 @@
+#![cfg(feature = "test-sbf")]
+
+use anchor_lang::prelude::*;
+use anchor_vault_q3::{ accounts, instruction, VaultErrorCode };
+use solana_sdk::instruction::Instruction;
+
+pub mod utils;
+use utils::{ assert_vault_error, vault_instruction, VaultTestEnv };
+
+const GOAL: u64 = 3_000_000;
+
+fn set_goal_instruction(
+    env: &VaultTestEnv,
+    user: Pubkey,
+    goal_amount: u64,
+    goal_deadline: i64,
+    lock_withdrawals: bool
+) -> Instruction {
+    vault_instruction(
+        accounts::SetGoal { user, vault_state: env.vault_state_address(&user) },
+        instruction::SetGoal { _vault_id: 0, goal_amount, goal_deadline, lock_withdrawals }
+    )
+}
+
+#[test]
+fn test_goal_holds_withdrawals_until_reached() {
+    let (mut env, user) = VaultTestEnv::with_initialized_vault();
+
+    let result = env.process(&set_goal_instruction(&env, user, GOAL, 0, true));
+    assert!(!result.program_result.is_err(), "Setting a goal should succeed");
+
+    let result = env.deposit(&user, 2_000_000);
+    assert!(!result.program_result.is_err(), "Deposit should succeed");
+    let state = env.vault_state(&user);
+    assert!(!state.goal_reached());
+    assert!(state.is_goal_locked(&env.mollusk.sysvars.clock));
+
+    let withdraw = env.withdraw(&user, 1_000_000);
+    assert_vault_error(&withdraw, VaultErrorCode::GoalNotReached);
+
+    // The deposit that covers the goal completes it
+    let result = env.deposit(&user, 1_000_000);
+    assert!(!result.program_result.is_err(), "Deposit should succeed");
+    let state = env.vault_state(&user);
+    assert!(state.goal_reached());
+    assert_eq!(state.goal_reached_at, env.now());
+
+    let withdraw = env.withdraw(&user, 1_000_000);
+    assert!(!withdraw.program_result.is_err(), "Withdraw should succeed once the goal is reached");
+
+    // Dropping back below the goal doesn't hold withdrawals again
+    let state = env.vault_state(&user);
+    assert!(state.saved() < GOAL);
+    assert!(state.goal_reached());
+    let withdraw = env.withdraw(&user, 1_000_000);
+    assert!(!withdraw.program_result.is_err(), "Withdraw should still succeed");
+}
+
+#[test]
+fn test_goal_deadline_releases_withdrawals() {
+    let (mut env, user) = VaultTestEnv::with_initialized_vault();
+    let deadline = env.now() + 1_000;
+
+    let result = env.process(&set_goal_instruction(&env, user, GOAL, deadline, true));
+    assert!(!result.program_result.is_err(), "Setting a goal should succeed");
+    let result = env.deposit(&user, 1_000_000);
+    assert!(!result.program_result.is_err(), "Deposit should succeed");
+
+    env.warp_to_timestamp(deadline - 1);
+    let withdraw = env.withdraw(&user, 1_000_000);
+    assert_vault_error(&withdraw, VaultErrorCode::GoalNotReached);
+
+    env.warp_to_timestamp(deadline);
+    let withdraw = env.withdraw(&user, 1_000_000);
+    assert!(!withdraw.program_result.is_err(), "Withdraw should succeed after the deadline");
+    assert!(!env.vault_state(&user).goal_reached());
+}
+
+#[test]
+fn test_goal_without_lock_allows_withdrawals() {
+    let (mut env, user) = VaultTestEnv::with_initialized_vault();
+
+    let result = env.process(&set_goal_instruction(&env, user, GOAL, 0, false));
+    assert!(!result.program_result.is_err(), "Setting a goal should succeed");
+    let result = env.deposit(&user, 1_000_000);
+    assert!(!result.program_result.is_err(), "Deposit should succeed");
+
+    let withdraw = env.withdraw(&user, 1_000_000);
+    assert!(!withdraw.program_result.is_err(), "Withdraw should succeed");
+}
+
+#[test]
+fn test_goal_already_covered_is_reached() {
+    let (mut env, user) = VaultTestEnv::with_initialized_vault();
+
+    let result = env.deposit(&user, GOAL);
+    assert!(!result.program_result.is_err(), "Deposit should succeed");
+    let result = env.process(&set_goal_instruction(&env, user, GOAL, 0, true));
+    assert!(!result.program_result.is_err(), "Setting a goal should succeed");
+    assert!(env.vault_state(&user).goal_reached());
+}
+
+#[test]
+fn test_held_goal_can_only_be_tightened() {
+    let (mut env, user) = VaultTestEnv::with_initialized_vault();
+    let deadline = env.now() + 1_000;
+
+    let result = env.process(&set_goal_instruction(&env, user, GOAL, deadline, true));
+    assert!(!result.program_result.is_err(), "Setting a goal should succeed");
+
+    for (goal_amount, goal_deadline, lock_withdrawals) in [
+        (0, 0, false),
+        (GOAL - 1, deadline, true),
+        (GOAL, deadline - 1, true),
+    ] {
+        let loosened = env.process(
+            &set_goal_instruction(&env, user, goal_amount, goal_deadline, lock_withdrawals)
+        );
+        assert_vault_error(&loosened, VaultErrorCode::GoalCannotBeLoosened);
+    }
+
+    let tightened = env.process(&set_goal_instruction(&env, user, GOAL + 1, deadline + 1, true));
+    assert!(!tightened.program_result.is_err(), "Tightening the goal should succeed");
+    let state = env.vault_state(&user);
+    assert_eq!(state.goal_amount, GOAL + 1);
+    assert_eq!(state.goal_deadline, deadline + 1);
+}
+
+#[test]
+fn test_goal_deadline_in_past_fails() {
+    let (mut env, user) = VaultTestEnv::with_initialized_vault();
+    let now = env.now();
+
+    let result = env.process(&set_goal_instruction(&env, user, GOAL, now, true));
+    assert_vault_error(&result, VaultErrorCode::InvalidGoal);
+}
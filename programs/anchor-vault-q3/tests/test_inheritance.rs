@@ -0,0 +1,92 @@
+#![cfg(feature = "test-sbf")]
+
+use anchor_lang::prelude::*;
+use anchor_vault_q3::{ accounts, instruction, VaultErrorCode, VaultState };
+use solana_sdk::instruction::Instruction;
+
+pub mod utils;
+use utils::{ assert_vault_error, find_event_authority, vault_instruction, VaultTestEnv };
+
+const INACTIVITY_PERIOD: i64 = VaultState::MIN_INACTIVITY_PERIOD;
+
+fn set_beneficiary_instruction(user: Pubkey, vault_state: Pubkey, beneficiary: Pubkey, inactivity_period: i64) -> Instruction {
+    vault_instruction(
+        accounts::SetBeneficiary { user, vault_state },
+        instruction::SetBeneficiary { _vault_id: 0, beneficiary, inactivity_period }
+    )
+}
+
+fn claim_inheritance_instruction(beneficiary: Pubkey, vault_state: Pubkey) -> Instruction {
+    vault_instruction(
+        accounts::ClaimInheritance {
+            beneficiary,
+            vault_state,
+            event_authority: find_event_authority(),
+            program: anchor_vault_q3::id(),
+        },
+        instruction::ClaimInheritance {}
+    )
+}
+
+#[test]
+fn test_claim_inheritance_after_inactivity() {
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+    let vault_state = env.vault_state_address(&user);
+    let beneficiary = Pubkey::new_unique();
+
+    let result = env.process(&set_beneficiary_instruction(user, vault_state, beneficiary, INACTIVITY_PERIOD));
+    assert!(!result.program_result.is_err(), "Set beneficiary should succeed");
+    let start = env.now();
+    assert_eq!(env.vault_state(&user).last_heartbeat, start);
+
+    env.warp_to_timestamp(start + INACTIVITY_PERIOD - 1);
+    let failed = env.process(&claim_inheritance_instruction(beneficiary, vault_state));
+    assert_vault_error(&failed, VaultErrorCode::OwnerStillActive);
+
+    let failed = env.process(&claim_inheritance_instruction(Pubkey::new_unique(), vault_state));
+    assert_vault_error(&failed, VaultErrorCode::NotBeneficiary);
+
+    env.warp_to_timestamp(start + INACTIVITY_PERIOD);
+    let result = env.process(&claim_inheritance_instruction(beneficiary, vault_state));
+    assert!(!result.program_result.is_err(), "Claim inheritance should succeed");
+    let state = env.vault_state(&user);
+    assert_eq!(state.owner, beneficiary);
+    assert_eq!(state.beneficiary, Pubkey::default());
+}
+
+#[test]
+fn test_heartbeat_postpones_claim() {
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+    let vault_state = env.vault_state_address(&user);
+    let beneficiary = Pubkey::new_unique();
+    let start = env.now();
+
+    let result = env.process(&set_beneficiary_instruction(user, vault_state, beneficiary, INACTIVITY_PERIOD));
+    assert!(!result.program_result.is_err(), "Set beneficiary should succeed");
+
+    env.warp_to_timestamp(start + INACTIVITY_PERIOD - 1);
+    let heartbeat_instruction = vault_instruction(
+        accounts::SetBeneficiary { user, vault_state },
+        instruction::Heartbeat { _vault_id: 0 }
+    );
+    let result = env.process(&heartbeat_instruction);
+    assert!(!result.program_result.is_err(), "Heartbeat should succeed");
+
+    env.warp_to_timestamp(start + INACTIVITY_PERIOD);
+    let failed = env.process(&claim_inheritance_instruction(beneficiary, vault_state));
+    assert_vault_error(&failed, VaultErrorCode::OwnerStillActive);
+}
+
+#[test]
+fn test_set_beneficiary_validation() {
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+    let vault_state = env.vault_state_address(&user);
+
+    let failed = env.process(
+        &set_beneficiary_instruction(user, vault_state, Pubkey::new_unique(), INACTIVITY_PERIOD - 1)
+    );
+    assert_vault_error(&failed, VaultErrorCode::InactivityPeriodTooShort);
+
+    let failed = env.process(&set_beneficiary_instruction(user, vault_state, user, INACTIVITY_PERIOD));
+    assert_vault_error(&failed, VaultErrorCode::InvalidBeneficiary);
+}
@@ -0,0 +1,66 @@
+#![cfg(feature = "test-sbf")]
+
+use anchor_lang::prelude::*;
+use anchor_vault_q3::{ accounts, instruction, VaultErrorCode };
+
+pub mod utils;
+use utils::{ assert_vault_error, vault_instruction, VaultTestEnv, DEPOSIT_AMOUNT };
+
+const UNLOCK_TIMESTAMP: i64 = 1_000;
+
+fn setup_locked_vault() -> (VaultTestEnv, Pubkey) {
+    let mut env = VaultTestEnv::new();
+    let user = env.add_user();
+    let result = env.initialize_vault(&user, 0, UNLOCK_TIMESTAMP, 0);
+    assert!(!result.program_result.is_err(), "Initializing a locked vault should succeed");
+    (env, user)
+}
+
+#[test]
+fn test_withdraw_locked_vault() {
+    let (mut env, user) = setup_locked_vault();
+
+    let result = env.deposit(&user, DEPOSIT_AMOUNT);
+    assert!(!result.program_result.is_err(), "Deposit into a locked vault should succeed");
+
+    // Withdrawing before the unlock timestamp fails
+    env.warp_to_timestamp(UNLOCK_TIMESTAMP - 1);
+    let result = env.withdraw(&user, DEPOSIT_AMOUNT);
+    assert_vault_error(&result, VaultErrorCode::VaultLocked);
+
+    // Warp past the unlock timestamp
+    env.warp_to_timestamp(UNLOCK_TIMESTAMP);
+    let result = env.withdraw(&user, DEPOSIT_AMOUNT);
+    assert!(!result.program_result.is_err(), "Withdraw should succeed once unlocked");
+}
+
+#[test]
+fn test_set_lock_only_extends() {
+    let (mut env, user) = setup_locked_vault();
+    let vault_state = env.vault_state_address(&user);
+
+    let set_lock_instruction = |unlock_timestamp: i64, unlock_slot: u64| {
+        vault_instruction(
+            accounts::SetLock { user, vault_state },
+            instruction::SetLock { _vault_id: 0, unlock_timestamp, unlock_slot }
+        )
+    };
+
+    // Shortening the lock fails
+    let result = env.process(&set_lock_instruction(UNLOCK_TIMESTAMP - 1, 0));
+    assert_vault_error(&result, VaultErrorCode::LockCannotBeShortened);
+
+    // Extending the lock by timestamp and slot succeeds
+    let result = env.process(&set_lock_instruction(UNLOCK_TIMESTAMP * 2, 100));
+    assert!(!result.program_result.is_err(), "Extending the lock should succeed");
+
+    // Past the original timestamp but still before the extended lock
+    env.warp_to_timestamp(UNLOCK_TIMESTAMP * 2);
+    env.warp_to_slot(99);
+    let result = env.close(&user);
+    assert_vault_error(&result, VaultErrorCode::VaultLocked);
+
+    env.warp_to_slot(100);
+    let result = env.close(&user);
+    assert!(!result.program_result.is_err(), "Close should succeed once unlocked");
+}
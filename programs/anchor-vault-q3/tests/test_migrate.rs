@@ -0,0 +1,106 @@
+#![cfg(feature = "test-sbf")]
+
+use anchor_lang::{ prelude::*, solana_program::rent::Rent, system_program };
+use anchor_vault_q3::{ accounts, instruction, VaultErrorCode, VaultRegistry, VaultState };
+use anchor_vault_q3_client::{ find_vault_address, find_vault_state_address };
+use solana_sdk::{ account::Account, instruction::Instruction };
+
+pub mod utils;
+use utils::{ assert_vault_error, find_registry, vault_instruction, VaultTestEnv, USER_INITIAL_LAMPORTS };
+
+fn migrate_instruction(user: Pubkey, vault_id: u64) -> Instruction {
+    vault_instruction(
+        accounts::MigrateVaultState {
+            user,
+            vault_state: find_vault_state_address(&user, vault_id).0,
+            registry: find_registry(&user),
+            system_program: system_program::ID,
+        },
+        instruction::MigrateVaultState { _vault_id: vault_id }
+    )
+}
+
+#[test]
+fn test_migrate_legacy_vault_state() {
+    let mut env = VaultTestEnv::new();
+    let user = env.add_user();
+    let (vault_state, vault_state_bump) = find_vault_state_address(&user, 0);
+    let (_, vault_bump) = find_vault_address(&vault_state);
+
+    // Build an account with the unversioned layout: discriminator + bump + vault_bump
+    let rent = Rent::default();
+    let mut legacy_data = VaultState::DISCRIMINATOR.to_vec();
+    legacy_data.extend_from_slice(&[vault_state_bump, vault_bump]);
+    let legacy_rent = rent.minimum_balance(legacy_data.len());
+    let mut legacy_account = Account::new(legacy_rent, legacy_data.len(), &anchor_vault_q3::id());
+    legacy_account.data = legacy_data;
+    env.set_account(vault_state, legacy_account);
+
+    let result = env.process(&migrate_instruction(user, 0));
+    assert!(!result.program_result.is_err(), "Migration should succeed");
+
+    // Verify the account was grown to the current layout and kept its bumps
+    let vault_state_space = 8 + VaultState::INIT_SPACE;
+    let vault_state_account = env.account(&vault_state).unwrap();
+    assert_eq!(vault_state_account.data.len(), vault_state_space);
+    assert_eq!(vault_state_account.lamports, rent.minimum_balance(vault_state_space));
+
+    let state = env.vault_state(&user);
+    assert_eq!(state.bump, vault_state_bump);
+    assert_eq!(state.vault_bump, vault_bump);
+    assert_eq!(state.version, VaultState::CURRENT_VERSION);
+    assert_eq!(state.total_deposited, 0);
+    assert_eq!(state.creator, user);
+    assert_eq!(state.owner, user);
+
+    // The pre-existing vault is registered as the default vault
+    let registry: VaultRegistry = env.decode(&find_registry(&user));
+    assert_eq!(registry.vault_count, 1);
+    assert_eq!(registry.open_vaults, 1);
+
+    // User paid the rent difference and the registry rent
+    let registry_rent = rent.minimum_balance(8 + VaultRegistry::INIT_SPACE);
+    env.assert_lamports(
+        &user,
+        USER_INITIAL_LAMPORTS - (rent.minimum_balance(vault_state_space) - legacy_rent) - registry_rent
+    );
+}
+
+#[test]
+fn test_migrate_current_vault_state_fails() {
+    let (mut env, user) = VaultTestEnv::with_initialized_vault();
+
+    let result = env.process(&migrate_instruction(user, 0));
+    assert_vault_error(&result, VaultErrorCode::VaultAlreadyMigrated);
+}
+
+#[test]
+fn test_migrate_non_default_vault() {
+    let mut env = VaultTestEnv::new();
+    let user = env.add_user();
+    for vault_id in 0..2 {
+        let result = env.initialize_vault(&user, vault_id, 0, 0);
+        assert!(!result.program_result.is_err(), "Initialize should succeed");
+    }
+    let vault_state = find_vault_state_address(&user, 1).0;
+
+    // Roll vault 1 back to the layout before guardian sets were tracked
+    let has_guardians_field = 1;
+    let mut account = env.account(&vault_state).unwrap().clone();
+    account.data.truncate(account.data.len() - has_guardians_field);
+    account.data[VaultState::VERSION_OFFSET] = VaultState::CURRENT_VERSION - 1;
+    env.set_account(vault_state, account);
+
+    let result = env.process(&migrate_instruction(user, 1));
+    assert!(!result.program_result.is_err(), "Migrating vault 1 should succeed");
+
+    assert_eq!(env.account(&vault_state).unwrap().data.len(), 8 + VaultState::INIT_SPACE);
+    let state: VaultState = env.decode(&vault_state);
+    assert_eq!(state.version, VaultState::CURRENT_VERSION);
+    assert_eq!(state.vault_id, 1);
+    assert!(!state.has_guardians);
+
+    // The registry already counted both vaults
+    let registry: VaultRegistry = env.decode(&find_registry(&user));
+    assert_eq!(registry.vault_count, 2);
+}
@@ -0,0 +1,42 @@
+#![cfg(feature = "test-sbf")]
+
+use anchor_vault_q3::{ VaultErrorCode, VaultRegistry, VaultState };
+use anchor_vault_q3_client::{ close_ix, find_vault_state_address };
+
+pub mod utils;
+use utils::{ assert_vault_error, find_registry, VaultTestEnv };
+
+#[test]
+fn test_multiple_vaults_per_user() {
+    // Vault 0 keeps the original `[b"state", user]` address
+    let (mut env, user) = VaultTestEnv::with_initialized_vault();
+
+    let vault_state = find_vault_state_address(&user, 1).0;
+    assert_ne!(vault_state, env.vault_state_address(&user), "Each vault id should get its own state PDA");
+
+    let result = env.initialize_vault(&user, 1, 0, 0);
+    assert!(!result.program_result.is_err(), "Initializing a second vault should succeed");
+
+    let state: VaultState = env.decode(&vault_state);
+    assert_eq!(state.vault_id, 1);
+
+    let registry: VaultRegistry = env.decode(&find_registry(&user));
+    assert_eq!(registry.vault_count, 2);
+    assert_eq!(registry.open_vaults, 2);
+
+    // Closing the second vault keeps its id reserved
+    let result = env.process(&close_ix(&user, &user, 1, None));
+    assert!(!result.program_result.is_err(), "Closing the second vault should succeed");
+
+    let registry: VaultRegistry = env.decode(&find_registry(&user));
+    assert_eq!(registry.vault_count, 2);
+    assert_eq!(registry.open_vaults, 1);
+}
+
+#[test]
+fn test_vault_id_cannot_skip() {
+    let (mut env, user) = VaultTestEnv::with_initialized_vault();
+
+    let result = env.initialize_vault(&user, 2, 0, 0);
+    assert_vault_error(&result, VaultErrorCode::InvalidVaultId);
+}
@@ -0,0 +1,343 @@
+#![cfg(feature = "test-sbf")]
+
+use anchor_lang::{ prelude::*, system_program };
+use anchor_vault_q3::{ accounts, instruction, Proposal, ProposalAction, VaultErrorCode, VaultMultisig };
+use anchor_vault_q3_client::find_allow_list_address;
+use mollusk_svm::result::InstructionResult;
+use solana_sdk::instruction::Instruction;
+
+pub mod utils;
+use utils::{
+    assert_vault_error,
+    find_config,
+    find_event_authority,
+    find_registry,
+    find_treasury,
+    vault_instruction,
+    VaultTestEnv,
+};
+
+fn find_multisig(vault_state: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"multisig", vault_state.as_ref()], &anchor_vault_q3::id()).0
+}
+
+fn find_proposal(multisig: &Pubkey, index: u64) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"proposal", multisig.as_ref(), index.to_le_bytes().as_ref()],
+        &anchor_vault_q3::id()
+    ).0
+}
+
+fn create_multisig_instruction(user: Pubkey, vault_state: Pubkey, owners: Vec<Pubkey>, threshold: u8) -> Instruction {
+    vault_instruction(
+        accounts::CreateMultisig {
+            user,
+            vault_state,
+            multisig: find_multisig(&vault_state),
+            system_program: system_program::ID,
+        },
+        instruction::CreateMultisig { _vault_id: 0, owners, threshold }
+    )
+}
+
+struct MultisigVault {
+    env: VaultTestEnv,
+    user: Pubkey,
+    owners: Vec<Pubkey>,
+}
+
+/// Sets up a deposited vault guarded by a 2-of-3 multisig
+fn setup_multisig_vault() -> MultisigVault {
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+    let owners: Vec<Pubkey> = (0..3).map(|_| env.add_user()).collect();
+
+    let vault_state = env.vault_state_address(&user);
+    let result = env.process(&create_multisig_instruction(user, vault_state, owners.clone(), 2));
+    assert!(!result.program_result.is_err(), "Create multisig should succeed");
+
+    MultisigVault { env, user, owners }
+}
+
+impl MultisigVault {
+    fn vault_state(&self) -> Pubkey {
+        self.env.vault_state_address(&self.user)
+    }
+
+    fn multisig(&self) -> Pubkey {
+        find_multisig(&self.vault_state())
+    }
+
+    fn proposal(&self, index: u64) -> Pubkey {
+        find_proposal(&self.multisig(), index)
+    }
+
+    fn propose(&mut self, proposer: Pubkey, action: ProposalAction, destination: Pubkey) -> InstructionResult {
+        let index = self.env.decode::<VaultMultisig>(&self.multisig()).proposal_count;
+        let instruction = vault_instruction(
+            accounts::ProposeWithdrawal {
+                proposer,
+                multisig: self.multisig(),
+                proposal: self.proposal(index),
+                system_program: system_program::ID,
+            },
+            instruction::ProposeWithdrawal { action, destination }
+        );
+        self.env.process(&instruction)
+    }
+
+    fn approve(&mut self, owner: Pubkey, index: u64) -> InstructionResult {
+        let instruction = vault_instruction(
+            accounts::Approve { owner, multisig: self.multisig(), proposal: self.proposal(index) },
+            instruction::Approve {}
+        );
+        self.env.process(&instruction)
+    }
+
+    fn execute(&mut self, executor: Pubkey, index: u64, proposer: Pubkey, destination: Pubkey) -> InstructionResult {
+        let instruction = vault_instruction(
+            accounts::Execute {
+                executor,
+                multisig: self.multisig(),
+                proposal: self.proposal(index),
+                proposer,
+                vault_state: self.vault_state(),
+                vault: self.env.vault_address(&self.user),
+                destination,
+                registry: find_registry(&self.user),
+                allow_list: find_allow_list_address(&self.vault_state()).0,
+                config: find_config(),
+                treasury: find_treasury(),
+                system_program: system_program::ID,
+                event_authority: find_event_authority(),
+                program: anchor_vault_q3::id(),
+            },
+            instruction::Execute {}
+        );
+        self.env.process(&instruction)
+    }
+
+    fn cancel(&mut self, proposer: Pubkey, index: u64) -> InstructionResult {
+        let instruction = vault_instruction(
+            accounts::Cancel { proposer, multisig: self.multisig(), proposal: self.proposal(index) },
+            instruction::Cancel {}
+        );
+        self.env.process(&instruction)
+    }
+}
+
+#[test]
+fn test_create_multisig() {
+    let test = setup_multisig_vault();
+
+    let multisig: VaultMultisig = test.env.decode(&test.multisig());
+    assert_eq!(multisig.owners, test.owners);
+    assert_eq!(multisig.threshold, 2);
+    assert_eq!(multisig.creator, test.user);
+
+    assert_eq!(test.env.vault_state(&test.user).multisig, test.multisig());
+}
+
+#[test]
+fn test_create_multisig_invalid_threshold() {
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+    let vault_state = env.vault_state_address(&user);
+    let owners = vec![Pubkey::new_unique(), Pubkey::new_unique()];
+
+    let result = env.process(&create_multisig_instruction(user, vault_state, owners.clone(), 3));
+    assert_vault_error(&result, VaultErrorCode::InvalidThreshold);
+
+    let result = env.process(&create_multisig_instruction(user, vault_state, vec![owners[0], owners[0]], 1));
+    assert_vault_error(&result, VaultErrorCode::InvalidOwners);
+}
+
+#[test]
+fn test_owner_withdraw_blocked_by_multisig() {
+    let mut test = setup_multisig_vault();
+
+    let result = test.env.withdraw(&test.user, 1_000_000);
+    assert_vault_error(&result, VaultErrorCode::MultisigRequired);
+}
+
+#[test]
+fn test_multisig_withdrawal() {
+    let mut test = setup_multisig_vault();
+    let proposer = test.owners[0];
+    let destination = Pubkey::new_unique();
+    let amount = 1_000_000;
+
+    let result = test.propose(proposer, ProposalAction::Withdraw { amount }, destination);
+    assert!(!result.program_result.is_err(), "Propose should succeed");
+
+    // The proposer's own approval is not enough for a 2-of-3 multisig
+    let result = test.execute(proposer, 0, proposer, destination);
+    assert_vault_error(&result, VaultErrorCode::NotEnoughApprovals);
+
+    let result = test.approve(proposer, 0);
+    assert_vault_error(&result, VaultErrorCode::AlreadyApproved);
+
+    let result = test.approve(Pubkey::new_unique(), 0);
+    assert_vault_error(&result, VaultErrorCode::NotMultisigOwner);
+
+    let result = test.approve(test.owners[1], 0);
+    assert!(!result.program_result.is_err(), "Approve should succeed");
+    let proposal: Proposal = test.env.decode(&test.proposal(0));
+    assert_eq!(proposal.approvals, vec![proposer, test.owners[1]]);
+
+    let vault = test.env.vault_address(&test.user);
+    let vault_lamports_before = test.env.lamports(&vault);
+    let result = test.execute(test.owners[2], 0, proposer, destination);
+    assert!(!result.program_result.is_err(), "Execute should succeed");
+
+    test.env.assert_lamports(&destination, amount);
+    test.env.assert_lamports(&vault, vault_lamports_before - amount);
+    assert_eq!(test.env.lamports(&test.proposal(0)), 0, "Executed proposal should be closed");
+}
+
+#[test]
+fn test_multisig_withdrawal_pays_fee() {
+    let mut test = setup_multisig_vault();
+    test.env.set_withdraw_fee(50);
+    let destination = Pubkey::new_unique();
+    let amount = 1_000_000;
+
+    let result = test.propose(test.owners[0], ProposalAction::Withdraw { amount }, destination);
+    assert!(!result.program_result.is_err(), "Propose should succeed");
+    let result = test.approve(test.owners[1], 0);
+    assert!(!result.program_result.is_err(), "Approve should succeed");
+
+    let vault = test.env.vault_address(&test.user);
+    let vault_lamports_before = test.env.lamports(&vault);
+    let treasury_lamports_before = test.env.lamports(&find_treasury());
+    let result = test.execute(test.owners[0], 0, test.owners[0], destination);
+    assert!(!result.program_result.is_err(), "Execute should succeed");
+
+    // 50 bps of the amount goes to the treasury, like a regular withdrawal
+    let fee = 5_000;
+    test.env.assert_lamports(&destination, amount - fee);
+    test.env.assert_lamports(&vault, vault_lamports_before - amount);
+    test.env.assert_lamports(&find_treasury(), treasury_lamports_before + fee);
+}
+
+#[test]
+fn test_multisig_close() {
+    let mut test = setup_multisig_vault();
+    let proposer = test.owners[1];
+    let destination = Pubkey::new_unique();
+
+    let result = test.propose(proposer, ProposalAction::Close, destination);
+    assert!(!result.program_result.is_err(), "Propose should succeed");
+    let result = test.approve(test.owners[2], 0);
+    assert!(!result.program_result.is_err(), "Approve should succeed");
+
+    let vault = test.env.vault_address(&test.user);
+    let vault_lamports = test.env.lamports(&vault);
+    let result = test.execute(proposer, 0, proposer, destination);
+    assert!(!result.program_result.is_err(), "Execute close should succeed");
+
+    test.env.assert_lamports(&vault, 0);
+    test.env.assert_lamports(&test.vault_state(), 0);
+    test.env.assert_lamports(&test.multisig(), 0);
+    assert!(test.env.lamports(&destination) > vault_lamports);
+}
+
+#[test]
+fn test_multisig_close_closes_allow_list() {
+    let mut test = setup_multisig_vault();
+    let destination = Pubkey::new_unique();
+    let vault_state = test.vault_state();
+    let allow_list = find_allow_list_address(&vault_state).0;
+
+    let add = vault_instruction(
+        accounts::AddDestination { user: test.user, vault_state, allow_list, system_program: system_program::ID },
+        instruction::AddDestination { _vault_id: 0, destination: Pubkey::new_unique() }
+    );
+    let result = test.env.process(&add);
+    assert!(!result.program_result.is_err(), "Adding a destination should succeed");
+    let allow_list_lamports = test.env.lamports(&allow_list);
+
+    let result = test.propose(test.owners[0], ProposalAction::Close, destination);
+    assert!(!result.program_result.is_err(), "Propose should succeed");
+    let result = test.approve(test.owners[1], 0);
+    assert!(!result.program_result.is_err(), "Approve should succeed");
+    let vault_lamports = test.env.lamports(&test.env.vault_address(&test.user));
+    let state_lamports = test.env.lamports(&vault_state) + test.env.lamports(&test.multisig());
+
+    let result = test.execute(test.owners[0], 0, test.owners[0], destination);
+    assert!(!result.program_result.is_err(), "Execute close should succeed");
+    test.env.assert_lamports(&allow_list, 0);
+    test.env.assert_lamports(&destination, vault_lamports + state_lamports + allow_list_lamports);
+}
+
+#[test]
+fn test_multisig_close_blocked_by_open_proposals() {
+    let mut test = setup_multisig_vault();
+    let destination = Pubkey::new_unique();
+
+    let result = test.propose(test.owners[0], ProposalAction::Withdraw { amount: 1_000_000 }, destination);
+    assert!(!result.program_result.is_err(), "Propose withdrawal should succeed");
+    let result = test.propose(test.owners[1], ProposalAction::Close, destination);
+    assert!(!result.program_result.is_err(), "Propose close should succeed");
+    let result = test.approve(test.owners[2], 1);
+    assert!(!result.program_result.is_err(), "Approve should succeed");
+    assert_eq!(test.env.decode::<VaultMultisig>(&test.multisig()).open_proposals, 2);
+
+    // The open withdrawal would outlive the multisig
+    let result = test.execute(test.owners[1], 1, test.owners[1], destination);
+    assert_vault_error(&result, VaultErrorCode::ProposalsOutstanding);
+
+    let result = test.cancel(test.owners[0], 0);
+    assert!(!result.program_result.is_err(), "Cancel should succeed");
+    let result = test.execute(test.owners[1], 1, test.owners[1], destination);
+    assert!(!result.program_result.is_err(), "Execute close should succeed");
+    test.env.assert_lamports(&test.multisig(), 0);
+}
+
+#[test]
+fn test_approvals_from_former_owners_dont_count() {
+    let mut test = setup_multisig_vault();
+    let destination = Pubkey::new_unique();
+
+    let result = test.propose(test.owners[0], ProposalAction::Withdraw { amount: 1_000_000 }, destination);
+    assert!(!result.program_result.is_err(), "Propose should succeed");
+    let result = test.approve(test.owners[1], 0);
+    assert!(!result.program_result.is_err(), "Approve should succeed");
+
+    // Swap out both approvers, keeping the threshold at 2
+    let new_owners = vec![test.owners[2], test.env.add_user()];
+    let mut multisig: VaultMultisig = test.env.decode(&test.multisig());
+    multisig.owners = new_owners.clone();
+    let mut account = test.env.account(&test.multisig()).unwrap().clone();
+    let mut data = Vec::with_capacity(account.data.len());
+    multisig.try_serialize(&mut data).unwrap();
+    data.resize(account.data.len(), 0);
+    account.data = data;
+    test.env.set_account(test.multisig(), account);
+
+    let result = test.execute(new_owners[0], 0, test.owners[0], destination);
+    assert_vault_error(&result, VaultErrorCode::NotEnoughApprovals);
+
+    for owner in &new_owners {
+        let result = test.approve(*owner, 0);
+        assert!(!result.program_result.is_err(), "Approve should succeed");
+    }
+    let result = test.execute(new_owners[0], 0, test.owners[0], destination);
+    assert!(!result.program_result.is_err(), "Execute should succeed");
+    test.env.assert_lamports(&destination, 1_000_000);
+}
+
+#[test]
+fn test_cancel_proposal() {
+    let mut test = setup_multisig_vault();
+    let proposer = test.owners[0];
+
+    let result = test.propose(proposer, ProposalAction::Withdraw { amount: 1_000_000 }, Pubkey::new_unique());
+    assert!(!result.program_result.is_err(), "Propose should succeed");
+
+    // Only the proposer can cancel
+    let result = test.cancel(test.owners[1], 0);
+    assert!(result.program_result.is_err(), "Cancel by another owner should fail");
+
+    let result = test.cancel(proposer, 0);
+    assert!(!result.program_result.is_err(), "Cancel should succeed");
+    test.env.assert_lamports(&test.proposal(0), 0);
+}
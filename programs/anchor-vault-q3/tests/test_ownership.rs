@@ -0,0 +1,99 @@
+#![cfg(feature = "test-sbf")]
+
+use anchor_lang::prelude::*;
+use anchor_vault_q3::{ accounts, instruction, VaultErrorCode };
+use anchor_vault_q3_client::close_ix;
+use solana_sdk::instruction::Instruction;
+
+pub mod utils;
+use utils::{ assert_vault_error, find_event_authority, vault_instruction, VaultTestEnv };
+
+fn propose_owner_instruction(user: Pubkey, vault_state: Pubkey, new_owner: Pubkey) -> Instruction {
+    vault_instruction(
+        accounts::ProposeOwner { user, vault_state },
+        instruction::ProposeOwner { _vault_id: 0, new_owner }
+    )
+}
+
+fn accept_owner_instruction(new_owner: Pubkey, vault_state: Pubkey) -> Instruction {
+    vault_instruction(
+        accounts::AcceptOwner {
+            new_owner,
+            vault_state,
+            event_authority: find_event_authority(),
+            program: anchor_vault_q3::id(),
+        },
+        instruction::AcceptOwner {}
+    )
+}
+
+/// Moves `user`'s default vault to `new_owner`
+fn transfer_ownership(env: &mut VaultTestEnv, user: Pubkey, new_owner: Pubkey) {
+    let vault_state = env.vault_state_address(&user);
+    let result = env.process(&propose_owner_instruction(user, vault_state, new_owner));
+    assert!(!result.program_result.is_err(), "Propose owner should succeed");
+    let result = env.process(&accept_owner_instruction(new_owner, vault_state));
+    assert!(!result.program_result.is_err(), "Accept owner should succeed");
+}
+
+#[test]
+fn test_transfer_ownership() {
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+    let vault_state = env.vault_state_address(&user);
+    let new_owner = env.add_user_with_lamports(0);
+
+    let result = env.process(&propose_owner_instruction(user, vault_state, new_owner));
+    assert!(!result.program_result.is_err(), "Propose owner should succeed");
+    let state = env.vault_state(&user);
+    assert_eq!(state.pending_owner, new_owner);
+    assert_eq!(state.owner, user, "Ownership only moves once accepted");
+
+    // Only the proposed key can accept
+    let failed = env.process(&accept_owner_instruction(Pubkey::new_unique(), vault_state));
+    assert_vault_error(&failed, VaultErrorCode::NoPendingOwner);
+
+    let result = env.process(&accept_owner_instruction(new_owner, vault_state));
+    assert!(!result.program_result.is_err(), "Accept owner should succeed");
+    let state = env.vault_state(&user);
+    assert_eq!(state.owner, new_owner);
+    assert_eq!(state.pending_owner, Pubkey::default());
+
+    // The new owner can close the vault; the funds never moved before that.
+    // The registry stays with the key the vault was created with.
+    let vault_lamports = env.lamports(&env.vault_address(&user));
+    let result = env.process(&close_ix(&new_owner, &user, 0, None));
+    assert!(!result.program_result.is_err(), "New owner should be able to close the vault");
+    assert!(env.lamports(&new_owner) >= vault_lamports);
+}
+
+#[test]
+fn test_previous_owner_loses_access() {
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+    let vault_state = env.vault_state_address(&user);
+    transfer_ownership(&mut env, user, Pubkey::new_unique());
+
+    let failed = env.process(&propose_owner_instruction(user, vault_state, Pubkey::new_unique()));
+    assert_vault_error(&failed, VaultErrorCode::NotVaultOwner);
+}
+
+#[test]
+fn test_cancel_owner_transfer() {
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+    let vault_state = env.vault_state_address(&user);
+    let new_owner = Pubkey::new_unique();
+    let cancel_instruction = vault_instruction(
+        accounts::ProposeOwner { user, vault_state },
+        instruction::CancelOwnerTransfer { _vault_id: 0 }
+    );
+
+    let failed = env.process(&cancel_instruction);
+    assert_vault_error(&failed, VaultErrorCode::NoPendingOwner);
+
+    let result = env.process(&propose_owner_instruction(user, vault_state, new_owner));
+    assert!(!result.program_result.is_err(), "Propose owner should succeed");
+    let result = env.process(&cancel_instruction);
+    assert!(!result.program_result.is_err(), "Cancel owner transfer should succeed");
+
+    let failed = env.process(&accept_owner_instruction(new_owner, vault_state));
+    assert_vault_error(&failed, VaultErrorCode::NoPendingOwner);
+}
@@ -0,0 +1,253 @@
+#![cfg(feature = "test-sbf")]
+
+use ::{
+    anchor_lang::{ prelude::*, solana_program::{ program_pack::Pack, rent::Rent }, system_program },
+    anchor_spl::{ associated_token::get_associated_token_address_with_program_id, token::spl_token },
+    anchor_vault_q3::{ accounts, instruction },
+    mollusk_svm_programs_token::{ associated_token, token },
+    solana_sdk::{ account::Account, instruction::Instruction },
+};
+
+pub mod utils;
+use utils::{ find_config, vault_instruction, VaultTestEnv };
+
+const DEPOSITOR_INITIAL_LAMPORTS: u64 = 10_000_000_000;
+
+struct PoolTest {
+    env: VaultTestEnv,
+    creator: Pubkey,
+    pool: Pubkey,
+    pool_vault: Pubkey,
+    share_mint: Pubkey,
+}
+
+impl PoolTest {
+    fn new() -> Self {
+        let program_id = anchor_vault_q3::id();
+        let mut env = VaultTestEnv::new();
+        token::add_program(&mut env.mollusk);
+        associated_token::add_program(&mut env.mollusk);
+        let (token_program, token_account) = token::keyed_account();
+        env.set_account(token_program, token_account);
+        let (associated_token_program, associated_token_account) = associated_token::keyed_account();
+        env.set_account(associated_token_program, associated_token_account);
+
+        let creator = env.add_user_with_lamports(1_000_000_000);
+        let (pool, _) = Pubkey::find_program_address(&[b"pool", creator.as_ref()], &program_id);
+        let (pool_vault, _) = Pubkey::find_program_address(&[b"pool_vault", pool.as_ref()], &program_id);
+        let (share_mint, _) = Pubkey::find_program_address(&[b"share_mint", pool.as_ref()], &program_id);
+
+        Self {
+            env,
+            creator,
+            pool,
+            pool_vault,
+            share_mint,
+        }
+    }
+
+    /// Adds a funded depositor and returns it with its share account address
+    fn add_depositor(&mut self) -> (Pubkey, Pubkey) {
+        let depositor = self.env.add_user_with_lamports(DEPOSITOR_INITIAL_LAMPORTS);
+        let shares = get_associated_token_address_with_program_id(
+            &depositor,
+            &self.share_mint,
+            &spl_token::ID
+        );
+        (depositor, shares)
+    }
+
+    /// Runs an instruction and reports whether it succeeded
+    fn process(&mut self, instruction: &Instruction) -> bool {
+        !self.env.process(instruction).program_result.is_err()
+    }
+
+    fn account(&self, key: &Pubkey) -> &Account {
+        self.env.account(key).unwrap()
+    }
+
+    fn lamports(&self, key: &Pubkey) -> u64 {
+        self.env.lamports(key)
+    }
+
+    fn token_balance(&self, key: &Pubkey) -> u64 {
+        spl_token::state::Account::unpack(&self.account(key).data).unwrap().amount
+    }
+
+    fn share_supply(&self) -> u64 {
+        spl_token::state::Mint::unpack(&self.account(&self.share_mint).data).unwrap().supply
+    }
+
+    /// Sends lamports straight to the pool vault, outside of `deposit_pool`
+    fn donate(&mut self, amount: u64) {
+        let mut pool_vault = self.account(&self.pool_vault).clone();
+        pool_vault.lamports += amount;
+        self.env.set_account(self.pool_vault, pool_vault);
+    }
+
+    fn state(&self) -> anchor_vault_q3::PoolState {
+        self.env.decode(&self.pool)
+    }
+
+    fn initialize_ix(&self) -> Instruction {
+        vault_instruction(
+            accounts::InitializePool {
+                creator: self.creator,
+                pool: self.pool,
+                pool_vault: self.pool_vault,
+                share_mint: self.share_mint,
+                token_program: spl_token::ID,
+                system_program: system_program::ID,
+            },
+            instruction::InitializePool {}
+        )
+    }
+
+    fn deposit_ix(&self, depositor: Pubkey, shares: Pubkey, amount: u64) -> Instruction {
+        vault_instruction(
+            accounts::DepositPool {
+                depositor,
+                pool: self.pool,
+                pool_vault: self.pool_vault,
+                share_mint: self.share_mint,
+                depositor_shares: shares,
+                config: find_config(),
+                token_program: spl_token::ID,
+                associated_token_program: anchor_spl::associated_token::ID,
+                system_program: system_program::ID,
+            },
+            instruction::DepositPool { amount }
+        )
+    }
+
+    fn withdraw_ix(&self, depositor: Pubkey, shares: Pubkey, amount: u64) -> Instruction {
+        vault_instruction(
+            accounts::WithdrawPool {
+                depositor,
+                pool: self.pool,
+                pool_vault: self.pool_vault,
+                share_mint: self.share_mint,
+                depositor_shares: shares,
+                token_program: spl_token::ID,
+                system_program: system_program::ID,
+            },
+            instruction::WithdrawPool { shares: amount }
+        )
+    }
+}
+
+/// The virtual shares and assets shift the share price by a small fraction
+fn assert_close(actual: u64, expected: u64, message: &str) {
+    assert!(actual.abs_diff(expected) <= expected / 1_000, "{}: {} vs {}", message, actual, expected);
+}
+
+#[test]
+fn test_pool_initialize() {
+    let mut test = PoolTest::new();
+    assert!(test.process(&test.initialize_ix()), "Initialize pool should succeed");
+
+    let state = test.state();
+    assert_eq!(state.creator, test.creator);
+    assert_eq!(state.share_mint, test.share_mint);
+    assert_eq!(test.lamports(&test.pool_vault), Rent::default().minimum_balance(0));
+
+    let mint = spl_token::state::Mint::unpack(&test.account(&test.share_mint).data).unwrap();
+    assert_eq!(mint.mint_authority, Some(test.pool).into());
+    assert_eq!(mint.decimals, anchor_vault_q3::PoolState::SHARE_DECIMALS);
+    assert_eq!(mint.supply, 0);
+}
+
+#[test]
+fn test_pool_shares_are_pro_rata() {
+    let mut test = PoolTest::new();
+    assert!(test.process(&test.initialize_ix()), "Initialize pool should succeed");
+    let (alice, alice_shares) = test.add_depositor();
+    let (bob, bob_shares) = test.add_depositor();
+
+    assert!(test.process(&test.deposit_ix(alice, alice_shares, 1_000_000_000)), "Deposit should succeed");
+    assert_eq!(test.token_balance(&alice_shares), 1_000_000_000, "First deposit mints one share per lamport");
+
+    // The pool earns 50%, so shares are now worth 1.5 lamports each
+    test.donate(500_000_000);
+
+    assert!(test.process(&test.deposit_ix(bob, bob_shares, 1_500_000_000)), "Deposit should succeed");
+    let bob_minted = test.token_balance(&bob_shares);
+    assert_close(bob_minted, 1_000_000_000, "Bob's shares follow the share price");
+    assert_eq!(test.share_supply(), 1_000_000_000 + bob_minted);
+
+    let alice_before = test.lamports(&alice);
+    assert!(test.process(&test.withdraw_ix(alice, alice_shares, 1_000_000_000)), "Withdraw should succeed");
+    let alice_received = test.lamports(&alice) - alice_before;
+    assert_close(alice_received, 1_500_000_000, "Alice keeps the pool's earnings");
+    assert_eq!(test.token_balance(&alice_shares), 0);
+
+    let bob_before = test.lamports(&bob);
+    assert!(test.process(&test.withdraw_ix(bob, bob_shares, bob_minted)), "Withdraw should succeed");
+    let bob_received = test.lamports(&bob) - bob_before;
+    assert_close(bob_received, 1_500_000_000, "Bob gets back the deposit");
+
+    // Rounding always favours the pool, which keeps its rent reserve
+    assert_eq!(test.share_supply(), 0);
+    assert!(test.lamports(&test.pool_vault) >= Rent::default().minimum_balance(0));
+    let state = test.state();
+    assert_eq!(state.total_deposited, 2_500_000_000);
+    assert_eq!(state.total_withdrawn, alice_received + bob_received);
+}
+
+#[test]
+fn test_pool_withdraw_more_than_held_fails() {
+    let mut test = PoolTest::new();
+    assert!(test.process(&test.initialize_ix()), "Initialize pool should succeed");
+    let (alice, alice_shares) = test.add_depositor();
+
+    assert!(test.process(&test.deposit_ix(alice, alice_shares, 1_000_000)), "Deposit should succeed");
+    assert!(
+        !test.process(&test.withdraw_ix(alice, alice_shares, 1_000_001)),
+        "Burning more shares than held should fail"
+    );
+}
+
+#[test]
+fn test_pool_first_depositor_inflation() {
+    let mut test = PoolTest::new();
+    assert!(test.process(&test.initialize_ix()), "Initialize pool should succeed");
+    let (attacker, attacker_shares) = test.add_depositor();
+    let (victim, victim_shares) = test.add_depositor();
+
+    // The attacker takes the first share, then donates to inflate its price
+    assert!(test.process(&test.deposit_ix(attacker, attacker_shares, 1)), "Deposit should succeed");
+    assert_eq!(test.token_balance(&attacker_shares), 1);
+    let donation = 1_000_000_000;
+    test.donate(donation);
+
+    // The virtual shares still leave the victim with a fair number of shares
+    let deposit = 100_000_000;
+    assert!(test.process(&test.deposit_ix(victim, victim_shares, deposit)), "Deposit should succeed");
+    assert!(test.token_balance(&victim_shares) > 0, "Victim should receive shares");
+
+    let victim_before = test.lamports(&victim);
+    let victim_minted = test.token_balance(&victim_shares);
+    assert!(test.process(&test.withdraw_ix(victim, victim_shares, victim_minted)), "Withdraw should succeed");
+    let victim_received = test.lamports(&victim) - victim_before;
+    assert!(victim_received >= deposit - deposit / 100, "Victim loses less than 1% of the deposit");
+
+    // The attacker recovers almost none of the donation
+    let attacker_before = test.lamports(&attacker);
+    assert!(test.process(&test.withdraw_ix(attacker, attacker_shares, 1)), "Withdraw should succeed");
+    let attacker_received = test.lamports(&attacker) - attacker_before;
+    assert!(attacker_received < donation / 100, "The donation should be a loss for the attacker");
+}
+
+#[test]
+fn test_pool_dust_deposit_fails() {
+    let mut test = PoolTest::new();
+    assert!(test.process(&test.initialize_ix()), "Initialize pool should succeed");
+    let (alice, alice_shares) = test.add_depositor();
+    let (bob, bob_shares) = test.add_depositor();
+
+    assert!(test.process(&test.deposit_ix(alice, alice_shares, 1)), "Deposit should succeed");
+    test.donate(10_000_000_000);
+
+    // One lamport is now worth far less than a share
+    assert!(!test.process(&test.deposit_ix(bob, bob_shares, 1)), "Deposits worth no shares should fail");
+}
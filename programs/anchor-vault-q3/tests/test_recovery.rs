@@ -0,0 +1,216 @@
+#![cfg(feature = "test-sbf")]
+
+use anchor_lang::{ prelude::*, system_program };
+use anchor_vault_q3::{ accounts, instruction, GuardianSet, VaultErrorCode };
+use mollusk_svm::result::InstructionResult;
+use solana_sdk::instruction::Instruction;
+
+pub mod utils;
+use utils::{ assert_vault_error, find_event_authority, vault_instruction, VaultTestEnv };
+
+const RECOVERY_DELAY: i64 = GuardianSet::MIN_RECOVERY_DELAY;
+
+fn find_guardian_set(vault_state: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"guardians", vault_state.as_ref()], &anchor_vault_q3::id()).0
+}
+
+fn find_recovery_request(vault_state: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"recovery", vault_state.as_ref()], &anchor_vault_q3::id()).0
+}
+
+fn set_guardians_instruction(user: Pubkey, vault_state: Pubkey, guardians: Vec<Pubkey>, threshold: u8) -> Instruction {
+    vault_instruction(
+        accounts::SetGuardians {
+            user,
+            vault_state,
+            guardian_set: find_guardian_set(&vault_state),
+            system_program: system_program::ID,
+        },
+        instruction::SetGuardians { _vault_id: 0, guardians, threshold, recovery_delay: RECOVERY_DELAY }
+    )
+}
+
+struct RecoveryTest {
+    env: VaultTestEnv,
+    user: Pubkey,
+    guardians: Vec<Pubkey>,
+    new_owner: Pubkey,
+}
+
+/// Sets up a deposited vault with 2-of-3 guardians and an open recovery request
+fn setup_recovery() -> RecoveryTest {
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+    let vault_state = env.vault_state_address(&user);
+    let guardians: Vec<Pubkey> = (0..3).map(|_| env.add_user()).collect();
+
+    let result = env.process(&set_guardians_instruction(user, vault_state, guardians.clone(), 2));
+    assert!(!result.program_result.is_err(), "Set guardians should succeed");
+
+    let new_owner = Pubkey::new_unique();
+    let initiate_instruction = vault_instruction(
+        accounts::InitiateRecovery {
+            guardian: guardians[0],
+            guardian_set: find_guardian_set(&vault_state),
+            vault_state,
+            recovery_request: find_recovery_request(&vault_state),
+            system_program: system_program::ID,
+        },
+        instruction::InitiateRecovery { new_owner }
+    );
+    let result = env.process(&initiate_instruction);
+    assert!(!result.program_result.is_err(), "Initiate recovery should succeed");
+
+    RecoveryTest { env, user, guardians, new_owner }
+}
+
+impl RecoveryTest {
+    fn vault_state(&self) -> Pubkey {
+        self.env.vault_state_address(&self.user)
+    }
+
+    fn approve(&mut self, guardian: Pubkey) -> InstructionResult {
+        let instruction = vault_instruction(
+            accounts::ApproveRecovery {
+                guardian,
+                guardian_set: find_guardian_set(&self.vault_state()),
+                recovery_request: find_recovery_request(&self.vault_state()),
+            },
+            instruction::ApproveRecovery {}
+        );
+        self.env.process(&instruction)
+    }
+
+    fn complete(&mut self) -> InstructionResult {
+        let instruction = vault_instruction(
+            accounts::CompleteRecovery {
+                new_owner: self.new_owner,
+                guardian_set: find_guardian_set(&self.vault_state()),
+                vault_state: self.vault_state(),
+                recovery_request: find_recovery_request(&self.vault_state()),
+                proposer: self.guardians[0],
+                event_authority: find_event_authority(),
+                program: anchor_vault_q3::id(),
+            },
+            instruction::CompleteRecovery {}
+        );
+        self.env.process(&instruction)
+    }
+}
+
+#[test]
+fn test_recovery_moves_ownership() {
+    let mut test = setup_recovery();
+
+    // One approval is below the threshold
+    let result = test.complete();
+    assert_vault_error(&result, VaultErrorCode::NotEnoughApprovals);
+
+    let result = test.approve(Pubkey::new_unique());
+    assert_vault_error(&result, VaultErrorCode::NotGuardian);
+
+    let result = test.approve(test.guardians[1]);
+    assert!(!result.program_result.is_err(), "Approve recovery should succeed");
+
+    let result = test.complete();
+    assert_vault_error(&result, VaultErrorCode::RecoveryDelayNotElapsed);
+
+    test.env.warp_to_timestamp(test.env.now() + RECOVERY_DELAY);
+    let result = test.complete();
+    assert!(!result.program_result.is_err(), "Complete recovery should succeed");
+
+    let state = test.env.vault_state(&test.user);
+    assert_eq!(state.owner, test.new_owner);
+    assert_eq!(state.creator, test.user, "The vault keeps its address");
+
+    // The lost key no longer controls the vault, the recovered one does
+    let result = test.env.withdraw(&test.user, 1_000_000);
+    assert_vault_error(&result, VaultErrorCode::NotVaultOwner);
+
+    let result = test.env.process(
+        &anchor_vault_q3_client::withdraw_ix(&test.new_owner, &test.user, 0, 1_000_000, None)
+    );
+    assert!(!result.program_result.is_err(), "New owner should be able to withdraw");
+    test.env.assert_lamports(&test.new_owner, 1_000_000);
+}
+
+#[test]
+fn test_owner_vetoes_recovery() {
+    let mut test = setup_recovery();
+    let result = test.approve(test.guardians[1]);
+    assert!(!result.program_result.is_err(), "Approve recovery should succeed");
+
+    let vault_state = test.vault_state();
+    let veto_instruction = vault_instruction(
+        accounts::VetoRecovery {
+            user: test.user,
+            vault_state,
+            recovery_request: find_recovery_request(&vault_state),
+            proposer: test.guardians[0],
+        },
+        instruction::VetoRecovery { _vault_id: 0 }
+    );
+    let result = test.env.process(&veto_instruction);
+    assert!(!result.program_result.is_err(), "Veto should succeed");
+    test.env.assert_lamports(&find_recovery_request(&vault_state), 0);
+
+    test.env.warp_to_timestamp(test.env.now() + RECOVERY_DELAY);
+    let result = test.complete();
+    assert!(result.program_result.is_err(), "A vetoed recovery can't be completed");
+}
+
+#[test]
+fn test_close_blocked_by_guardians() {
+    let mut test = setup_recovery();
+    let user = test.user;
+    let vault_state = test.vault_state();
+    let guardian_set = find_guardian_set(&vault_state);
+    assert!(test.env.vault_state(&user).has_guardians);
+
+    // Closing would leave the guardians to a vault reopened at the same address
+    let result = test.env.close(&user);
+    assert_vault_error(&result, VaultErrorCode::GuardiansOutstanding);
+
+    let remove_instruction = vault_instruction(
+        accounts::RemoveGuardians {
+            user,
+            vault_state,
+            guardian_set,
+            recovery_request: find_recovery_request(&vault_state),
+        },
+        instruction::RemoveGuardians { _vault_id: 0 }
+    );
+    let result = test.env.process(&remove_instruction);
+    assert_vault_error(&result, VaultErrorCode::RecoveryPending);
+
+    let veto_instruction = vault_instruction(
+        accounts::VetoRecovery {
+            user,
+            vault_state,
+            recovery_request: find_recovery_request(&vault_state),
+            proposer: test.guardians[0],
+        },
+        instruction::VetoRecovery { _vault_id: 0 }
+    );
+    let result = test.env.process(&veto_instruction);
+    assert!(!result.program_result.is_err(), "Veto should succeed");
+
+    let user_lamports_before = test.env.lamports(&user);
+    let guardian_set_lamports = test.env.lamports(&guardian_set);
+    let result = test.env.process(&remove_instruction);
+    assert!(!result.program_result.is_err(), "Remove guardians should succeed");
+    test.env.assert_lamports(&guardian_set, 0);
+    test.env.assert_lamports(&user, user_lamports_before + guardian_set_lamports);
+    assert!(!test.env.vault_state(&user).has_guardians);
+
+    let result = test.env.close(&user);
+    assert!(!result.program_result.is_err(), "Close should succeed once the guardians are removed");
+}
+
+#[test]
+fn test_set_guardians_rejects_owner() {
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+    let vault_state = env.vault_state_address(&user);
+
+    let result = env.process(&set_guardians_instruction(user, vault_state, vec![user, Pubkey::new_unique()], 1));
+    assert_vault_error(&result, VaultErrorCode::InvalidGuardians);
+}
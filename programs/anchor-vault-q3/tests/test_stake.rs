@@ -0,0 +1,226 @@
+#![cfg(feature = "test-sbf")]
+#![allow(deprecated)]
+
+use anchor_lang::{
+    prelude::*,
+    solana_program::{ native_token::LAMPORTS_PER_SOL, rent::Rent, stake, sysvar },
+    system_program,
+};
+use anchor_vault_q3::{ accounts, instruction, VaultErrorCode };
+use solana_sdk::{ account::Account, instruction::Instruction, vote::state::{ VoteInit, VoteState, VoteStateVersions } };
+
+pub mod utils;
+use utils::{ assert_vault_error, vault_instruction, VaultTestEnv };
+
+fn find_stake_account(vault_state: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"stake", vault_state.as_ref()], &anchor_vault_q3::id()).0
+}
+
+fn stake_amount() -> u64 {
+    Rent::default().minimum_balance(stake::state::StakeStateV2::size_of()) + LAMPORTS_PER_SOL
+}
+
+fn vote_account(clock: &Clock) -> Account {
+    let node = Pubkey::new_unique();
+    let vote_state = VoteState::new(
+        &(VoteInit {
+            node_pubkey: node,
+            authorized_voter: node,
+            authorized_withdrawer: node,
+            commission: 0,
+        }),
+        clock
+    );
+    let space = VoteState::size_of();
+    let mut account = Account::new(
+        Rent::default().minimum_balance(space),
+        space,
+        &solana_sdk::vote::program::ID
+    );
+    VoteState::serialize(&VoteStateVersions::new_current(vote_state), &mut account.data).unwrap();
+    account
+}
+
+/// Adds what staking needs to a deposited vault: the sysvars, the stake program,
+/// a vote account to delegate to and enough lamports in the vault to stake.
+/// Returns the vote account alongside the environment and user.
+fn setup_stakeable_vault() -> (VaultTestEnv, Pubkey, Pubkey) {
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+
+    let vote = Pubkey::new_unique();
+    let clock = env.mollusk.sysvars.clock.clone();
+    env.set_account(vote, vote_account(&clock));
+    for (key, account) in [
+        env.mollusk.sysvars.keyed_account_for_rent_sysvar(),
+        env.mollusk.sysvars.keyed_account_for_clock_sysvar(),
+        env.mollusk.sysvars.keyed_account_for_stake_history_sysvar(),
+        (stake::config::ID, Account::default()),
+        mollusk_svm::program::create_keyed_account_for_builtin_program(&stake::program::ID, "solana_stake_program"),
+    ] {
+        env.set_account(key, account);
+    }
+
+    // The shared setup only deposits 0.005 SOL, less than the minimum delegation
+    let vault = env.vault_address(&user);
+    let mut vault_account = env.account(&vault).unwrap().clone();
+    vault_account.lamports += 2 * LAMPORTS_PER_SOL;
+    env.set_account(vault, vault_account);
+
+    (env, user, vote)
+}
+
+fn stake_instruction(env: &VaultTestEnv, user: Pubkey, signer: Pubkey, vote: Pubkey, amount: u64) -> Instruction {
+    let vault_state = env.vault_state_address(&user);
+    vault_instruction(
+        accounts::StakeVault {
+            user: signer,
+            vault_state,
+            vault: env.vault_address(&user),
+            stake_account: find_stake_account(&vault_state),
+            vote_account: vote,
+            rent: sysvar::rent::ID,
+            clock: sysvar::clock::ID,
+            stake_history: sysvar::stake_history::ID,
+            stake_config: stake::config::ID,
+            stake_program: stake::program::ID,
+            system_program: system_program::ID,
+        },
+        instruction::StakeVault { _vault_id: 0, amount }
+    )
+}
+
+fn deactivate_instruction(env: &VaultTestEnv, user: Pubkey) -> Instruction {
+    let vault_state = env.vault_state_address(&user);
+    vault_instruction(
+        accounts::DeactivateStake {
+            user,
+            vault_state,
+            vault: env.vault_address(&user),
+            stake_account: find_stake_account(&vault_state),
+            clock: sysvar::clock::ID,
+            stake_program: stake::program::ID,
+        },
+        instruction::DeactivateStake { _vault_id: 0 }
+    )
+}
+
+fn withdraw_stake_instruction(env: &VaultTestEnv, user: Pubkey) -> Instruction {
+    let vault_state = env.vault_state_address(&user);
+    vault_instruction(
+        accounts::WithdrawStake {
+            user,
+            vault_state,
+            vault: env.vault_address(&user),
+            stake_account: find_stake_account(&vault_state),
+            clock: sysvar::clock::ID,
+            stake_history: sysvar::stake_history::ID,
+            stake_program: stake::program::ID,
+        },
+        instruction::WithdrawStake { _vault_id: 0 }
+    )
+}
+
+#[test]
+fn test_stake_moves_liquid_funds() {
+    let (mut env, user, vote) = setup_stakeable_vault();
+    let vault = env.vault_address(&user);
+    let stake_account = find_stake_account(&env.vault_state_address(&user));
+    let vault_lamports = env.lamports(&vault);
+    let amount = stake_amount();
+
+    let result = env.process(&stake_instruction(&env, user, user, vote, amount));
+    assert!(!result.program_result.is_err(), "Stake should succeed");
+
+    assert_eq!(env.account(&stake_account).unwrap().owner, stake::program::ID);
+    env.assert_lamports(&stake_account, amount);
+    env.assert_lamports(&vault, vault_lamports - amount);
+    assert_eq!(env.vault_state(&user).staked_lamports, amount);
+
+    // A vault has one stake account at a time
+    let restake = env.process(&stake_instruction(&env, user, user, vote, amount));
+    assert_vault_error(&restake, VaultErrorCode::StakeOutstanding);
+
+    // Closing would strand the stake
+    let close = env.close(&user);
+    assert_vault_error(&close, VaultErrorCode::StakeOutstanding);
+
+    // Only the liquid balance can be withdrawn
+    let liquid = vault_lamports - amount - Rent::default().minimum_balance(0);
+    let withdraw = env.withdraw(&user, liquid + 1);
+    assert_vault_error(&withdraw, VaultErrorCode::InsufficientVaultBalance);
+    let withdraw = env.withdraw(&user, liquid);
+    assert!(!withdraw.program_result.is_err(), "Withdrawing the liquid balance should succeed");
+}
+
+#[test]
+fn test_stake_with_prefunded_stake_account() {
+    let (mut env, user, vote) = setup_stakeable_vault();
+    let vault = env.vault_address(&user);
+    let stake_account = find_stake_account(&env.vault_state_address(&user));
+    let vault_lamports = env.lamports(&vault);
+    let amount = stake_amount();
+
+    // Anyone can send lamports to the stake address before the owner stakes
+    let donation = 1_000;
+    env.set_account(stake_account, Account::new(donation, 0, &system_program::ID));
+
+    let result = env.process(&stake_instruction(&env, user, user, vote, amount));
+    assert!(!result.program_result.is_err(), "Stake should succeed on a pre-funded address");
+
+    assert_eq!(env.account(&stake_account).unwrap().owner, stake::program::ID);
+    env.assert_lamports(&stake_account, amount + donation);
+    env.assert_lamports(&vault, vault_lamports - amount);
+    assert_eq!(env.vault_state(&user).staked_lamports, amount);
+}
+
+#[test]
+fn test_stake_more_than_liquid_fails() {
+    let (mut env, user, vote) = setup_stakeable_vault();
+    let vault_lamports = env.lamports(&env.vault_address(&user));
+
+    let result = env.process(&stake_instruction(&env, user, user, vote, vault_lamports));
+    assert_vault_error(&result, VaultErrorCode::InsufficientVaultBalance);
+}
+
+#[test]
+fn test_stake_requires_owner() {
+    let (mut env, user, vote) = setup_stakeable_vault();
+
+    let result = env.process(&stake_instruction(&env, user, Pubkey::new_unique(), vote, stake_amount()));
+    assert_vault_error(&result, VaultErrorCode::NotVaultOwner);
+}
+
+#[test]
+fn test_deactivate_and_withdraw_stake() {
+    let (mut env, user, vote) = setup_stakeable_vault();
+    let vault = env.vault_address(&user);
+    let vault_lamports = env.lamports(&vault);
+    let result = env.process(&stake_instruction(&env, user, user, vote, stake_amount()));
+    assert!(!result.program_result.is_err(), "Stake should succeed");
+
+    // Active stake can't be withdrawn
+    let result = env.process(&withdraw_stake_instruction(&env, user));
+    assert!(result.program_result.is_err(), "Withdrawing active stake should fail");
+
+    let result = env.process(&deactivate_instruction(&env, user));
+    assert!(!result.program_result.is_err(), "Deactivate should succeed");
+
+    // Stake deactivated in the epoch it was delegated in is inactive straight away
+    let result = env.process(&withdraw_stake_instruction(&env, user));
+    assert!(!result.program_result.is_err(), "Withdraw stake should succeed");
+    env.assert_lamports(&vault, vault_lamports);
+    env.assert_lamports(&find_stake_account(&env.vault_state_address(&user)), 0);
+    assert_eq!(env.vault_state(&user).staked_lamports, 0);
+
+    // With the stake back the vault can be closed
+    let close = env.close(&user);
+    assert!(!close.program_result.is_err(), "Close should succeed");
+}
+
+#[test]
+fn test_deactivate_without_stake_fails() {
+    let (mut env, user, _) = setup_stakeable_vault();
+
+    let result = env.process(&deactivate_instruction(&env, user));
+    assert_vault_error(&result, VaultErrorCode::NoStake);
+}
@@ -0,0 +1,273 @@
+#![cfg(feature = "test-sbf")]
+
+use anchor_lang::{ prelude::*, system_program, InstructionData };
+use anchor_vault_q3::{ accounts, instruction, Stream, VaultErrorCode };
+use anchor_vault_q3_client::withdraw_ix;
+use solana_sdk::instruction::Instruction;
+
+pub mod utils;
+use utils::{
+    assert_vault_error,
+    find_config,
+    find_event_authority,
+    find_treasury,
+    vault_instruction,
+    VaultTestEnv,
+    DEPOSIT_AMOUNT,
+};
+
+const TOTAL: u64 = 4_000_000;
+const DURATION: i64 = 1_000;
+const CLIFF: i64 = 250;
+
+fn find_stream(vault_state: &Pubkey, stream_id: u64) -> Pubkey {
+    Pubkey::find_program_address(
+        &[b"stream", vault_state.as_ref(), stream_id.to_le_bytes().as_ref()],
+        &anchor_vault_q3::id()
+    ).0
+}
+
+fn create_stream_instruction(env: &VaultTestEnv, user: Pubkey, recipient: Pubkey, total: u64, start: i64) -> Instruction {
+    let vault_state = env.vault_state_address(&user);
+    vault_instruction(
+        accounts::CreateStream {
+            user,
+            vault_state,
+            vault: env.vault_address(&user),
+            stream: find_stream(&vault_state, 0),
+            system_program: system_program::ID,
+        },
+        instruction::CreateStream {
+            _vault_id: 0,
+            recipient,
+            total,
+            start,
+            cliff: start + CLIFF,
+            end: start + DURATION,
+        }
+    )
+}
+
+fn claim_stream_instruction(env: &VaultTestEnv, user: Pubkey, recipient: Pubkey) -> Instruction {
+    let vault_state = env.vault_state_address(&user);
+    vault_instruction(
+        accounts::ClaimStream {
+            recipient,
+            stream: find_stream(&vault_state, 0),
+            vault_state,
+            vault: env.vault_address(&user),
+            payer: user,
+            config: find_config(),
+            treasury: find_treasury(),
+            system_program: system_program::ID,
+            event_authority: find_event_authority(),
+            program: anchor_vault_q3::id(),
+        },
+        instruction::ClaimStream {}
+    )
+}
+
+#[test]
+fn test_claim_stream_vests_linearly() {
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+    let vault_state = env.vault_state_address(&user);
+    let recipient = Pubkey::new_unique();
+    let start = env.now();
+
+    let result = env.process(&create_stream_instruction(&env, user, recipient, TOTAL, start));
+    assert!(!result.program_result.is_err(), "Create stream should succeed");
+    assert_eq!(env.vault_state(&user).reserved_for_streams, TOTAL);
+
+    // Reserved lamports can't be withdrawn by the owner
+    let withdraw_all_instruction = Instruction {
+        data: (instruction::WithdrawAll { _vault_id: 0 }).data(),
+        ..withdraw_ix(&user, &user, 0, 0, None)
+    };
+    let user_lamports_before = env.lamports(&user);
+    let result = env.process(&withdraw_all_instruction);
+    assert!(!result.program_result.is_err(), "Withdraw all should succeed");
+    env.assert_lamports(&user, user_lamports_before + DEPOSIT_AMOUNT - TOTAL);
+
+    // Nothing is claimable before the cliff
+    env.warp_to_timestamp(start + CLIFF - 1);
+    let failed = env.process(&claim_stream_instruction(&env, user, recipient));
+    assert_vault_error(&failed, VaultErrorCode::NothingToClaim);
+
+    env.warp_to_timestamp(start + DURATION / 2);
+    let result = env.process(&claim_stream_instruction(&env, user, recipient));
+    assert!(!result.program_result.is_err(), "Claim stream should succeed");
+    env.assert_lamports(&recipient, TOTAL / 2);
+
+    let stream: Stream = env.decode(&find_stream(&vault_state, 0));
+    assert_eq!(stream.withdrawn, TOTAL / 2);
+    assert_eq!(stream.payer, user);
+
+    // The final claim closes the stream, refunding its rent to the payer
+    env.warp_to_timestamp(start + DURATION);
+    let user_lamports_before = env.lamports(&user);
+    let stream_lamports = env.lamports(&find_stream(&vault_state, 0));
+    let result = env.process(&claim_stream_instruction(&env, user, recipient));
+    assert!(!result.program_result.is_err(), "Final claim should succeed");
+    env.assert_lamports(&recipient, TOTAL);
+    env.assert_lamports(&find_stream(&vault_state, 0), 0);
+    env.assert_lamports(&user, user_lamports_before + stream_lamports);
+    assert_eq!(env.vault_state(&user).reserved_for_streams, 0);
+}
+
+#[test]
+fn test_claim_stream_pays_fee() {
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+    env.set_withdraw_fee(50);
+    let recipient = Pubkey::new_unique();
+    let start = env.now();
+
+    let result = env.process(&create_stream_instruction(&env, user, recipient, TOTAL, start));
+    assert!(!result.program_result.is_err(), "Create stream should succeed");
+
+    env.warp_to_timestamp(start + DURATION);
+    let treasury_lamports_before = env.lamports(&find_treasury());
+    let result = env.process(&claim_stream_instruction(&env, user, recipient));
+    assert!(!result.program_result.is_err(), "Claim stream should succeed");
+
+    // The fee comes out of the claimed amount, which still settles the stream in full
+    let fee = TOTAL * 50 / 10_000;
+    env.assert_lamports(&recipient, TOTAL - fee);
+    env.assert_lamports(&find_treasury(), treasury_lamports_before + fee);
+    assert_eq!(env.vault_state(&user).reserved_for_streams, 0);
+}
+
+#[test]
+fn test_reopened_vault_can_stream_again() {
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+    let recipient = Pubkey::new_unique();
+    let start = env.now();
+
+    let result = env.process(&create_stream_instruction(&env, user, recipient, TOTAL, start));
+    assert!(!result.program_result.is_err(), "Create stream should succeed");
+    env.warp_to_timestamp(start + DURATION);
+    let result = env.process(&claim_stream_instruction(&env, user, recipient));
+    assert!(!result.program_result.is_err(), "Claim stream should succeed");
+
+    let result = env.close(&user);
+    assert!(!result.program_result.is_err(), "Close should succeed");
+    let result = env.initialize(&user);
+    assert!(!result.program_result.is_err(), "Reopening the vault should succeed");
+    let result = env.deposit(&user, DEPOSIT_AMOUNT);
+    assert!(!result.program_result.is_err(), "Deposit should succeed");
+
+    // The reopened vault starts counting streams from zero again
+    let result = env.process(&create_stream_instruction(&env, user, recipient, TOTAL, env.now()));
+    assert!(!result.program_result.is_err(), "Create stream in the reopened vault should succeed");
+}
+
+#[test]
+fn test_cancel_stream_returns_unvested() {
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+    let vault_state = env.vault_state_address(&user);
+    let vault = env.vault_address(&user);
+    let stream = find_stream(&vault_state, 0);
+    let recipient = Pubkey::new_unique();
+    let start = env.now();
+
+    let result = env.process(&create_stream_instruction(&env, user, recipient, TOTAL, start));
+    assert!(!result.program_result.is_err(), "Create stream should succeed");
+    let vault_lamports = env.lamports(&vault);
+
+    env.warp_to_timestamp(start + DURATION / 4);
+    let cancel_instruction = vault_instruction(
+        accounts::CancelStream {
+            user,
+            vault_state,
+            vault,
+            stream,
+            recipient,
+            payer: user,
+            config: find_config(),
+            treasury: find_treasury(),
+            system_program: system_program::ID,
+            event_authority: find_event_authority(),
+            program: anchor_vault_q3::id(),
+        },
+        instruction::CancelStream { _vault_id: 0 }
+    );
+    let result = env.process(&cancel_instruction);
+    assert!(!result.program_result.is_err(), "Cancel stream should succeed");
+
+    // The vested quarter goes to the recipient, the rest stays in the vault unreserved
+    env.assert_lamports(&recipient, TOTAL / 4);
+    env.assert_lamports(&vault, vault_lamports - TOTAL / 4);
+    assert_eq!(env.vault_state(&user).reserved_for_streams, 0);
+    env.assert_lamports(&stream, 0);
+
+    // Nothing is left to claim once cancelled
+    env.warp_to_timestamp(start + DURATION);
+    let result = env.process(&claim_stream_instruction(&env, user, recipient));
+    assert!(result.program_result.is_err(), "Claiming a cancelled stream should fail");
+}
+
+#[test]
+fn test_stream_with_extreme_schedule() {
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+    let vault_state = env.vault_state_address(&user);
+    let recipient = Pubkey::new_unique();
+    let stream = find_stream(&vault_state, 0);
+
+    // Spans the whole i64 range, so `now` sits about halfway through it
+    let create_instruction = vault_instruction(
+        accounts::CreateStream {
+            user,
+            vault_state,
+            vault: env.vault_address(&user),
+            stream,
+            system_program: system_program::ID,
+        },
+        instruction::CreateStream {
+            _vault_id: 0,
+            recipient,
+            total: TOTAL,
+            start: i64::MIN,
+            cliff: i64::MIN,
+            end: i64::MAX,
+        }
+    );
+    let result = env.process(&create_instruction);
+    assert!(!result.program_result.is_err(), "Create stream should succeed");
+
+    let result = env.process(&claim_stream_instruction(&env, user, recipient));
+    assert!(!result.program_result.is_err(), "Claim stream should succeed");
+    let claimed = env.decode::<Stream>(&stream).withdrawn;
+    assert!(claimed > 0 && claimed < TOTAL);
+    env.assert_lamports(&recipient, claimed);
+
+    let cancel_instruction = vault_instruction(
+        accounts::CancelStream {
+            user,
+            vault_state,
+            vault: env.vault_address(&user),
+            stream,
+            recipient,
+            payer: user,
+            config: find_config(),
+            treasury: find_treasury(),
+            system_program: system_program::ID,
+            event_authority: find_event_authority(),
+            program: anchor_vault_q3::id(),
+        },
+        instruction::CancelStream { _vault_id: 0 }
+    );
+    let result = env.process(&cancel_instruction);
+    assert!(!result.program_result.is_err(), "Cancel stream should succeed");
+    assert_eq!(env.vault_state(&user).reserved_for_streams, 0);
+}
+
+#[test]
+fn test_create_stream_validation() {
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+    let start = env.now();
+
+    let failed = env.process(&create_stream_instruction(&env, user, Pubkey::new_unique(), 6_000_000, start));
+    assert_vault_error(&failed, VaultErrorCode::InsufficientVaultBalance);
+
+    let failed = env.process(&create_stream_instruction(&env, user, Pubkey::new_unique(), 0, start));
+    assert_vault_error(&failed, VaultErrorCode::InvalidStreamSchedule);
+}
@@ -0,0 +1,310 @@
+#![cfg(feature = "test-sbf")]
+
+use ::{
+    anchor_lang::{ prelude::*, solana_program::{ program_option::COption, program_pack::Pack, rent::Rent }, system_program },
+    anchor_spl::{
+        associated_token::get_associated_token_address_with_program_id,
+        token::spl_token,
+        token_2022::spl_token_2022::{
+            self,
+            extension::{
+                transfer_fee::{ TransferFee, TransferFeeAmount, TransferFeeConfig },
+                BaseStateWithExtensionsMut,
+                ExtensionType,
+                StateWithExtensionsMut,
+            },
+            state::{ Account as TokenAccount2022, AccountState, Mint as Mint2022 },
+        },
+    },
+    anchor_vault_q3::{ accounts, instruction, VaultErrorCode },
+    mollusk_svm_programs_token::{ associated_token, token, token2022 },
+    solana_sdk::{ account::Account, instruction::Instruction },
+};
+
+pub mod utils;
+use utils::{ assert_vault_error, config_account, find_config, vault_instruction, VaultTestEnv };
+
+const DECIMALS: u8 = 6;
+const USER_INITIAL_TOKENS: u64 = 1_000_000;
+
+struct TokenVaultTest {
+    env: VaultTestEnv,
+    token_program: Pubkey,
+    user: Pubkey,
+    mint: Pubkey,
+    user_token_account: Pubkey,
+    token_vault_state: Pubkey,
+    token_vault: Pubkey,
+}
+
+impl TokenVaultTest {
+    fn new(token_program: Pubkey, transfer_fee_bps: Option<u16>) -> Self {
+        let program_id = anchor_vault_q3::id();
+        let mut env = VaultTestEnv::new();
+        token::add_program(&mut env.mollusk);
+        token2022::add_program(&mut env.mollusk);
+        associated_token::add_program(&mut env.mollusk);
+
+        let user = env.add_user_with_lamports(1_000_000_000);
+        let mint = Pubkey::new_unique();
+        let user_token_account = Pubkey::new_unique();
+        let (token_vault_state, _) = Pubkey::find_program_address(
+            &[b"token_state", user.as_ref(), mint.as_ref()],
+            &program_id
+        );
+        let token_vault = get_associated_token_address_with_program_id(
+            &token_vault_state,
+            &mint,
+            &token_program
+        );
+
+        let token_program_account = if token_program == spl_token::ID {
+            token::keyed_account().1
+        } else {
+            token2022::keyed_account().1
+        };
+        let (associated_token_program, associated_token_account) = associated_token::keyed_account();
+
+        env.set_account(mint, mint_account(&token_program, transfer_fee_bps));
+        env.set_account(
+            user_token_account,
+            token_account(&token_program, &mint, &user, USER_INITIAL_TOKENS, transfer_fee_bps.is_some())
+        );
+        env.set_account(token_program, token_program_account);
+        env.set_account(associated_token_program, associated_token_account);
+
+        Self {
+            env,
+            token_program,
+            user,
+            mint,
+            user_token_account,
+            token_vault_state,
+            token_vault,
+        }
+    }
+
+    /// Runs an instruction and reports whether it succeeded
+    fn process(&mut self, instruction: &Instruction) -> bool {
+        !self.env.process(instruction).program_result.is_err()
+    }
+
+    fn account(&self, key: &Pubkey) -> &Account {
+        self.env.account(key).unwrap()
+    }
+
+    fn token_balance(&self, key: &Pubkey) -> u64 {
+        let data = &self.account(key).data;
+        spl_token::state::Account::unpack_from_slice(&data[..spl_token::state::Account::LEN])
+            .unwrap()
+            .amount
+    }
+
+    fn state(&self) -> anchor_vault_q3::TokenVaultState {
+        self.env.decode(&self.token_vault_state)
+    }
+
+    fn initialize_ix(&self) -> Instruction {
+        vault_instruction(
+            accounts::InitializeTokenVault {
+                user: self.user,
+                mint: self.mint,
+                token_vault_state: self.token_vault_state,
+                token_vault: self.token_vault,
+                token_program: self.token_program,
+                associated_token_program: anchor_spl::associated_token::ID,
+                system_program: system_program::ID,
+            },
+            instruction::InitializeTokenVault {}
+        )
+    }
+
+    fn deposit_ix(&self, amount: u64) -> Instruction {
+        vault_instruction(
+            accounts::DepositToken {
+                user: self.user,
+                mint: self.mint,
+                user_token_account: self.user_token_account,
+                token_vault_state: self.token_vault_state,
+                token_vault: self.token_vault,
+                config: find_config(),
+                token_program: self.token_program,
+            },
+            instruction::DepositToken { amount }
+        )
+    }
+
+    fn withdraw_ix(&self, amount: u64) -> Instruction {
+        vault_instruction(
+            accounts::WithdrawToken {
+                user: self.user,
+                mint: self.mint,
+                user_token_account: self.user_token_account,
+                token_vault_state: self.token_vault_state,
+                token_vault: self.token_vault,
+                config: find_config(),
+                token_program: self.token_program,
+            },
+            instruction::WithdrawToken { amount }
+        )
+    }
+
+    fn close_ix(&self) -> Instruction {
+        vault_instruction(
+            accounts::CloseTokenVault {
+                user: self.user,
+                mint: self.mint,
+                user_token_account: self.user_token_account,
+                token_vault_state: self.token_vault_state,
+                token_vault: self.token_vault,
+                token_program: self.token_program,
+            },
+            instruction::CloseTokenVault {}
+        )
+    }
+}
+
+fn mint_account(token_program: &Pubkey, transfer_fee_bps: Option<u16>) -> Account {
+    let extensions = match transfer_fee_bps {
+        Some(_) => vec![ExtensionType::TransferFeeConfig],
+        None => vec![],
+    };
+    let space = if *token_program == spl_token::ID {
+        spl_token::state::Mint::LEN
+    } else {
+        ExtensionType::try_calculate_account_len::<Mint2022>(&extensions).unwrap()
+    };
+    let mut data = vec![0u8; space];
+    let mut state = StateWithExtensionsMut::<Mint2022>::unpack_uninitialized(&mut data).unwrap();
+    if let Some(bps) = transfer_fee_bps {
+        let fee = TransferFee {
+            epoch: (0).into(),
+            maximum_fee: u64::MAX.into(),
+            transfer_fee_basis_points: bps.into(),
+        };
+        let config = state.init_extension::<TransferFeeConfig>(true).unwrap();
+        config.older_transfer_fee = fee;
+        config.newer_transfer_fee = fee;
+    }
+    state.base = Mint2022 {
+        mint_authority: COption::None,
+        supply: USER_INITIAL_TOKENS,
+        decimals: DECIMALS,
+        is_initialized: true,
+        freeze_authority: COption::None,
+    };
+    state.pack_base();
+    if *token_program == spl_token_2022::ID {
+        state.init_account_type().unwrap();
+    }
+
+    let mut account = Account::new(Rent::default().minimum_balance(space), space, token_program);
+    account.data = data;
+    account
+}
+
+fn token_account(
+    token_program: &Pubkey,
+    mint: &Pubkey,
+    owner: &Pubkey,
+    amount: u64,
+    with_transfer_fee: bool
+) -> Account {
+    let extensions = if with_transfer_fee { vec![ExtensionType::TransferFeeAmount] } else { vec![] };
+    let space = if *token_program == spl_token::ID {
+        spl_token::state::Account::LEN
+    } else {
+        ExtensionType::try_calculate_account_len::<TokenAccount2022>(&extensions).unwrap()
+    };
+    let mut data = vec![0u8; space];
+    let mut state = StateWithExtensionsMut::<TokenAccount2022>
+        ::unpack_uninitialized(&mut data)
+        .unwrap();
+    if with_transfer_fee {
+        state.init_extension::<TransferFeeAmount>(true).unwrap();
+    }
+    state.base = TokenAccount2022 {
+        mint: *mint,
+        owner: *owner,
+        amount,
+        delegate: COption::None,
+        state: AccountState::Initialized,
+        is_native: COption::None,
+        delegated_amount: 0,
+        close_authority: COption::None,
+    };
+    state.pack_base();
+    if *token_program == spl_token_2022::ID {
+        state.init_account_type().unwrap();
+    }
+
+    let mut account = Account::new(Rent::default().minimum_balance(space), space, token_program);
+    account.data = data;
+    account
+}
+
+fn run_lifecycle(token_program: Pubkey) {
+    let mut test = TokenVaultTest::new(token_program, None);
+
+    assert!(test.process(&test.initialize_ix()), "Initialize token vault should succeed");
+    assert_eq!(test.account(&test.token_vault).owner, token_program);
+    assert_eq!(test.state().mint, test.mint);
+
+    assert!(test.process(&test.deposit_ix(400_000)), "Deposit token should succeed");
+    assert_eq!(test.token_balance(&test.token_vault), 400_000);
+    assert_eq!(test.token_balance(&test.user_token_account), USER_INITIAL_TOKENS - 400_000);
+
+    assert!(!test.process(&test.withdraw_ix(500_000)), "Withdrawing more than the vault holds should fail");
+
+    assert!(test.process(&test.withdraw_ix(100_000)), "Withdraw token should succeed");
+    assert_eq!(test.token_balance(&test.token_vault), 300_000);
+    let state = test.state();
+    assert_eq!(state.total_deposited, 400_000);
+    assert_eq!(state.total_withdrawn, 100_000);
+    assert_eq!(state.deposit_count, 1);
+
+    assert!(test.process(&test.close_ix()), "Close token vault should succeed");
+    assert_eq!(test.token_balance(&test.user_token_account), USER_INITIAL_TOKENS);
+    assert_eq!(test.account(&test.token_vault).lamports, 0, "Token vault should be closed");
+    assert_eq!(test.account(&test.token_vault_state).lamports, 0, "Token vault state should be closed");
+}
+
+#[test]
+fn test_token_vault_lifecycle_spl_token() {
+    run_lifecycle(spl_token::ID);
+}
+
+#[test]
+fn test_token_vault_lifecycle_token_2022() {
+    run_lifecycle(spl_token_2022::ID);
+}
+
+#[test]
+fn test_token_vault_transfer_fee() {
+    // 1% transfer fee
+    let mut test = TokenVaultTest::new(spl_token_2022::ID, Some(100));
+
+    assert!(test.process(&test.initialize_ix()), "Initialize token vault should succeed");
+    assert!(test.process(&test.deposit_ix(10_000)), "Deposit token should succeed");
+
+    // Only the amount net of the withheld fee is credited
+    assert_eq!(test.token_balance(&test.token_vault), 9_900);
+    assert_eq!(test.state().total_deposited, 9_900);
+
+    // Withheld fees are harvested to the mint so the vault can still be closed
+    assert!(test.process(&test.close_ix()), "Close token vault should succeed");
+    assert_eq!(test.account(&test.token_vault).lamports, 0, "Token vault should be closed");
+}
+
+#[test]
+fn test_token_vault_paused() {
+    let mut test = TokenVaultTest::new(spl_token::ID, None);
+    assert!(test.process(&test.initialize_ix()), "Initialize token vault should succeed");
+    assert!(test.process(&test.deposit_ix(10_000)), "Deposit token should succeed");
+    test.env.set_account(find_config(), config_account(Pubkey::default(), true));
+
+    let failed = test.env.process(&test.deposit_ix(10_000));
+    assert_vault_error(&failed, VaultErrorCode::ProgramPaused);
+    let failed = test.env.process(&test.withdraw_ix(10_000));
+    assert_vault_error(&failed, VaultErrorCode::ProgramPaused);
+}
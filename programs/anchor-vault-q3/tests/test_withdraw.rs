@@ -1,61 +1,129 @@
 #![cfg(feature = "test-sbf")]
 
-use anchor_lang::{ InstructionData };
-use solana_sdk::{ instruction::{ AccountMeta, Instruction } };
+use anchor_lang::{ solana_program::rent::Rent, InstructionData };
+use anchor_vault_q3::VaultErrorCode;
+use anchor_vault_q3_client::withdraw_ix;
+use solana_sdk::instruction::{ AccountMeta, Instruction };
 
-mod utils;
-use utils::{ setup_initialized_and_deposited_vault };
+pub mod utils;
+use utils::{ assert_vault_error, VaultTestEnv, DEPOSIT_AMOUNT };
 
 #[test]
 fn test_withdraw_success() {
-    let (mollusk, user, vault_state, vault, _, _, deposit_result) =
-        setup_initialized_and_deposited_vault();
-    let (system_program, system_account) = mollusk_svm::program::keyed_account_for_system_program();
-    let program_id = anchor_vault_q3::id();
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+    let vault = env.vault_address(&user);
+    let user_lamports_before = env.lamports(&user);
+    let vault_lamports_before = env.lamports(&vault);
 
     let withdraw_amount = 5_000_000; // 0.005 SOL
+    let result = env.withdraw(&user, withdraw_amount);
 
-    // Create withdraw instruction
-    let instruction = Instruction::new_with_bytes(
-        program_id,
-        &(anchor_vault_q3::instruction::Withdraw { amount: withdraw_amount }).data(),
-        vec![
-            AccountMeta::new(user, true),
-            AccountMeta::new(vault, false),
-            AccountMeta::new_readonly(vault_state, false),
-            AccountMeta::new_readonly(system_program, false)
-        ]
-    );
+    // Verify success
+    assert!(!result.program_result.is_err(), "Withdraw should succeed");
 
-    let user_account = deposit_result.get_account(&user).unwrap();
-    let user_lamports_before = user_account.lamports;
-    let vault_account = deposit_result.get_account(&vault).unwrap();
-    let vault_lamports_before = vault_account.lamports;
+    // Verify user lamports increased by withdraw amount
+    env.assert_lamports(&user, user_lamports_before + withdraw_amount);
 
-    let withdraw_accounts = &vec![
-        (user, user_account.clone()),
-        (vault_state, deposit_result.get_account(&vault_state).unwrap().clone()),
-        (vault, vault_account.clone()),
-        (system_program, system_account)
-    ];
-    let result = mollusk.process_instruction(&instruction, &withdraw_accounts);
+    // Verify vault lamports decreased by withdraw amount
+    env.assert_lamports(&vault, vault_lamports_before - withdraw_amount);
 
-    // Verify success
-    assert!(!result.program_result.is_err(), "Withdraw should succeed");
+    // Verify the withdrawal was recorded on-chain
+    let state = env.vault_state(&user);
+    assert_eq!(state.total_deposited, DEPOSIT_AMOUNT, "Deposit should still be tracked");
+    assert_eq!(state.total_withdrawn, withdraw_amount, "Withdrawal should be tracked");
+}
 
-    // Verify user lamports increased
-    let user_account = &result.get_account(&user).unwrap();
-    assert_eq!(
-        user_account.lamports,
-        user_lamports_before + withdraw_amount,
-        "User lamports should increase by withdraw amount"
-    );
+#[test]
+fn test_withdraw_small_amount() {
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+    let user_lamports_before = env.lamports(&user);
 
-    // Verify vault lamports decreased
-    let vault_account = &result.get_account(&vault).unwrap();
-    assert_eq!(
-        vault_account.lamports,
-        vault_lamports_before - withdraw_amount,
-        "Vault lamports should decrease by withdraw amount"
+    // Amounts below the rent-exempt minimum are allowed
+    let result = env.withdraw(&user, 1_000);
+    assert!(!result.program_result.is_err(), "Small withdraw should succeed");
+    env.assert_lamports(&user, user_lamports_before + 1_000);
+}
+
+#[test]
+fn test_withdraw_keeps_rent_reserve() {
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+    let vault_lamports = env.lamports(&env.vault_address(&user));
+
+    // The rent reserve can only be reclaimed through close
+    let result = env.withdraw(&user, vault_lamports);
+    assert_vault_error(&result, VaultErrorCode::InsufficientVaultBalance);
+}
+
+#[test]
+fn test_withdraw_below_minimum() {
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+    let min_withdrawal = 1_000_000;
+
+    let set_min_instruction = Instruction::new_with_bytes(
+        anchor_vault_q3::id(),
+        &(anchor_vault_q3::instruction::SetMinWithdrawal { _vault_id: 0, min_withdrawal }).data(),
+        vec![AccountMeta::new_readonly(user, true), AccountMeta::new(env.vault_state_address(&user), false)]
     );
+    let result = env.process(&set_min_instruction);
+    assert!(!result.program_result.is_err(), "Setting the minimum should succeed");
+
+    let result = env.withdraw(&user, min_withdrawal - 1);
+    assert_vault_error(&result, VaultErrorCode::InsufficientWithdrawalAmount);
+
+    let result = env.withdraw(&user, min_withdrawal);
+    assert!(!result.program_result.is_err(), "Withdrawing the minimum should succeed");
+}
+
+#[test]
+fn test_withdraw_all() {
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+    let vault = env.vault_address(&user);
+    let vault_rent = Rent::default().minimum_balance(0);
+    let user_lamports_before = env.lamports(&user);
+    let vault_lamports_before = env.lamports(&vault);
+
+    // `withdraw_all` takes the same accounts as `withdraw`
+    let instruction = Instruction {
+        data: (anchor_vault_q3::instruction::WithdrawAll { _vault_id: 0 }).data(),
+        ..withdraw_ix(&user, &user, 0, 0, None)
+    };
+    let result = env.process(&instruction);
+    assert!(!result.program_result.is_err(), "Withdraw all should succeed");
+
+    // Everything but the rent reserve is returned
+    env.assert_lamports(&vault, vault_rent);
+    env.assert_lamports(&user, user_lamports_before + vault_lamports_before - vault_rent);
+
+    // Nothing is left to withdraw
+    let result = env.process(&instruction);
+    assert_vault_error(&result, VaultErrorCode::InsufficientVaultBalance);
+}
+
+#[test]
+fn test_withdraw_from_another_users_vault_fails() {
+    let (mut env, owner) = VaultTestEnv::with_deposited_vault();
+    let other = env.add_user();
+
+    let result = env.process(&withdraw_ix(&other, &owner, 0, 1_000_000, None));
+    assert_vault_error(&result, VaultErrorCode::NotVaultOwner);
+    env.assert_lamports(&env.vault_address(&owner), Rent::default().minimum_balance(0) + DEPOSIT_AMOUNT);
+}
+
+#[test]
+fn test_withdraw_after_unlock() {
+    let mut env = VaultTestEnv::new();
+    let user = env.add_user();
+    let unlock_timestamp = env.now() + 100;
+
+    let result = env.initialize_vault(&user, 0, unlock_timestamp, 0);
+    assert!(!result.program_result.is_err(), "Initialize should succeed");
+    let result = env.deposit(&user, DEPOSIT_AMOUNT);
+    assert!(!result.program_result.is_err(), "Deposit should succeed");
+
+    let result = env.withdraw(&user, 1_000_000);
+    assert_vault_error(&result, VaultErrorCode::VaultLocked);
+
+    env.warp_to_timestamp(unlock_timestamp);
+    let result = env.withdraw(&user, 1_000_000);
+    assert!(!result.program_result.is_err(), "Withdraw should succeed once unlocked");
 }
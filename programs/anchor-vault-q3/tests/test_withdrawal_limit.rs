@@ -0,0 +1,87 @@
+#![cfg(feature = "test-sbf")]
+
+use anchor_lang::prelude::*;
+use anchor_vault_q3::{ accounts, instruction, VaultErrorCode, VaultState };
+use solana_sdk::instruction::Instruction;
+
+pub mod utils;
+use utils::{ assert_vault_error, vault_instruction, VaultTestEnv };
+
+const LIMIT: u64 = 1_000_000;
+const WINDOW: i64 = 3_600;
+
+fn set_limit_instruction(env: &VaultTestEnv, user: Pubkey, withdrawal_limit: u64, withdrawal_window: i64) -> Instruction {
+    vault_instruction(
+        accounts::SetWithdrawalLimit { user, vault_state: env.vault_state_address(&user) },
+        instruction::SetWithdrawalLimit { _vault_id: 0, withdrawal_limit, withdrawal_window }
+    )
+}
+
+/// Sets a first limit on a deposited vault, which applies immediately
+fn setup_limited_vault() -> (VaultTestEnv, Pubkey) {
+    let (mut env, user) = VaultTestEnv::with_deposited_vault();
+    let result = env.process(&set_limit_instruction(&env, user, LIMIT, WINDOW));
+    assert!(!result.program_result.is_err(), "Setting a first limit should succeed");
+    (env, user)
+}
+
+#[test]
+fn test_withdrawal_limit_per_window() {
+    let (mut env, user) = setup_limited_vault();
+    let state = env.vault_state(&user);
+    assert_eq!(state.withdrawal_limit, LIMIT);
+    assert_eq!(state.pending_limit_effective_at, 0, "A first limit applies immediately");
+
+    let result = env.withdraw(&user, 600_000);
+    assert!(!result.program_result.is_err(), "Withdrawal within the limit should succeed");
+
+    let failed = env.withdraw(&user, 600_000);
+    assert_vault_error(&failed, VaultErrorCode::WithdrawalLimitExceeded);
+
+    let result = env.withdraw(&user, 400_000);
+    assert!(!result.program_result.is_err(), "Withdrawing the rest of the window should succeed");
+
+    // A new window starts once the old one has passed
+    env.warp_to_timestamp(env.now() + WINDOW);
+    let result = env.withdraw(&user, 600_000);
+    assert!(!result.program_result.is_err(), "Withdrawal in a new window should succeed");
+    assert_eq!(env.vault_state(&user).withdrawn_in_window, 600_000);
+}
+
+#[test]
+fn test_loosening_limit_is_delayed() {
+    let (mut env, user) = setup_limited_vault();
+
+    // Removing the limit only takes effect after the delay
+    let result = env.process(&set_limit_instruction(&env, user, 0, 0));
+    assert!(!result.program_result.is_err(), "Queueing a looser limit should succeed");
+    let state = env.vault_state(&user);
+    assert_eq!(state.withdrawal_limit, LIMIT);
+    assert_eq!(state.pending_limit_effective_at, env.now() + VaultState::WITHDRAWAL_LIMIT_DELAY);
+
+    let failed = env.withdraw(&user, 2_000_000);
+    assert_vault_error(&failed, VaultErrorCode::WithdrawalLimitExceeded);
+
+    env.warp_to_timestamp(env.now() + VaultState::WITHDRAWAL_LIMIT_DELAY);
+    let result = env.withdraw(&user, 2_000_000);
+    assert!(!result.program_result.is_err(), "Withdrawal should succeed once the limit is lifted");
+    assert_eq!(env.vault_state(&user).withdrawal_limit, 0);
+}
+
+#[test]
+fn test_tightening_limit_cancels_pending_change() {
+    let (mut env, user) = setup_limited_vault();
+
+    let result = env.process(&set_limit_instruction(&env, user, LIMIT * 2, WINDOW));
+    assert!(!result.program_result.is_err(), "Queueing a looser limit should succeed");
+    assert_ne!(env.vault_state(&user).pending_limit_effective_at, 0);
+
+    let result = env.process(&set_limit_instruction(&env, user, LIMIT / 2, WINDOW));
+    assert!(!result.program_result.is_err(), "Tightening the limit should succeed");
+    let state = env.vault_state(&user);
+    assert_eq!(state.withdrawal_limit, LIMIT / 2);
+    assert_eq!(state.pending_limit_effective_at, 0);
+
+    let failed = env.process(&set_limit_instruction(&env, user, LIMIT, 0));
+    assert_vault_error(&failed, VaultErrorCode::InvalidWithdrawalLimit);
+}
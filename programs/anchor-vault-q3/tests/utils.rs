@@ -1,131 +1,364 @@
 //! Test utilities for the anchor-vault-q3 program
 //!
-//! This module contains shared setup functions and constants
-//! used across multiple test files.
+//! This module contains the `VaultTestEnv` harness and the constants and
+//! helpers shared across the test files. Test files declare it as `pub mod`
+//! since each of them only uses part of it.
+
+use std::collections::HashMap;
 
 use anchor_lang::prelude::*;
-use anchor_lang::InstructionData;
-use mollusk_svm::{ result::InstructionResult, Mollusk };
+use anchor_lang::{ InstructionData, ToAccountMetas };
+use anchor_vault_q3::{ ProgramConfig, VaultErrorCode, VaultState };
+use anchor_vault_q3_client::{
+    close_ix,
+    deposit_ix,
+    find_vault_address,
+    find_vault_state_address,
+    initialize_ix,
+    withdraw_ix,
+};
+use mollusk_svm::{ result::{ InstructionResult, ProgramResult }, Mollusk };
+use solana_log_collector::LogCollector;
 use solana_sdk::{ account::Account, instruction::Instruction, pubkey::Pubkey };
 
 /// Initial lamport balance for test users
 pub const USER_INITIAL_LAMPORTS: u64 = 10_000_000;
 
-/// Sets up a fully initialized vault for testing
+/// Amount `VaultTestEnv::with_deposited_vault` deposits
+pub const DEPOSIT_AMOUNT: u64 = 5_000_000;
+
+/// Stateful test environment around Mollusk
 ///
-/// Returns:
-/// - Mollusk instance
-/// - User public key
-/// - Vault state public key
-/// - Vault public key
-/// - Vault state bump
-/// - Vault bump
-/// - Initialize instruction result
-pub fn setup_initialized_vault() -> (
-    Mollusk,
-    Pubkey, // user
-    Pubkey, // vault_state
-    Pubkey, // vault
-    u8, // vault_state_bump
-    u8, // vault_bump
-    InstructionResult, // initialize result
-) {
-    let program_id = anchor_vault_q3::id();
-    let mollusk = Mollusk::new(&program_id, "anchor_vault_q3");
-    let (system_program, system_account) = mollusk_svm::program::keyed_account_for_system_program();
-    let user = Pubkey::new_unique();
+/// Keeps one account store that every instruction runs against, so scenarios
+/// can be written as a sequence of calls instead of threading results by hand.
+/// Like a transaction, an instruction's resulting accounts are only kept if it
+/// succeeds. The store starts out with the system program, the vault program,
+/// an unpaused config without fees and the funded treasury. Accounts not in the
+/// store are passed as empty system accounts.
+pub struct VaultTestEnv {
+    pub mollusk: Mollusk,
+    pub accounts: HashMap<Pubkey, Account>,
+}
 
-    let (vault_state, vault_state_bump) = Pubkey::find_program_address(
-        &[b"state", user.as_ref()],
-        &program_id
-    );
+impl Default for VaultTestEnv {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-    let (vault, vault_bump) = Pubkey::find_program_address(
-        &[b"vault", vault_state.as_ref()],
-        &program_id
-    );
+impl VaultTestEnv {
+    pub fn new() -> Self {
+        let mollusk = Mollusk::new(&anchor_vault_q3::id(), "anchor_vault_q3");
+        let accounts = HashMap::from([
+            mollusk_svm::program::keyed_account_for_system_program(),
+            keyed_account_for_program(),
+            keyed_config_account(),
+            keyed_treasury_account(),
+        ]);
+        Self { mollusk, accounts }
+    }
 
-    // First, run initialize to set up the vault properly
-    let initialize_instruction = Instruction::new_with_bytes(
-        program_id,
-        &(anchor_vault_q3::instruction::Initialize {}).data(),
-        vec![
-            AccountMeta::new(user, true),
-            AccountMeta::new(vault_state, false),
-            AccountMeta::new(vault, false),
-            AccountMeta::new_readonly(system_program, false)
-        ]
-    );
-    let user_account = Account::new(USER_INITIAL_LAMPORTS, 0, &system_program);
-    let vault_account = Account::new(0, 0, &system_program);
-
-    let initialize_accounts = &vec![
-        (user, user_account),
-        (vault_state, Account::new(0, 0, &system_program)),
-        (vault, vault_account),
-        (system_program, system_account.clone())
-    ];
-
-    let initialize_result = mollusk.process_instruction(
-        &initialize_instruction,
-        &initialize_accounts
-    );
-    assert!(!initialize_result.program_result.is_err(), "Initialize should succeed");
+    /// Sets up an environment with one user who has created their default vault
+    pub fn with_initialized_vault() -> (Self, Pubkey) {
+        let mut env = Self::new();
+        let user = env.add_user();
+        let result = env.initialize(&user);
+        assert!(!result.program_result.is_err(), "Initialize should succeed");
+        (env, user)
+    }
+
+    /// Sets up an environment with one user who has deposited `DEPOSIT_AMOUNT`
+    /// into their vault
+    pub fn with_deposited_vault() -> (Self, Pubkey) {
+        let (mut env, user) = Self::with_initialized_vault();
+        let result = env.deposit(&user, DEPOSIT_AMOUNT);
+        assert!(!result.program_result.is_err(), "Deposit should succeed");
+        (env, user)
+    }
+
+    /// Adds a user funded with `USER_INITIAL_LAMPORTS`
+    pub fn add_user(&mut self) -> Pubkey {
+        self.add_user_with_lamports(USER_INITIAL_LAMPORTS)
+    }
+
+    pub fn add_user_with_lamports(&mut self, lamports: u64) -> Pubkey {
+        let user = Pubkey::new_unique();
+        self.set_account(user, Account::new(lamports, 0, &anchor_lang::system_program::ID));
+        user
+    }
+
+    pub fn set_account(&mut self, key: Pubkey, account: Account) {
+        self.accounts.insert(key, account);
+    }
+
+    pub fn account(&self, key: &Pubkey) -> Option<&Account> {
+        self.accounts.get(key)
+    }
+
+    /// Runs an instruction against the store, keeping its resulting accounts
+    /// if it succeeds
+    pub fn process(&mut self, instruction: &Instruction) -> InstructionResult {
+        let accounts: Vec<(Pubkey, Account)> = instruction.accounts
+            .iter()
+            .map(|meta| (meta.pubkey, self.accounts.get(&meta.pubkey).cloned().unwrap_or_default()))
+            .collect();
+        let result = self.mollusk.process_instruction(instruction, &accounts);
+        if !result.program_result.is_err() {
+            for (key, account) in &result.resulting_accounts {
+                self.accounts.insert(*key, account.clone());
+            }
+        }
+        result
+    }
+
+    /// Runs an instruction like `process`, also returning the program logs it produced
+    pub fn process_with_logs(&mut self, instruction: &Instruction) -> (InstructionResult, Vec<String>) {
+        let logger = LogCollector::new_ref();
+        self.mollusk.logger = Some(logger.clone());
+        let result = self.process(instruction);
+        self.mollusk.logger = None;
+        let logs = logger.borrow().get_recorded_content().to_vec();
+        (result, logs)
+    }
 
-    (mollusk, user, vault_state, vault, vault_state_bump, vault_bump, initialize_result)
+    /// Creates `user`'s default vault without a lock
+    pub fn initialize(&mut self, user: &Pubkey) -> InstructionResult {
+        self.initialize_vault(user, 0, 0, 0)
+    }
+
+    pub fn initialize_vault(
+        &mut self,
+        user: &Pubkey,
+        vault_id: u64,
+        unlock_timestamp: i64,
+        unlock_slot: u64
+    ) -> InstructionResult {
+        self.process(&initialize_ix(user, vault_id, unlock_timestamp, unlock_slot))
+    }
+
+    /// Deposits into `user`'s default vault
+    pub fn deposit(&mut self, user: &Pubkey, amount: u64) -> InstructionResult {
+        self.process(&deposit_ix(user, user, 0, amount))
+    }
+
+    /// Withdraws from `user`'s default vault back to `user`
+    pub fn withdraw(&mut self, user: &Pubkey, amount: u64) -> InstructionResult {
+        self.process(&withdraw_ix(user, user, 0, amount, None))
+    }
+
+    /// Closes `user`'s default vault, paying everything back to `user`
+    pub fn close(&mut self, user: &Pubkey) -> InstructionResult {
+        self.process(&close_ix(user, user, 0, None))
+    }
+
+    /// Sets the program's withdrawal fee directly, without going through `set_fees`
+    pub fn set_withdraw_fee(&mut self, withdraw_fee_bps: u16) {
+        let mut config: ProgramConfig = self.decode(&find_config());
+        config.withdraw_fee_bps = withdraw_fee_bps;
+        let mut account = self.accounts[&find_config()].clone();
+        let mut data = Vec::with_capacity(account.data.len());
+        config.try_serialize(&mut data).unwrap();
+        data.resize(account.data.len(), 0);
+        account.data = data;
+        self.set_account(find_config(), account);
+    }
+
+    pub fn now(&self) -> i64 {
+        self.mollusk.sysvars.clock.unix_timestamp
+    }
+
+    pub fn warp_to_timestamp(&mut self, unix_timestamp: i64) {
+        self.mollusk.sysvars.clock.unix_timestamp = unix_timestamp;
+    }
+
+    pub fn warp_to_slot(&mut self, slot: u64) {
+        self.mollusk.sysvars.clock.slot = slot;
+    }
+
+    /// State address of `user`'s default vault
+    pub fn vault_state_address(&self, user: &Pubkey) -> Pubkey {
+        find_vault_state_address(user, 0).0
+    }
+
+    /// Address holding the lamports of `user`'s default vault
+    pub fn vault_address(&self, user: &Pubkey) -> Pubkey {
+        find_vault_address(&self.vault_state_address(user)).0
+    }
+
+    /// Decodes the state of `user`'s default vault
+    pub fn vault_state(&self, user: &Pubkey) -> VaultState {
+        self.decode(&self.vault_state_address(user))
+    }
+
+    /// Decodes a program account, checking its discriminator
+    pub fn decode<T: AccountDeserialize>(&self, key: &Pubkey) -> T {
+        let account = self.account(key).unwrap_or_else(|| panic!("Account {} not found", key));
+        T::try_deserialize(&mut account.data.as_slice()).unwrap()
+    }
+
+    /// Lamports of an account, zero if it isn't in the store
+    pub fn lamports(&self, key: &Pubkey) -> u64 {
+        self.account(key).map_or(0, |account| account.lamports)
+    }
+
+    pub fn assert_lamports(&self, key: &Pubkey, expected: u64) {
+        assert_eq!(self.lamports(key), expected, "Unexpected lamports for {}", key);
+    }
 }
 
-/// Sets up a fully deposited vault for testing
-///
-/// Returns:
-/// - Mollusk instance
-/// - User public key
-/// - Vault state public key
-/// - Vault public key
-/// - Vault state bump
-/// - Vault bump
-/// - Deposit instruction result
-pub fn setup_initialized_and_deposited_vault() -> (
-    Mollusk,
-    Pubkey, // user
-    Pubkey, // vault_state
-    Pubkey, // vault
-    u8, // vault_state_bump
-    u8, // vault_bump
-    InstructionResult, // deposit result
-) {
-    let (mollusk, user, vault_state, vault, vault_state_bump, vault_bump, initialize_result) =
-        setup_initialized_vault();
-
-    // Now run the deposit instruction
-    let deposit_amount = 5_000_000; // 0.005 SOL
-
-    let deposit_instruction = Instruction::new_with_bytes(
-        anchor_vault_q3::id(),
-        &(anchor_vault_q3::instruction::Deposit { amount: deposit_amount }).data(),
-        vec![
-            AccountMeta::new(user, true),
-            AccountMeta::new(vault, false),
-            AccountMeta::new_readonly(vault_state, false),
-            AccountMeta::new_readonly(
-                mollusk_svm::program::keyed_account_for_system_program().0,
-                false
-            )
-        ]
-    );
+/// Runs the measured instruction in an environment set up by a scenario and
+/// returns its compute units
+fn measure_compute_units(
+    (mut env, user): (VaultTestEnv, Pubkey),
+    measured: impl FnOnce(&mut VaultTestEnv, &Pubkey) -> InstructionResult
+) -> u64 {
+    let result = measured(&mut env, &user);
+    assert!(!result.program_result.is_err(), "Measured instruction should succeed");
+    result.compute_units_consumed
+}
 
-    let deposit_accounts = &vec![
-        (user, initialize_result.get_account(&user).unwrap().clone()),
-        (vault_state, initialize_result.get_account(&vault_state).unwrap().clone()),
-        (vault, initialize_result.get_account(&vault).unwrap().clone()),
+/// Compute units of `initialize`, `deposit`, `withdraw` and `close` in the
+/// scenarios tracked by the compute unit bench and its baseline, keyed by
+/// `instruction/scenario`
+pub fn compute_unit_scenarios() -> Vec<(&'static str, u64)> {
+    let fresh = || {
+        let mut env = VaultTestEnv::new();
+        let user = env.add_user();
+        (env, user)
+    };
+    vec![
+        (
+            "initialize/first_vault",
+            measure_compute_units(fresh(), |env, user| env.initialize(user)),
+        ),
+        (
+            "initialize/second_vault",
+            measure_compute_units(VaultTestEnv::with_initialized_vault(), |env, user| {
+                env.initialize_vault(user, 1, 0, 0)
+            }),
+        ),
+        (
+            "initialize/locked",
+            measure_compute_units(fresh(), |env, user| {
+                let unlock_timestamp = env.now() + 1_000;
+                env.initialize_vault(user, 0, unlock_timestamp, 0)
+            }),
+        ),
+        (
+            "deposit/first",
+            measure_compute_units(VaultTestEnv::with_initialized_vault(), |env, user| {
+                env.deposit(user, DEPOSIT_AMOUNT)
+            }),
+        ),
+        (
+            "deposit/repeat",
+            measure_compute_units(VaultTestEnv::with_deposited_vault(), |env, user| {
+                env.deposit(user, DEPOSIT_AMOUNT)
+            }),
+        ),
+        (
+            "withdraw/partial",
+            measure_compute_units(VaultTestEnv::with_deposited_vault(), |env, user| {
+                env.withdraw(user, DEPOSIT_AMOUNT / 2)
+            }),
+        ),
+        (
+            "withdraw/everything",
+            measure_compute_units(VaultTestEnv::with_deposited_vault(), |env, user| {
+                env.withdraw(user, DEPOSIT_AMOUNT)
+            }),
+        ),
         (
-            mollusk_svm::program::keyed_account_for_system_program().0,
-            mollusk_svm::program::keyed_account_for_system_program().1,
+            "close/empty",
+            measure_compute_units(VaultTestEnv::with_initialized_vault(), |env, user| env.close(user)),
+        ),
+        (
+            "close/with_balance",
+            measure_compute_units(VaultTestEnv::with_deposited_vault(), |env, user| env.close(user)),
         )
-    ];
+    ]
+}
+
+/// Builds a vault program instruction from its generated accounts and data
+pub fn vault_instruction(accounts: impl ToAccountMetas, data: impl InstructionData) -> Instruction {
+    Instruction {
+        program_id: anchor_vault_q3::id(),
+        accounts: accounts.to_account_metas(None),
+        data: data.data(),
+    }
+}
 
-    let deposit_result = mollusk.process_instruction(&deposit_instruction, &deposit_accounts);
-    assert!(!deposit_result.program_result.is_err(), "Deposit should succeed");
+/// Derives the event authority PDA that signs `emit_cpi!` self-invocations
+pub fn find_event_authority() -> Pubkey {
+    Pubkey::find_program_address(&[b"__event_authority"], &anchor_vault_q3::id()).0
+}
+
+/// Program account for the vault program, needed by instructions that emit events
+pub fn keyed_account_for_program() -> (Pubkey, Account) {
+    let program_id = anchor_vault_q3::id();
+    (program_id, mollusk_svm::program::create_program_account_loader_v3(&program_id))
+}
 
-    (mollusk, user, vault_state, vault, vault_state_bump, vault_bump, deposit_result)
+/// Decodes an event from the data of an `emit_cpi!` self-invocation
+pub fn decode_event<T: AnchorDeserialize + anchor_lang::Discriminator>(data: &[u8]) -> Option<T> {
+    let data = data.strip_prefix(anchor_lang::event::EVENT_IX_TAG_LE)?;
+    let data = data.strip_prefix(T::DISCRIMINATOR)?;
+    T::try_from_slice(data).ok()
+}
+
+/// Derives the program config address
+pub fn find_config() -> Pubkey {
+    Pubkey::find_program_address(&[b"config"], &anchor_vault_q3::id()).0
+}
+
+/// Builds a program config account directly, since `init_config` needs the
+/// upgradeable loader's program data account
+pub fn config_account(admin: Pubkey, paused: bool) -> Account {
+    let config = ProgramConfig {
+        bump: Pubkey::find_program_address(&[b"config"], &anchor_vault_q3::id()).1,
+        version: ProgramConfig::CURRENT_VERSION,
+        admin,
+        paused,
+        deposit_fee_bps: 0,
+        withdraw_fee_bps: 0,
+        close_fee_lamports: 0,
+    };
+    let space = 8 + ProgramConfig::INIT_SPACE;
+    let mut data = Vec::with_capacity(space);
+    config.try_serialize(&mut data).unwrap();
+    data.resize(space, 0);
+    let mut account = Account::new(Rent::default().minimum_balance(space), space, &anchor_vault_q3::id());
+    account.data = data;
+    account
+}
+
+/// Unpaused program config, keyed by its address
+pub fn keyed_config_account() -> (Pubkey, Account) {
+    (find_config(), config_account(Pubkey::default(), false))
+}
+
+/// Derives the fee treasury address
+pub fn find_treasury() -> Pubkey {
+    Pubkey::find_program_address(&[b"treasury"], &anchor_vault_q3::id()).0
+}
+
+/// Treasury holding just its rent reserve, as `init_config` leaves it
+pub fn keyed_treasury_account() -> (Pubkey, Account) {
+    let system_program = mollusk_svm::program::keyed_account_for_system_program().0;
+    (find_treasury(), Account::new(Rent::default().minimum_balance(0), 0, &system_program))
+}
+
+/// Derives the vault registry address for a user
+pub fn find_registry(user: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"registry", user.as_ref()], &anchor_vault_q3::id()).0
+}
+
+/// Asserts that an instruction failed with the given vault error
+pub fn assert_vault_error(result: &InstructionResult, error: VaultErrorCode) {
+    assert_eq!(
+        result.program_result,
+        ProgramResult::Failure(ProgramError::Custom(error.into())),
+        "Expected vault error {:?}",
+        error
+    );
 }